@@ -0,0 +1,73 @@
+//Instanced mesh shader, places each copy of the mesh from its instance data then runs the standard pbr
+//lighting and fog, see src/instancing.rs
+
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT,
+    pbr_types::{pbr_input_new, STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT},
+    pbr_functions::{apply_pbr_lighting, calculate_view, main_pass_post_lighting_processing},
+    view_transformations::position_world_to_clip,
+}
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+#ifdef VERTEX_COLORS
+    @location(5) color: vec4<f32>,
+#endif
+    //world position and height
+    @location(8) i_position_height: vec4<f32>,
+    //width, heading as cos and sin, and shade
+    @location(9) i_width_heading_shade: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+//turn around y by an angle given as its cos and sin, the same way as Quat::from_rotation_y
+fn turn(v: vec3<f32>, c: f32, s: f32) -> vec3<f32> {
+    return vec3<f32>(c * v.x + s * v.z, v.y, c * v.z - s * v.x);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let scale = vec3<f32>(vertex.i_width_heading_shade.x, vertex.i_position_height.w, vertex.i_width_heading_shade.x);
+    let c = vertex.i_width_heading_shade.y;
+    let s = vertex.i_width_heading_shade.z;
+    let shade = vertex.i_width_heading_shade.w;
+
+    var out: VertexOutput;
+    out.world_position = vec4<f32>(turn(vertex.position * scale, c, s) + vertex.i_position_height.xyz, 1.0);
+    out.position = position_world_to_clip(out.world_position.xyz);
+    //normals of a stretched mesh tilt the other way, divide by the scale instead of multiplying
+    out.world_normal = normalize(turn(vertex.normal / scale, c, s));
+#ifdef VERTEX_COLORS
+    out.color = vec4<f32>(vertex.color.rgb * shade, vertex.color.a);
+#else
+    out.color = vec4<f32>(vec3<f32>(shade), 1.0);
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    var pbr_input = pbr_input_new();
+    pbr_input.material.base_color = in.color;
+    pbr_input.material.perceptual_roughness = 0.95;
+    pbr_input.material.reflectance = 0.1;
+    pbr_input.material.flags |= STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT;
+    pbr_input.frag_coord = in.position;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = normalize(in.world_normal);
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = MESH_FLAGS_SHADOW_RECEIVER_BIT;
+
+    //fog, tonemapping and the rest
+    return main_pass_post_lighting_processing(pbr_input, apply_pbr_lighting(pbr_input));
+}
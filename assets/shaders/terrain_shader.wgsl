@@ -1,17 +1,87 @@
+//Terrain shader, picks the base colour from imagery or from height and slope, lays the map overlay over it
+//then runs the standard pbr lighting
 
-//WIP
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+}
 
-#import bevy_pbr::forward_io::VertexOutput
-// we can import items from shader modules in the assets folder with a quoted path
-#import "shaders/terrain_shader.wgsl"::COLOR_MULTIPLIER
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
 
-@group(2) @binding(0) var<uniform> material_color: vec4<f32>;
-@group(2) @binding(1) var material_color_texture: texture_2d<f32>;
-@group(2) @binding(2) var material_color_sampler: sampler;
+struct BasicTerrainMaterial {
+    sand_color: vec4<f32>,
+    grass_color: vec4<f32>,
+    rock_color: vec4<f32>,
+    snow_color: vec4<f32>,
+    sand_height: f32,
+    snow_height: f32,
+    height_blend: f32,
+    rock_slope: f32,
+    slope_blend: f32,
+    use_imagery: f32,
+    failed: f32,
+    use_overlay: f32,
+}
+
+@group(2) @binding(100) var<uniform> terrain: BasicTerrainMaterial;
+@group(2) @binding(101) var overlay_texture: texture_2d<f32>;
+@group(2) @binding(102) var overlay_sampler: sampler;
+
+fn terrain_color(height: f32, normal: vec3<f32>) -> vec4<f32> {
+    let half_blend = terrain.height_blend * 0.5;
+    let grass = smoothstep(terrain.sand_height - half_blend, terrain.sand_height + half_blend, height);
+    let snow = smoothstep(terrain.snow_height - half_blend, terrain.snow_height + half_blend, height);
+    let slope = 1.0 - normal.y;
+    let rock = smoothstep(terrain.rock_slope - terrain.slope_blend * 0.5, terrain.rock_slope + terrain.slope_blend * 0.5, slope);
+
+    var color = mix(terrain.sand_color, terrain.grass_color, grass);
+    color = mix(color, terrain.snow_color, snow);
+    return mix(color, terrain.rock_color, rock);
+}
+
+//orange and grey squares so chunks that failed to load stand out
+fn failed_color(world_position: vec3<f32>) -> vec4<f32> {
+    let cell = floor(world_position.xz / 250.0);
+    let checker = abs(cell.x + cell.y) % 2.0;
+    return mix(vec4<f32>(0.9, 0.45, 0.1, 1.0), vec4<f32>(0.3, 0.3, 0.3, 1.0), checker);
+}
 
 @fragment
 fn fragment(
-    mesh: VertexOutput,
-) -> @location(0) vec4<f32> {
-    return material_color * textureSample(material_color_texture, material_color_sampler, mesh.uv) * COLOR_MULTIPLIER;
-}
\ No newline at end of file
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    //imagery already is the ground colour, so the bands are only used without it
+    var color = mix(terrain_color(in.world_position.y, normalize(in.world_normal)), vec4<f32>(1.0), terrain.use_imagery);
+    //land use, water and roads go over imagery too
+#ifdef VERTEX_UVS
+    let overlay = textureSample(overlay_texture, overlay_sampler, in.uv);
+    color = vec4<f32>(mix(color.rgb, overlay.rgb, overlay.a * terrain.use_overlay), color.a);
+#endif
+    color = mix(color, failed_color(in.world_position.xyz), terrain.failed);
+    pbr_input.material.base_color = pbr_input.material.base_color * color;
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    //fog, tonemapping and the rest
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
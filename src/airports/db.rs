@@ -0,0 +1,635 @@
+//Airport and runway database
+//Loaded at startup from OurAirports style CSV files, https://ourairports.com/data/
+//airports.csv gives every airport's identifier, name, position and elevation, runways.csv gives the
+//runways with both of their ends, width and surface. Columns are found by their header names, so
+//files with extra or reordered columns work. Airports and runways are bucketed by whole degrees of
+//latitude and longitude for area lookups.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::geo::{GeoPoint, EARTH_CIRCUMFERENCE, MAX_LATITUDE};
+
+const DEFAULT_AIRPORTS_CSV: &str = "./data/airports.csv";
+const DEFAULT_RUNWAYS_CSV: &str = "./data/runways.csv";
+
+const FEET_TO_METRES: f32 = 0.3048;
+//mean earth radius, for distances and bearings between airports
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+//airport types that have nowhere to land a plane
+const SKIPPED_AIRPORT_TYPES: [&str; 3] = ["closed", "heliport", "balloonport"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunwaySurface {
+    Asphalt,
+    Concrete,
+    Grass,
+    Gravel,
+    Water,
+    Unknown,
+}
+
+impl RunwaySurface {
+    //OurAirports surfaces are free text, mostly codes like ASP, CON or TURF
+    fn parse(surface: &str) -> Self {
+        let s = surface.trim().to_uppercase();
+        if s.starts_with("WAT") {
+            RunwaySurface::Water
+        } else if s.starts_with("CON") || s.starts_with("PEM") || s.starts_with("CEM") {
+            RunwaySurface::Concrete
+        } else if s.starts_with("ASP") || s.starts_with("BIT") || s.starts_with("TAR") || s.starts_with("PAV") || s.starts_with("ASF") {
+            RunwaySurface::Asphalt
+        } else if s.starts_with("GRS") || s.starts_with("GRASS") || s.starts_with("TURF") {
+            RunwaySurface::Grass
+        } else if s.starts_with("GRV") || s.starts_with("GRAV") || s.starts_with("DIRT") || s.starts_with("SAND") || s.starts_with("CLAY") {
+            RunwaySurface::Gravel
+        } else {
+            RunwaySurface::Unknown
+        }
+    }
+
+    //paved runways get painted markings
+    pub fn is_paved(&self) -> bool {
+        matches!(self, RunwaySurface::Asphalt | RunwaySurface::Concrete)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RunwayEnd {
+    //designator painted on the runway, like 09 or 27L
+    pub ident: String,
+    //threshold position
+    pub point: GeoPoint,
+    //metres
+    pub elevation: f32,
+    //true heading looking down the runway from this end, degrees
+    pub heading: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Runway {
+    //metres
+    pub length: f32,
+    pub width: f32,
+    pub surface: RunwaySurface,
+    pub ends: [RunwayEnd; 2],
+}
+
+impl Runway {
+    pub fn centre(&self) -> GeoPoint {
+        GeoPoint::new(
+            (self.ends[0].point.lat + self.ends[1].point.lat) * 0.5,
+            (self.ends[0].point.lon + self.ends[1].point.lon) * 0.5,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Airport {
+    pub ident: String,
+    pub iata: String,
+    pub name: String,
+    pub point: GeoPoint,
+    //metres
+    pub elevation: f32,
+    //indices into AirportDatabase::runways
+    pub runways: Vec<usize>,
+}
+
+impl Airport {
+    //the runway a plane would most likely be cleared for
+    pub fn longest_runway<'a>(&self, db: &'a AirportDatabase) -> Option<&'a Runway> {
+        self.runways
+            .iter()
+            .map(|i| &db.runways[*i])
+            .filter(|runway| runway.surface != RunwaySurface::Water)
+            .max_by(|a, b| a.length.total_cmp(&b.length))
+    }
+}
+
+#[derive(Default)]
+pub struct AirportDatabase {
+    pub airports: Vec<Airport>,
+    pub runways: Vec<Runway>,
+    by_ident: HashMap<String, usize>,
+    airport_cells: HashMap<(i32, i32), Vec<usize>>,
+    runway_cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl AirportDatabase {
+    //reads AIRPORTS_CSV and RUNWAYS_CSV, missing files leave the database empty
+    pub fn from_env() -> Self {
+        let airports_path = env::var("AIRPORTS_CSV").unwrap_or(DEFAULT_AIRPORTS_CSV.to_string());
+        let runways_path = env::var("RUNWAYS_CSV").unwrap_or(DEFAULT_RUNWAYS_CSV.to_string());
+        let mut db = AirportDatabase::default();
+
+        match read_csv(Path::new(&airports_path)) {
+            Ok(rows) => db.load_airports(&rows),
+            Err(e) => println!("no airports loaded from {airports_path}: {e}"),
+        }
+        match read_csv(Path::new(&runways_path)) {
+            Ok(rows) => db.load_runways(&rows),
+            Err(e) => println!("no runways loaded from {runways_path}: {e}"),
+        }
+        db.build_index();
+        info!("loaded {} airports and {} runways", db.airports.len(), db.runways.len());
+        db
+    }
+
+    fn load_airports(&mut self, rows: &CsvRows) {
+        for row in rows.iter() {
+            let kind = row.get("type").unwrap_or("");
+            if SKIPPED_AIRPORT_TYPES.contains(&kind) {
+                continue;
+            }
+            let (Some(ident), Some(lat), Some(lon)) = (row.get("ident"), row.number("latitude_deg"), row.number("longitude_deg")) else {
+                continue;
+            };
+            self.add_airport(Airport {
+                ident: ident.to_uppercase(),
+                iata: row.get("iata_code").unwrap_or("").to_uppercase(),
+                name: row.get("name").unwrap_or(ident).to_string(),
+                point: GeoPoint::new(lat, lon),
+                elevation: row.number("elevation_ft").unwrap_or(0.) as f32 * FEET_TO_METRES,
+                runways: Vec::new(),
+            });
+        }
+    }
+
+    fn add_airport(&mut self, airport: Airport) -> usize {
+        let index = self.airports.len();
+        self.by_ident.insert(airport.ident.clone(), index);
+        self.airports.push(airport);
+        index
+    }
+
+    fn load_runways(&mut self, rows: &CsvRows) {
+        for row in rows.iter() {
+            if row.get("closed") == Some("1") {
+                continue;
+            }
+            let Some(ident) = row.get("airport_ident").map(|s| s.to_uppercase()) else {
+                continue;
+            };
+            //runways of airports missing from airports.csv still count, the airport sits on the runway
+            let airport = match self.by_ident.get(&ident) {
+                Some(&airport) => airport,
+                None => {
+                    let (Some(lat), Some(lon)) = (row.number("le_latitude_deg"), row.number("le_longitude_deg")) else {
+                        continue;
+                    };
+                    self.add_airport(Airport {
+                        ident: ident.clone(),
+                        iata: String::new(),
+                        name: ident.clone(),
+                        point: GeoPoint::new(lat, lon),
+                        elevation: row.number("le_elevation_ft").unwrap_or(0.) as f32 * FEET_TO_METRES,
+                        runways: Vec::new(),
+                    })
+                }
+            };
+            if let Some(runway) = parse_runway(&row, &self.airports[airport]) {
+                self.airports[airport].runways.push(self.runways.len());
+                self.runways.push(runway);
+            }
+        }
+    }
+
+    fn build_index(&mut self) {
+        for (i, airport) in self.airports.iter().enumerate() {
+            self.airport_cells.entry(cell(airport.point)).or_default().push(i);
+        }
+        for (i, runway) in self.runways.iter().enumerate() {
+            self.runway_cells.entry(cell(runway.centre())).or_default().push(i);
+        }
+    }
+
+    //by ICAO style identifier or IATA code, case insensitive
+    pub fn find(&self, ident: &str) -> Option<&Airport> {
+        let ident = ident.trim().to_uppercase();
+        self.by_ident
+            .get(&ident)
+            .map(|i| &self.airports[*i])
+            .or_else(|| self.airports.iter().find(|airport| !airport.iata.is_empty() && airport.iata == ident))
+    }
+
+    //runways whose centre lies within a lat/lon box
+    pub fn runways_in(&self, min: GeoPoint, max: GeoPoint) -> impl Iterator<Item = &Runway> {
+        cells_between(min, max)
+            .filter_map(|c| self.runway_cells.get(&c))
+            .flatten()
+            .map(|i| &self.runways[*i])
+            .filter(move |runway| {
+                let c = runway.centre();
+                c.lat >= min.lat && c.lat <= max.lat && c.lon >= min.lon && c.lon <= max.lon
+            })
+    }
+
+    //closest airport with at least one runway, searching outwards up to max_distance metres
+    //cells are a degree wide both ways, away from the equator a degree of longitude is shorter so it
+    //takes more rings east and west than north and south to cover max_distance
+    pub fn nearest(&self, point: GeoPoint, max_distance: f64) -> Option<(&Airport, f64)> {
+        let mut best: Option<(&Airport, f64)> = None;
+        let metres_per_degree = EARTH_CIRCUMFERENCE / 360.;
+        let reach = max_distance / metres_per_degree;
+        //the most poleward latitude in reach has the narrowest cells
+        let poleward = (point.lat.abs() + reach + 1.).min(MAX_LATITUDE).to_radians().cos();
+        let lat_rings = reach.ceil() as i32 + 1;
+        let lon_rings = ((reach / poleward).ceil() as i32 + 1).min(180);
+        let (lat_cell, lon_cell) = cell(point);
+        for ring in 0..=lat_rings.max(lon_rings) {
+            //no airport further out can beat one already found once the ring is past it
+            if let Some((_, distance)) = best {
+                let ring_distance = (ring - 1) as f64 * metres_per_degree * poleward;
+                if ring_distance > distance {
+                    break;
+                }
+            }
+            let (rows, columns) = (ring.min(lat_rings), ring.min(lon_rings));
+            for dy in -rows..=rows {
+                for dx in -columns..=columns {
+                    if dx.abs() != ring && dy.abs() != ring {
+                        continue;
+                    }
+                    //wrap around the antimeridian
+                    let lon = (lon_cell + dx + 180).rem_euclid(360) - 180;
+                    let Some(airports) = self.airport_cells.get(&(lat_cell + dy, lon)) else {
+                        continue;
+                    };
+                    for airport in airports.iter().map(|i| &self.airports[*i]).filter(|a| !a.runways.is_empty()) {
+                        let distance = distance(point, airport.point);
+                        if distance <= max_distance && best.map_or(true, |(_, d)| distance < d) {
+                            best = Some((airport, distance));
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+fn parse_runway(row: &CsvRow, owner: &Airport) -> Option<Runway> {
+    let surface = RunwaySurface::parse(row.get("surface").unwrap_or(""));
+    let length = row.number("length_ft").map(|ft| ft as f32 * FEET_TO_METRES);
+    let width = row.number("width_ft").map(|ft| ft as f32 * FEET_TO_METRES).filter(|w| *w > 0.).unwrap_or(30.);
+    let le_ident = row.get("le_ident").unwrap_or("").to_uppercase();
+    let he_ident = row.get("he_ident").unwrap_or("").to_uppercase();
+    let elevation = |prefix: &str| {
+        row.number(&format!("{prefix}_elevation_ft"))
+            .map(|ft| ft as f32 * FEET_TO_METRES)
+            .unwrap_or(owner.elevation)
+    };
+
+    let le = row.number("le_latitude_deg").zip(row.number("le_longitude_deg")).map(|(lat, lon)| GeoPoint::new(lat, lon));
+    let he = row.number("he_latitude_deg").zip(row.number("he_longitude_deg")).map(|(lat, lon)| GeoPoint::new(lat, lon));
+    let (le, he) = match (le, he) {
+        (Some(le), Some(he)) if distance(le, he) > 1. => (le, he),
+        //without both ends lay the runway out from the airport along its heading
+        _ => {
+            let heading = row
+                .number("le_heading_degT")
+                .map(|h| h as f32)
+                .or_else(|| designator_heading(&le_ident))?;
+            let half = length? as f64 * 0.5;
+            (
+                offset(owner.point, heading as f64 + 180., half),
+                offset(owner.point, heading as f64, half),
+            )
+        }
+    };
+    let heading = bearing(le, he) as f32;
+
+    Some(Runway {
+        length: length.unwrap_or(distance(le, he) as f32),
+        width,
+        surface,
+        ends: [
+            RunwayEnd {
+                ident: le_ident,
+                point: le,
+                elevation: elevation("le"),
+                heading,
+            },
+            RunwayEnd {
+                ident: he_ident,
+                point: he,
+                elevation: elevation("he"),
+                heading: (heading + 180.) % 360.,
+            },
+        ],
+    })
+}
+
+//runway 09 points roughly 090 degrees, close enough when there is no surveyed heading
+fn designator_heading(ident: &str) -> Option<f32> {
+    let digits: String = ident.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let number: f32 = digits.parse().ok()?;
+    (1. ..=36.).contains(&number).then_some(number * 10.)
+}
+
+fn cell(point: GeoPoint) -> (i32, i32) {
+    (point.lat.floor() as i32, point.lon.floor() as i32)
+}
+
+fn cells_between(min: GeoPoint, max: GeoPoint) -> impl Iterator<Item = (i32, i32)> {
+    let (min_lat, min_lon) = cell(min);
+    let (max_lat, max_lon) = cell(max);
+    (min_lat..=max_lat).flat_map(move |lat| (min_lon..=max_lon).map(move |lon| (lat, lon)))
+}
+
+//great circle distance in metres
+pub fn distance(a: GeoPoint, b: GeoPoint) -> f64 {
+    let (lat_a, lat_b) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.lon - a.lon).to_radians();
+    let h = (d_lat * 0.5).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon * 0.5).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+//initial true bearing from a to b, degrees 0..360
+pub fn bearing(a: GeoPoint, b: GeoPoint) -> f64 {
+    let (lat_a, lat_b) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lon = (b.lon - a.lon).to_radians();
+    let y = d_lon.sin() * lat_b.cos();
+    let x = lat_a.cos() * lat_b.sin() - lat_a.sin() * lat_b.cos() * d_lon.cos();
+    (y.atan2(x).to_degrees() + 360.) % 360.
+}
+
+//point a short distance away along a bearing, flat earth is fine over a runway length
+fn offset(point: GeoPoint, bearing: f64, metres: f64) -> GeoPoint {
+    let (sin, cos) = bearing.to_radians().sin_cos();
+    let metres_per_degree = EARTH_CIRCUMFERENCE / 360.;
+    GeoPoint::new(
+        point.lat + cos * metres / metres_per_degree,
+        point.lon + sin * metres / (metres_per_degree * point.lat.to_radians().cos().max(0.01)),
+    )
+}
+
+//the loaded database, shared with chunk fetch tasks to flatten the ground under runways
+#[derive(Resource, Clone)]
+pub struct Airports(pub Arc<AirportDatabase>);
+
+impl Default for Airports {
+    fn default() -> Self {
+        Airports(Arc::new(AirportDatabase::from_env()))
+    }
+}
+
+//a csv file as rows of fields, with the header row mapping column names to positions
+struct CsvRows {
+    columns: HashMap<String, usize>,
+    rows: Vec<Vec<String>>,
+}
+
+struct CsvRow<'a> {
+    columns: &'a HashMap<String, usize>,
+    fields: &'a [String],
+}
+
+impl CsvRows {
+    fn iter(&self) -> impl Iterator<Item = CsvRow<'_>> {
+        self.rows.iter().map(|fields| CsvRow {
+            columns: &self.columns,
+            fields,
+        })
+    }
+}
+
+impl CsvRow<'_> {
+    //a field by column name, None when the column is missing or the field empty
+    fn get(&self, column: &str) -> Option<&str> {
+        let field = self.fields.get(*self.columns.get(column)?)?.trim();
+        (!field.is_empty()).then_some(field)
+    }
+
+    fn number(&self, column: &str) -> Option<f64> {
+        self.get(column)?.parse().ok()
+    }
+}
+
+fn read_csv(path: &Path) -> Result<CsvRows, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().ok_or("the file is empty")?;
+    let columns = split_csv_line(header)
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| (name.trim().to_string(), i))
+        .collect();
+    Ok(CsvRows {
+        columns,
+        rows: lines.map(split_csv_line).collect(),
+    })
+}
+
+//fields may be quoted to hold commas, a doubled quote inside quotes is a literal quote
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    const AIRPORTS: &str = "\
+id,ident,type,name,latitude_deg,longitude_deg,elevation_ft,iata_code
+1,KCVO,small_airport,\"Corvallis Municipal Airport\",44.4972,-123.2894,250,CVO
+2,OR99,heliport,Hospital Heliport,44.56,-123.25,300,
+3,XOLD,closed,\"Old Field, closed\",44.6,-123.3,200,
+4,ENGM,large_airport,Oslo Airport,60.1939,11.1004,681,OSL
+5,NOEAST,small_airport,No Runways,60.5,11,100,
+";
+
+    const RUNWAYS: &str = "\
+airport_ident,length_ft,width_ft,surface,closed,le_ident,le_latitude_deg,le_longitude_deg,le_elevation_ft,le_heading_degT,he_ident,he_latitude_deg,he_longitude_deg,he_elevation_ft
+KCVO,5900,150,ASPH-G,0,09,44.4972,-123.3100,245,,27,44.4972,-123.2700,250
+KCVO,3100,75,TURF,0,17,,,,175,35,,,
+ENGM,11811,147,ASP,0,01L,60.1850,11.0736,,,19R,60.2160,11.0910,
+ENGM,2000,50,GRS,1,05,,,,50,23,,,
+ENGM,1000,0,,0,H1,,,,,H2,,,
+LONE,4000,100,CON,0,18,-45.0,170.0,,,36,-45.01,170.0,
+";
+
+    //a database read from csv files written for the test
+    fn database(name: &str, airports: &str, runways: &str) -> AirportDatabase {
+        let dir = TempDir::new(&format!("airports-{name}"));
+        fs::write(dir.join("airports.csv"), airports).unwrap();
+        fs::write(dir.join("runways.csv"), runways).unwrap();
+        let mut db = AirportDatabase::default();
+        db.load_airports(&read_csv(&dir.join("airports.csv")).unwrap());
+        db.load_runways(&read_csv(&dir.join("runways.csv")).unwrap());
+        db.build_index();
+        db
+    }
+
+    //airports with one short runway each and nothing else, for search tests
+    fn runways_at(name: &str, airports: &[(&str, f64, f64)]) -> AirportDatabase {
+        let mut runways =
+            "airport_ident,le_latitude_deg,le_longitude_deg,he_latitude_deg,he_longitude_deg\n"
+                .to_string();
+        for (ident, lat, lon) in airports {
+            runways += &format!("{ident},{lat},{lon},{lat},{}\n", lon + 0.01);
+        }
+        database(name, "ident,latitude_deg,longitude_deg\n", &runways)
+    }
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(
+            split_csv_line("a,\"b, c\",\"say \"\"hi\"\"\",,e"),
+            ["a", "b, c", "say \"hi\"", "", "e"]
+        );
+        assert_eq!(split_csv_line(""), [""]);
+        assert_eq!(split_csv_line("\"\""), [""]);
+    }
+
+    #[test]
+    fn loads_airports_and_runways() {
+        let db = database("load", AIRPORTS, RUNWAYS);
+        //heliports and closed airports are left out, the lone runway brings its own airport
+        let idents: Vec<&str> = db
+            .airports
+            .iter()
+            .map(|airport| airport.ident.as_str())
+            .collect();
+        assert_eq!(idents, ["KCVO", "ENGM", "NOEAST", "LONE"]);
+        assert_eq!(db.find("cvo").unwrap().ident, "KCVO");
+        assert_eq!(db.find(" engm ").unwrap().name, "Oslo Airport");
+        assert!(db.find("XOLD").is_none());
+
+        let kcvo = db.find("KCVO").unwrap();
+        assert!((kcvo.elevation - 250. * FEET_TO_METRES).abs() < 1e-3);
+        assert_eq!(kcvo.runways.len(), 2);
+        assert_eq!(kcvo.longest_runway(&db).unwrap().ends[0].ident, "09");
+        //the closed grass strip is skipped, and so is the runway with no position or heading
+        assert_eq!(db.find("ENGM").unwrap().runways.len(), 1);
+        assert_eq!(db.find("LONE").unwrap().point, GeoPoint::new(-45.0, 170.0));
+    }
+
+    #[test]
+    fn runways_from_both_ends() {
+        let db = database("ends", AIRPORTS, RUNWAYS);
+        let runway = db.find("KCVO").unwrap().longest_runway(&db).unwrap();
+        assert_eq!(runway.surface, RunwaySurface::Asphalt);
+        assert!((runway.length - 5900. * FEET_TO_METRES).abs() < 1e-3);
+        assert!((runway.width - 150. * FEET_TO_METRES).abs() < 1e-3);
+        assert!((runway.ends[0].heading - 90.).abs() < 0.1);
+        assert!((runway.ends[1].heading - 270.).abs() < 0.1);
+        //an end without an elevation takes the airport's
+        assert!((runway.ends[0].elevation - 245. * FEET_TO_METRES).abs() < 1e-3);
+        assert_eq!(
+            db.find("LONE").unwrap().longest_runway(&db).unwrap().ends[1].elevation,
+            0.
+        );
+    }
+
+    #[test]
+    fn runways_laid_out_from_the_heading() {
+        let db = database("heading", AIRPORTS, RUNWAYS);
+        let kcvo = db.find("KCVO").unwrap();
+        let grass = &db.runways[kcvo.runways[1]];
+        assert_eq!(grass.surface, RunwaySurface::Grass);
+        assert!(!grass.surface.is_paved());
+        assert!((grass.ends[0].heading - 175.).abs() < 0.1);
+        assert!(
+            (distance(grass.ends[0].point, grass.ends[1].point) - 3100. * FEET_TO_METRES as f64)
+                .abs()
+                < 2.
+        );
+        assert!(distance(grass.centre(), kcvo.point) < 1.);
+        //the default width for runways listed without one
+        assert_eq!(
+            db.runways[db.find("ENGM").unwrap().runways[0]].width,
+            147. * FEET_TO_METRES
+        );
+        assert_eq!(designator_heading("27L"), Some(270.));
+        assert_eq!(designator_heading("H1"), None);
+        assert_eq!(designator_heading("37"), None);
+    }
+
+    #[test]
+    fn runways_in_a_box() {
+        let db = database("box", AIRPORTS, RUNWAYS);
+        let found: Vec<&str> = db
+            .runways_in(GeoPoint::new(44., -124.), GeoPoint::new(45., -123.))
+            .map(|runway| runway.ends[0].ident.as_str())
+            .collect();
+        assert_eq!(found, ["09", "17"]);
+        assert_eq!(
+            db.runways_in(GeoPoint::new(50., 0.), GeoPoint::new(51., 1.))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn nearest_airport_with_runways() {
+        let db = database("nearest", AIRPORTS, RUNWAYS);
+        let (airport, distance) = db.nearest(GeoPoint::new(60.5, 11.), 100_000.).unwrap();
+        assert_eq!(airport.ident, "ENGM");
+        assert!((30_000. ..40_000.).contains(&distance));
+        assert!(db.nearest(GeoPoint::new(60.5, 11.), 20_000.).is_none());
+        assert!(db.nearest(GeoPoint::new(0., 0.), 1_000_000.).is_none());
+    }
+
+    //at 60 degrees a degree of longitude is half as long, the search has to reach twice as many cells east
+    #[test]
+    fn nearest_searches_further_east_and_west_near_the_poles() {
+        let db = runways_at("poleward", &[("NORTH", 61.9, 10.5), ("EAST", 60.5, 13.3)]);
+        assert_eq!(
+            db.nearest(GeoPoint::new(60.5, 10.5), 210_000.)
+                .unwrap()
+                .0
+                .ident,
+            "EAST"
+        );
+
+        let db = runways_at("poleward-far", &[("FAR", 60.5, 14.2)]);
+        let (airport, distance) = db.nearest(GeoPoint::new(60.5, 10.5), 210_000.).unwrap();
+        assert_eq!(airport.ident, "FAR");
+        assert!(distance > 200_000.);
+    }
+
+    #[test]
+    fn nearest_wraps_around_the_antimeridian() {
+        let db = runways_at("antimeridian", &[("WEST", -17.5, -179.9)]);
+        let (airport, distance) = db.nearest(GeoPoint::new(-17.5, 179.8), 50_000.).unwrap();
+        assert_eq!(airport.ident, "WEST");
+        assert!(distance < 35_000.);
+    }
+
+    #[test]
+    fn distances_and_bearings() {
+        let (a, b) = (GeoPoint::new(0., 0.), GeoPoint::new(0., 1.));
+        assert!((distance(a, b) - 111_195.).abs() < 1.);
+        assert!((bearing(a, b) - 90.).abs() < 1e-9);
+        assert!((bearing(b, a) - 270.).abs() < 1e-9);
+        assert!((bearing(a, GeoPoint::new(-1., 0.)) - 180.).abs() < 1e-9);
+        let moved = offset(GeoPoint::new(60., 10.), 90., 1000.);
+        assert!((distance(GeoPoint::new(60., 10.), moved) - 1000.).abs() < 2.);
+        assert_eq!(cell(GeoPoint::new(-0.5, 179.9)), (-1, 179));
+    }
+}
@@ -0,0 +1,173 @@
+//Airports
+//The airport and runway database feeds three things: runway meshes on the chunks around them (with the
+//terrain levelled under them while the chunk loads), spawning on the approach to a runway, and the
+//nearest airport readout in the HUD.
+
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+use bevy::utils::HashMap;
+
+use crate::geo::{GeoOrigin, Spawn};
+use crate::scene::{handle_terrain_data_threads, ChunkComponent};
+use crate::terrain::chunk_manager::{ChunkManager, ChunkState, ChunkStateChanged};
+use crate::terrain::settings::TerrainSettings;
+
+pub mod db;
+pub mod runway;
+
+pub use db::{AirportDatabase, Airports};
+use runway::{chunk_runways, RunwayMeshBuilder};
+
+//chunks at this lod or finer get runway meshes, further out runways are too thin to see
+const MAX_RUNWAY_LOD: u32 = 1;
+
+pub struct AirportsPlugin;
+
+impl Plugin for AirportsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Airports>()
+            .init_resource::<ChunkRunways>()
+            .add_systems(Startup, setup_runways)
+            .add_systems(Update, update_chunk_runways.after(handle_terrain_data_threads));
+    }
+}
+
+#[derive(Component)]
+pub struct Runways;
+
+//runway mesh entity of each chunk entity
+#[derive(Resource, Default)]
+pub struct ChunkRunways {
+    meshes: HashMap<Entity, Entity>,
+    material: Handle<StandardMaterial>,
+}
+
+impl ChunkRunways {
+    fn clear(&mut self, commands: &mut Commands, chunk: Entity) {
+        if let Some(runways) = self.meshes.remove(&chunk) {
+            commands.entity(runways).despawn();
+        }
+    }
+}
+
+fn setup_runways(mut runways: ResMut<ChunkRunways>, mut materials: ResMut<Assets<StandardMaterial>>) {
+    //surface and markings come from vertex colours
+    runways.material = materials.add(StandardMaterial {
+        perceptual_roughness: 0.95,
+        reflectance: 0.1,
+        fog_enabled: true,
+        ..default()
+    });
+}
+
+//runways are cheap to lay out, so unlike buildings they are built right away on the main thread
+#[allow(clippy::too_many_arguments)]
+fn update_chunk_runways(
+    mut commands: Commands,
+    mut chunk_events: EventReader<ChunkStateChanged>,
+    mut removed_chunks: RemovedComponents<ChunkComponent>,
+    mut runways: ResMut<ChunkRunways>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut runway_meshes: Query<&mut Handle<Mesh>, With<Runways>>,
+    chunk_manager: Res<ChunkManager>,
+    airports: Res<Airports>,
+    geo_origin: Res<GeoOrigin>,
+    settings: Res<TerrainSettings>,
+) {
+    for chunk in removed_chunks.read() {
+        runways.meshes.remove(&chunk);
+    }
+
+    for event in chunk_events.read() {
+        match event.state {
+            ChunkState::Ready => {}
+            ChunkState::Evicting | ChunkState::Pooled => {
+                runways.clear(&mut commands, event.entity);
+                continue;
+            }
+            _ => continue,
+        }
+
+        //placeholder chunks have no levelled ground to put a runway on
+        let placeholder = chunk_manager.get(event.coord).and_then(|chunk| chunk.failed_at).is_some();
+        if event.lod > MAX_RUNWAY_LOD || placeholder {
+            runways.clear(&mut commands, event.entity);
+            continue;
+        }
+
+        //runways crossing chunk borders belong to the chunk holding their centre
+        let half = geo_origin.chunk_size * 0.5;
+        let mut builder = RunwayMeshBuilder::default();
+        for runway in chunk_runways(&airports.0, &geo_origin, event.coord, settings.exaggeration) {
+            let centre = runway.centre();
+            if centre.x >= -half && centre.x < half && centre.y >= -half && centre.y < half {
+                builder.add(&runway);
+            }
+        }
+        let Some(mesh) = builder.build() else {
+            runways.clear(&mut commands, event.entity);
+            continue;
+        };
+
+        let handle = meshes.add(mesh);
+        match runways.meshes.get(&event.entity).copied() {
+            Some(entity) if runway_meshes.contains(entity) => {
+                if let Ok(mut old) = runway_meshes.get_mut(entity) {
+                    *old = handle;
+                }
+                //bounds are only worked out for entities without them
+                commands.entity(entity).remove::<Aabb>();
+            }
+            _ => {
+                let entity = commands
+                    .spawn((
+                        Runways,
+                        PbrBundle {
+                            mesh: handle,
+                            material: runways.material.clone(),
+                            ..default()
+                        },
+                    ))
+                    .id();
+                commands.entity(event.entity).add_child(entity);
+                runways.meshes.insert(event.entity, entity);
+            }
+        }
+    }
+}
+
+//spawn on the approach to an airport's runway, the one named by runway if given or the longest
+pub fn airport_spawn(db: &AirportDatabase, ident: &str, runway: Option<&str>) -> Option<Spawn> {
+    let airport = db.find(ident)?;
+    let name = format!("{} {}", airport.ident, airport.name);
+    let end = match runway {
+        Some(wanted) => {
+            let wanted = wanted.trim().to_uppercase();
+            let end = airport
+                .runways
+                .iter()
+                .flat_map(|i| db.runways[*i].ends.iter())
+                .find(|end| end.ident == wanted || end.ident.trim_start_matches('0') == wanted.trim_start_matches('0'));
+            if end.is_none() {
+                println!("{} has no runway {wanted}", airport.ident);
+            }
+            end.or_else(|| airport.longest_runway(db).map(|runway| &runway.ends[0]))
+        }
+        None => airport.longest_runway(db).map(|runway| &runway.ends[0]),
+    };
+
+    Some(match end {
+        Some(end) => Spawn {
+            name: format!("{name} runway {}", end.ident),
+            point: end.point,
+            elevation: Some(end.elevation),
+            heading: Some(end.heading),
+        },
+        None => Spawn {
+            name,
+            point: airport.point,
+            elevation: Some(airport.elevation),
+            heading: None,
+        },
+    })
+}
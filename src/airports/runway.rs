@@ -0,0 +1,358 @@
+//Runway geometry
+//Runways are placed in chunk space, relative to the centre of the chunk they touch. Chunk fetch tasks
+//flatten the heightfield under them to a straight slope between the two threshold elevations, and the
+//runway mesh is laid on that same slope, so it sits on the ground without poking through or floating.
+//Paved runways get their markings: threshold bars, designators, aiming points, edge and centre lines.
+//Marking sizes follow ICAO Annex 14.
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+
+use crate::airports::db::{AirportDatabase, RunwaySurface};
+use crate::geo::GeoOrigin;
+use crate::scene::get_world_space_position;
+use crate::terrain::heightfield::Heightfield;
+
+//metres of level ground kept around a runway, more if the chunk's vertices are further apart
+const FLAT_MARGIN: f32 = 40.;
+//metres over which the levelled ground blends back into the terrain
+const BLEND_DISTANCE: f32 = 150.;
+//no runway is longer than this, so a runway centre further from a chunk than this can't reach it
+const MAX_RUNWAY_REACH: f32 = 3500.;
+//the lowest ground a runway is levelled to, so runways below sea level don't turn into water
+const MIN_RUNWAY_HEIGHT: f32 = 1.;
+//metres the runway surface and its markings float above the levelled ground to avoid z fighting
+const SURFACE_OFFSET: f32 = 0.4;
+const MARKING_OFFSET: f32 = 0.1;
+
+const MARKING_COLOR: [f32; 4] = [0.92, 0.92, 0.9, 1.];
+
+//a runway in a chunk's space, positions relative to the chunk's centre and sizes in world units
+#[derive(Clone, Debug)]
+pub struct ChunkRunway {
+    pub start: Vec2,
+    pub end: Vec2,
+    //ground height at each threshold
+    pub start_height: f32,
+    pub end_height: f32,
+    pub width: f32,
+    pub surface: RunwaySurface,
+    pub idents: [String; 2],
+    //metres to world units
+    pub scale: f32,
+}
+
+impl ChunkRunway {
+    fn length(&self) -> f32 {
+        self.start.distance(self.end)
+    }
+
+    fn direction(&self) -> Vec2 {
+        (self.end - self.start).normalize_or_zero()
+    }
+
+    //distance along the runway from the start and across it from the centre line
+    fn along_across(&self, p: Vec2) -> (f32, f32) {
+        let dir = self.direction();
+        let offset = p - self.start;
+        (offset.dot(dir), offset.perp_dot(dir))
+    }
+
+    //height of the levelled runway at a distance along it, level past either end
+    fn height_at(&self, along: f32) -> f32 {
+        let t = (along / self.length().max(f32::EPSILON)).clamp(0., 1.);
+        self.start_height + (self.end_height - self.start_height) * t
+    }
+
+    pub fn centre(&self) -> Vec2 {
+        (self.start + self.end) * 0.5
+    }
+
+    //whether a point is on the runway or within margin metres of it
+    pub fn covers(&self, p: Vec2, margin: f32) -> bool {
+        let (along, across) = self.along_across(p);
+        let margin = margin * self.scale;
+        along >= -margin && along <= self.length() + margin && across.abs() <= self.width * 0.5 + margin
+    }
+}
+
+//runways that could touch the chunk at coord, in that chunk's space. Water runways are left out,
+//there is no ground to level under a seaplane lane
+pub fn chunk_runways(db: &AirportDatabase, geo_origin: &GeoOrigin, coord: IVec2, exaggeration: f32) -> Vec<ChunkRunway> {
+    if db.runways.is_empty() {
+        return Vec::new();
+    }
+    let Some((_, tile_y)) = geo_origin.chunk_to_tile(coord.x, coord.y) else {
+        return Vec::new();
+    };
+    let scale = geo_origin.vertical_scale(tile_y);
+    let centre = get_world_space_position(Vec3::new(coord.x as f32, 0., coord.y as f32), geo_origin.chunk_size);
+    let reach = geo_origin.chunk_size * 0.5 + (MAX_RUNWAY_REACH + BLEND_DISTANCE) * scale;
+    //+z is south, so the north west corner has the highest latitude
+    let north_west = geo_origin.world_to_lat_lon(centre + Vec3::new(-reach, 0., -reach));
+    let south_east = geo_origin.world_to_lat_lon(centre + Vec3::new(reach, 0., reach));
+    let min = crate::geo::GeoPoint::new(south_east.lat, north_west.lon);
+    let max = crate::geo::GeoPoint::new(north_west.lat, south_east.lon);
+
+    let to_local = |point| {
+        let world = geo_origin.lat_lon_to_world(point) - centre;
+        Vec2::new(world.x, world.z)
+    };
+    let to_height = |metres: f32| (metres * scale * exaggeration).max(MIN_RUNWAY_HEIGHT);
+    let half = geo_origin.chunk_size * 0.5;
+
+    db.runways_in(min, max)
+        .filter(|runway| runway.surface != RunwaySurface::Water)
+        .map(|runway| ChunkRunway {
+            start: to_local(runway.ends[0].point),
+            end: to_local(runway.ends[1].point),
+            start_height: to_height(runway.ends[0].elevation),
+            end_height: to_height(runway.ends[1].elevation),
+            width: runway.width * scale,
+            surface: runway.surface,
+            idents: [runway.ends[0].ident.clone(), runway.ends[1].ident.clone()],
+            scale,
+        })
+        .filter(|runway| {
+            //bounding box of the runway and its blend against the chunk's square
+            let pad = runway.width * 0.5 + (FLAT_MARGIN + BLEND_DISTANCE) * scale;
+            let min = runway.start.min(runway.end) - Vec2::splat(pad);
+            let max = runway.start.max(runway.end) + Vec2::splat(pad);
+            min.x <= half && min.y <= half && max.x >= -half && max.y >= -half
+        })
+        .collect()
+}
+
+//level the ground under and around runways. vertex_spacing is the distance between the chunk mesh's
+//vertices, the level area reaches past the smoothing the mesh does over neighbouring vertices
+pub fn flatten_runways(heights: &mut Heightfield, runways: &[ChunkRunway], chunk_size: f32, vertex_spacing: f32) {
+    for runway in runways {
+        let margin = (FLAT_MARGIN * runway.scale).max(vertex_spacing * 2.);
+        let blend = BLEND_DISTANCE * runway.scale;
+        let half_width = runway.width * 0.5 + margin;
+        let length = runway.length();
+
+        for y in 0..heights.height {
+            for x in 0..heights.width {
+                //pixel centres, the same layout the mesh samples with
+                let p = Vec2::new(
+                    ((x as f32 + 0.5) / heights.width as f32 - 0.5) * chunk_size,
+                    ((y as f32 + 0.5) / heights.height as f32 - 0.5) * chunk_size,
+                );
+                let (along, across) = runway.along_across(p);
+                let outside = Vec2::new(
+                    (-margin - along).max(along - length - margin).max(0.),
+                    (across.abs() - half_width).max(0.),
+                )
+                .length();
+                if outside >= blend {
+                    continue;
+                }
+                let t = outside / blend;
+                let weight = 1. - t * t * (3. - 2. * t);
+                let height = &mut heights.data[y * heights.width + x];
+                *height += (runway.height_at(along) - *height) * weight;
+            }
+        }
+    }
+}
+
+//flat quads in a chunk's space, each vertex lifted onto the runway slope
+#[derive(Default)]
+pub struct RunwayMeshBuilder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl RunwayMeshBuilder {
+    pub fn add(&mut self, runway: &ChunkRunway) {
+        let length = runway.length();
+        if length <= 0. {
+            return;
+        }
+        let half_width = runway.width * 0.5;
+        let surface = surface_color(runway.surface);
+        self.quad(runway, [0., length], [-half_width, half_width], SURFACE_OFFSET, surface);
+        if !runway.surface.is_paved() {
+            return;
+        }
+
+        //both ends are marked the same way, read from the approach to that end
+        for end in 0..2 {
+            self.threshold_markings(runway, end);
+        }
+
+        let m = runway.scale;
+        let lift = SURFACE_OFFSET + MARKING_OFFSET;
+        //edge lines
+        let edge = 0.9 * m;
+        for side in [-1., 1.] {
+            let outer = side * (half_width - 0.5 * m);
+            self.quad(runway, [0., length], [outer, outer - side * edge], lift, MARKING_COLOR);
+        }
+        //centre line dashes between the designators
+        let (dash, gap, clear) = (30. * m, 20. * m, 100. * m);
+        let mut along = clear;
+        while along + dash <= length - clear {
+            self.quad(runway, [along, along + dash], [-0.45 * m, 0.45 * m], lift, MARKING_COLOR);
+            along += dash + gap;
+        }
+    }
+
+    //threshold bars, designator and aiming point for one end, 0 is the runway's start
+    fn threshold_markings(&mut self, runway: &ChunkRunway, end: usize) {
+        let m = runway.scale;
+        let length = runway.length();
+        let lift = SURFACE_OFFSET + MARKING_OFFSET;
+        //marking positions are given from this end, looking down the runway
+        let frame = |along: f32, across: f32| if end == 0 { (along, across) } else { (length - along, -across) };
+        let rect = |builder: &mut Self, along: [f32; 2], across: [f32; 2]| {
+            let (a0, c0) = frame(along[0], across[0]);
+            let (a1, c1) = frame(along[1], across[1]);
+            builder.quad(runway, [a0, a1], [c0, c1], lift, MARKING_COLOR);
+        };
+
+        //threshold bars, 1.8 m wide with 1.8 m gaps, more of them on wider runways
+        let half_width = runway.width * 0.5;
+        let pairs = ((runway.width / m / 11.5).floor() as i32).clamp(2, 8);
+        for i in 0..pairs {
+            let inner = 3. * m + i as f32 * 3.6 * m;
+            if inner + 1.8 * m > half_width - 1.5 * m {
+                break;
+            }
+            for side in [-1., 1.] {
+                rect(self, [6. * m, 36. * m], [side * inner, side * (inner + 1.8 * m)]);
+            }
+        }
+
+        //designator, the letter closest to the threshold with the number behind it
+        let text_scale = (runway.width / m / 45.).clamp(0.4, 1.);
+        let (number, letter): (String, String) = runway.idents[end].chars().partition(|c| c.is_ascii_digit());
+        let mut row = 42. * m;
+        for text in [letter, format!("{number:0>2}")] {
+            if text.is_empty() || text == "00" {
+                continue;
+            }
+            let glyph_width = 6. * m * text_scale;
+            let spacing = 3. * m * text_scale;
+            let height = 18. * m * text_scale;
+            let total = text.len() as f32 * (glyph_width + spacing) - spacing;
+            for (i, c) in text.chars().enumerate() {
+                let left = -total * 0.5 + i as f32 * (glyph_width + spacing);
+                for [from, to] in glyph_strokes(c) {
+                    let thickness = 1.5 * m * text_scale;
+                    let point = |p: Vec2| frame(row + p.y * height, left + p.x * glyph_width);
+                    self.stroke(runway, point(from), point(to), thickness, lift, MARKING_COLOR);
+                }
+            }
+            row += height + 6. * m * text_scale;
+        }
+
+        //aiming point blocks on runways long enough to have them
+        if length >= 1200. * m {
+            let (start, block) = (300. * m, 45. * m);
+            let inner = (half_width * 0.3).max(3. * m);
+            let outer = inner + (half_width * 0.3).clamp(4. * m, 10. * m);
+            for side in [-1., 1.] {
+                rect(self, [start, start + block], [side * inner, side * outer]);
+            }
+        }
+    }
+
+    //rectangle spanning a range along and across the runway
+    fn quad(&mut self, runway: &ChunkRunway, along: [f32; 2], across: [f32; 2], lift: f32, color: [f32; 4]) {
+        let corners = [
+            (along[0], across[0]),
+            (along[1], across[0]),
+            (along[1], across[1]),
+            (along[0], across[1]),
+        ];
+        self.polygon(runway, corners, lift, color);
+    }
+
+    //a line from one (along, across) point to another, extended by half its thickness so strokes join
+    fn stroke(&mut self, runway: &ChunkRunway, from: (f32, f32), to: (f32, f32), thickness: f32, lift: f32, color: [f32; 4]) {
+        let (a, b) = (Vec2::new(from.0, from.1), Vec2::new(to.0, to.1));
+        let dir = (b - a).normalize_or_zero() * thickness * 0.5;
+        let side = dir.perp();
+        let (a, b) = (a - dir, b + dir);
+        let corners = [a - side, b - side, b + side, a + side].map(|p| (p.x, p.y));
+        self.polygon(runway, corners, lift, color);
+    }
+
+    fn polygon(&mut self, runway: &ChunkRunway, corners: [(f32, f32); 4], lift: f32, color: [f32; 4]) {
+        let dir = runway.direction();
+        let start = self.positions.len() as u32;
+        let points = corners.map(|(along, across)| {
+            //across is measured to the right of the runway's direction, which perp is with +z south
+            let p = runway.start + dir * along + dir.perp() * across;
+            Vec3::new(p.x, runway.height_at(along) + lift * runway.scale, p.y)
+        });
+        self.positions.extend(points);
+        self.normals.extend([Vec3::Y; 4]);
+        self.colors.extend([color; 4]);
+        //keep the quad facing up whichever way its corners turn
+        let facing_up = (points[1] - points[0]).cross(points[2] - points[0]).y > 0.;
+        let order = if facing_up { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+        self.indices.extend(order.map(|i| start + i));
+    }
+
+    pub fn build(self) -> Option<Mesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_indices(Indices::U32(self.indices));
+        Some(mesh)
+    }
+}
+
+fn surface_color(surface: RunwaySurface) -> [f32; 4] {
+    match surface {
+        RunwaySurface::Asphalt => [0.2, 0.2, 0.21, 1.],
+        RunwaySurface::Concrete => [0.56, 0.56, 0.54, 1.],
+        RunwaySurface::Grass => [0.3, 0.44, 0.22, 1.],
+        RunwaySurface::Gravel => [0.5, 0.44, 0.34, 1.],
+        RunwaySurface::Water | RunwaySurface::Unknown => [0.32, 0.32, 0.32, 1.],
+    }
+}
+
+//strokes of a designator character in a unit box, x to the right and y away from the threshold.
+//Digits are drawn as seven segments
+fn glyph_strokes(c: char) -> Vec<[Vec2; 2]> {
+    let (tl, tr, ml, mr, bl, br) = (
+        Vec2::new(0., 1.),
+        Vec2::new(1., 1.),
+        Vec2::new(0., 0.5),
+        Vec2::new(1., 0.5),
+        Vec2::new(0., 0.),
+        Vec2::new(1., 0.),
+    );
+    //a top, b top right, c bottom right, d bottom, e bottom left, f top left, g middle
+    let segments = [[tl, tr], [tr, mr], [mr, br], [bl, br], [ml, bl], [tl, ml], [ml, mr]];
+    let lit: &[usize] = match c {
+        '0' => &[0, 1, 2, 3, 4, 5],
+        '1' => &[1, 2],
+        '2' => &[0, 1, 6, 4, 3],
+        '3' => &[0, 1, 6, 2, 3],
+        '4' => &[5, 6, 1, 2],
+        '5' => &[0, 5, 6, 2, 3],
+        '6' => &[0, 5, 6, 4, 3, 2],
+        '7' => &[0, 1, 2],
+        '8' => &[0, 1, 2, 3, 4, 5, 6],
+        '9' => &[0, 1, 2, 3, 5, 6],
+        'L' => &[5, 4, 3],
+        'C' => &[0, 5, 4, 3],
+        //a P with a leg
+        'R' => return vec![[tl, tr], [tr, mr], [ml, mr], [tl, bl], [ml, br]],
+        _ => &[],
+    };
+    lit.iter().map(|i| segments[*i]).collect()
+}
@@ -0,0 +1,535 @@
+//Buildings
+//Building footprints come from the vector tiles covering a chunk and are extruded by their height
+//attribute into one mesh per chunk. The mesh is a child of the chunk entity, so it moves and hides with
+//the chunk, and it is built again whenever the chunk's ground changes. Only full resolution chunks get
+//buildings, further out they would be too small to see.
+//Courtyards are roofed over, holes in a footprint only cut its walls.
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::primitives::Aabb;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::tasks::{IoTaskPool, Task};
+use bevy::utils::HashMap;
+use futures_lite::future;
+use std::sync::Arc;
+
+use crate::geo::GeoOrigin;
+use crate::scene::{handle_terrain_data_threads, ChunkComponent};
+use crate::terrain::chunk_manager::{ChunkManager, ChunkState, ChunkStateChanged};
+use crate::terrain::fetch::TerrainFetchError;
+use crate::terrain::mvt::{Feature, Tile};
+use crate::terrain::query::GroundGrid;
+use crate::terrain::vector::{VectorSource, VectorTileSource};
+
+//layer names used by tilezen and by openmaptiles style tiles
+const BUILDING_LAYERS: [&str; 2] = ["buildings", "building"];
+const HEIGHT_KEYS: [&str; 2] = ["height", "render_height"];
+const MIN_HEIGHT_KEYS: [&str; 2] = ["min_height", "render_min_height"];
+const LEVELS_KEYS: [&str; 2] = ["building:levels", "levels"];
+
+//metres, for buildings tagged with neither a height nor a number of levels
+const DEFAULT_BUILDING_HEIGHT: f32 = 8.;
+const LEVEL_HEIGHT: f32 = 3.;
+//walls reach this far below the lowest ground under a building so slopes don't show a gap
+const FOUNDATION_DEPTH: f32 = 2.;
+
+pub struct BuildingsPlugin;
+
+impl Plugin for BuildingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChunkBuildings>()
+            .add_systems(Startup, setup_buildings)
+            .add_systems(
+                Update,
+                (update_chunk_buildings, handle_building_tasks)
+                    .chain()
+                    .after(handle_terrain_data_threads),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct Buildings;
+
+#[derive(Component)]
+pub struct GenBuildings(Task<(Entity, Option<Mesh>)>);
+
+//building mesh entity and running task of each chunk entity
+#[derive(Resource, Default)]
+pub struct ChunkBuildings {
+    meshes: HashMap<Entity, Entity>,
+    tasks: HashMap<Entity, Entity>,
+    material: Handle<StandardMaterial>,
+}
+
+impl ChunkBuildings {
+    //drop the task entity, which drops the task and stops it
+    fn cancel(&mut self, commands: &mut Commands, chunk: Entity) {
+        if let Some(task) = self.tasks.remove(&chunk) {
+            commands.entity(task).despawn();
+        }
+    }
+
+    fn clear(&mut self, commands: &mut Commands, chunk: Entity) {
+        self.cancel(commands, chunk);
+        if let Some(buildings) = self.meshes.remove(&chunk) {
+            commands.entity(buildings).despawn();
+        }
+    }
+}
+
+fn setup_buildings(mut buildings: ResMut<ChunkBuildings>, mut materials: ResMut<Assets<StandardMaterial>>) {
+    //vertex colours vary the shade per building
+    buildings.material = materials.add(StandardMaterial {
+        base_color: Color::rgb(0.82, 0.8, 0.76),
+        perceptual_roughness: 0.9,
+        reflectance: 0.2,
+        fog_enabled: true,
+        ..default()
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_chunk_buildings(
+    mut commands: Commands,
+    mut chunk_events: EventReader<ChunkStateChanged>,
+    mut removed_chunks: RemovedComponents<ChunkComponent>,
+    mut buildings: ResMut<ChunkBuildings>,
+    meshes: Res<Assets<Mesh>>,
+    chunk_manager: Res<ChunkManager>,
+    geo_origin: Res<GeoOrigin>,
+    vector_source: Res<VectorSource>,
+) {
+    for chunk in removed_chunks.read() {
+        buildings.cancel(&mut commands, chunk);
+        buildings.meshes.remove(&chunk);
+    }
+
+    for event in chunk_events.read() {
+        match event.state {
+            ChunkState::Ready => {}
+            ChunkState::Evicting | ChunkState::Pooled => {
+                buildings.clear(&mut commands, event.entity);
+                continue;
+            }
+            _ => continue,
+        }
+
+        //the old buildings stay up until the new ones are done, unless the chunk can't have any
+        buildings.cancel(&mut commands, event.entity);
+        let placeholder = chunk_manager.get(event.coord).and_then(|chunk| chunk.failed_at).is_some();
+        let (Some(source), Some((tile_x, tile_y))) = (&vector_source.source, geo_origin.chunk_to_tile(event.coord.x, event.coord.y)) else {
+            continue;
+        };
+        if event.lod > 0 || placeholder {
+            buildings.clear(&mut commands, event.entity);
+            continue;
+        }
+        let Some(ground) = GroundGrid::from_chunk(&chunk_manager, &meshes, event.coord, geo_origin.chunk_size) else {
+            continue;
+        };
+
+        let area = ChunkArea {
+            zoom: geo_origin.zoom,
+            tile_x,
+            tile_y,
+            vector_zoom: vector_source.zoom_for(geo_origin.zoom),
+            size: geo_origin.chunk_size,
+            vertical_scale: geo_origin.vertical_scale(tile_y),
+        };
+        let source = source.clone();
+        let chunk = event.entity;
+        let task = IoTaskPool::get().spawn(async move { (chunk, build_chunk_buildings(source, &area, &ground)) });
+        let task_entity = commands.spawn(GenBuildings(task)).id();
+        buildings.tasks.insert(chunk, task_entity);
+    }
+}
+
+fn handle_building_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut GenBuildings)>,
+    mut buildings: ResMut<ChunkBuildings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut building_meshes: Query<&mut Handle<Mesh>, With<Buildings>>,
+) {
+    for (task_entity, mut task) in &mut tasks {
+        let Some((chunk, mesh)) = bevy::tasks::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(task_entity).despawn();
+        //a chunk that started another task since then only wants the newest
+        if buildings.tasks.get(&chunk) != Some(&task_entity) {
+            continue;
+        }
+        buildings.tasks.remove(&chunk);
+
+        let Some(mesh) = mesh else {
+            buildings.clear(&mut commands, chunk);
+            continue;
+        };
+        let handle = meshes.add(mesh);
+        match buildings.meshes.get(&chunk).copied() {
+            Some(entity) if building_meshes.contains(entity) => {
+                if let Ok(mut old) = building_meshes.get_mut(entity) {
+                    *old = handle;
+                }
+                //bounds are only worked out for entities without them
+                commands.entity(entity).remove::<Aabb>();
+            }
+            _ => {
+                let entity = commands
+                    .spawn((
+                        Buildings,
+                        PbrBundle {
+                            mesh: handle,
+                            material: buildings.material.clone(),
+                            ..default()
+                        },
+                    ))
+                    .id();
+                commands.entity(chunk).add_child(entity);
+                buildings.meshes.insert(chunk, entity);
+            }
+        }
+    }
+}
+
+//where a chunk sits on the tile grid and how big it is in the world
+struct ChunkArea {
+    zoom: u32,
+    tile_x: u32,
+    tile_y: u32,
+    vector_zoom: u32,
+    size: f32,
+    //metres to world units at the chunk's latitude
+    vertical_scale: f32,
+}
+
+//fetch every vector tile covering the chunk and extrude its buildings, None if there are none
+fn build_chunk_buildings(source: Arc<dyn VectorTileSource>, area: &ChunkArea, ground: &GroundGrid) -> Option<Mesh> {
+    let depth = area.vector_zoom - area.zoom;
+    let tiles_per_side = 1u32 << depth;
+    let mut builder = BuildingMeshBuilder::default();
+
+    for sub_y in 0..tiles_per_side {
+        for sub_x in 0..tiles_per_side {
+            let (x, y) = ((area.tile_x << depth) + sub_x, (area.tile_y << depth) + sub_y);
+            let tile = match source.fetch_tile(area.vector_zoom, x, y) {
+                Ok(tile) => tile,
+                Err(TerrainFetchError::NoData) => continue,
+                Err(e) => {
+                    println!("vector tile {}/{x}/{y}: {e}", area.vector_zoom);
+                    continue;
+                }
+            };
+
+            //tile coordinates to positions relative to the chunk's centre
+            let tile_size = area.size / tiles_per_side as f32;
+            let corner = Vec2::new(sub_x as f32, sub_y as f32) * tile_size - Vec2::splat(area.size * 0.5);
+            add_tile_buildings(&mut builder, &tile, |p, extent| corner + p.as_vec2() / extent as f32 * tile_size, area, ground);
+        }
+    }
+    builder.build()
+}
+
+fn add_tile_buildings(
+    builder: &mut BuildingMeshBuilder,
+    tile: &Tile,
+    to_local: impl Fn(IVec2, u32) -> Vec2,
+    area: &ChunkArea,
+    ground: &GroundGrid,
+) {
+    for layer in tile.layers.iter().filter(|layer| BUILDING_LAYERS.contains(&layer.name.as_str())) {
+        let extent = layer.extent as i32;
+        for feature in &layer.features {
+            let (height, min_height) = building_heights(feature);
+            for polygon in feature.polygons() {
+                //buildings crossing a tile border are in both tiles, the one holding the centre keeps it
+                let (min, max) = polygon[0]
+                    .iter()
+                    .fold((IVec2::MAX, IVec2::MIN), |(min, max), p| (min.min(*p), max.max(*p)));
+                let centre = (min + max) / 2;
+                if centre.x < 0 || centre.y < 0 || centre.x >= extent || centre.y >= extent {
+                    continue;
+                }
+
+                let rings: Vec<Vec<Vec2>> = polygon
+                    .iter()
+                    .map(|ring| ring.iter().map(|p| to_local(*p, layer.extent)).collect())
+                    .collect();
+                let shade = building_shade(feature, centre);
+                builder.add(&rings, height * area.vertical_scale, min_height * area.vertical_scale, shade, ground, area.vertical_scale);
+            }
+        }
+    }
+}
+
+//height and base height in metres
+fn building_heights(feature: &Feature) -> (f32, f32) {
+    let number = |keys: &[&str]| keys.iter().find_map(|key| feature.get(key).and_then(|v| v.as_f64())).map(|v| v as f32);
+    let height = number(&HEIGHT_KEYS)
+        .or_else(|| number(&LEVELS_KEYS).map(|levels| levels * LEVEL_HEIGHT))
+        .filter(|height| *height > 0.)
+        .unwrap_or(DEFAULT_BUILDING_HEIGHT);
+    let min_height = number(&MIN_HEIGHT_KEYS).unwrap_or(0.).clamp(0., height);
+    (height, min_height)
+}
+
+//a slightly different grey for every building, picked from its id or position so it never flickers
+fn building_shade(feature: &Feature, centre: IVec2) -> f32 {
+    let seed = feature.id.unwrap_or((centre.x as u64) << 32 | centre.y as u32 as u64);
+    let mut h = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h ^= h >> 29;
+    0.8 + (h % 1000) as f32 / 1000. * 0.25
+}
+
+#[derive(Default)]
+struct BuildingMeshBuilder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl BuildingMeshBuilder {
+    //rings are an exterior ring followed by holes, as positions relative to the chunk's centre.
+    //Exterior rings run clockwise seen from above, the vector tile winding
+    fn add(&mut self, rings: &[Vec<Vec2>], height: f32, min_height: f32, shade: f32, ground: &GroundGrid, vertical_scale: f32) {
+        let (low, high) = rings[0]
+            .iter()
+            .map(|p| ground.height(*p))
+            .fold((f32::MAX, f32::MIN), |(low, high), h| (low.min(h), high.max(h)));
+        let bottom = if min_height > 0. { high + min_height } else { low - FOUNDATION_DEPTH * vertical_scale };
+        let top = high + height;
+        let color = [shade, shade, shade, 1.];
+
+        for ring in rings {
+            for (i, a) in ring.iter().enumerate() {
+                let b = ring[(i + 1) % ring.len()];
+                let edge = b - *a;
+                if edge.length_squared() == 0. {
+                    continue;
+                }
+                let normal = Vec3::new(edge.y, 0., -edge.x).normalize();
+                let start = self.positions.len() as u32;
+                self.positions.extend([
+                    Vec3::new(a.x, bottom, a.y),
+                    Vec3::new(b.x, bottom, b.y),
+                    Vec3::new(b.x, top, b.y),
+                    Vec3::new(a.x, top, a.y),
+                ]);
+                self.normals.extend([normal; 4]);
+                self.colors.extend([color; 4]);
+                self.indices.extend([0, 2, 1, 0, 3, 2].map(|i| start + i));
+            }
+        }
+
+        let start = self.positions.len() as u32;
+        self.positions.extend(rings[0].iter().map(|p| Vec3::new(p.x, top, p.y)));
+        self.normals.extend(std::iter::repeat(Vec3::Y).take(rings[0].len()));
+        self.colors.extend(std::iter::repeat(color).take(rings[0].len()));
+        //ears come out clockwise seen from above, flip them to face up
+        for [a, b, c] in triangulate(&rings[0]) {
+            self.indices.extend([start + a, start + c, start + b]);
+        }
+    }
+
+    fn build(self) -> Option<Mesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.insert_indices(Indices::U32(self.indices));
+        Some(mesh)
+    }
+}
+
+//ear clipping for a simple polygon in the vector tile winding, triangles keep that winding
+//https://en.wikipedia.org/wiki/Polygon_triangulation#Ear_clipping_method
+fn triangulate(ring: &[Vec2]) -> Vec<[u32; 3]> {
+    let cross = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - b);
+    let mut remaining: Vec<u32> = (0..ring.len() as u32).collect();
+    let mut triangles = Vec::with_capacity(ring.len().saturating_sub(2));
+
+    let mut i = 0;
+    //stops after a full pass without an ear, which only happens for self intersecting rings
+    let mut since_last_ear = 0;
+    while remaining.len() > 3 && since_last_ear < remaining.len() {
+        let n = remaining.len();
+        let (ia, ib, ic) = (remaining[(i + n - 1) % n], remaining[i % n], remaining[(i + 1) % n]);
+        let (a, b, c) = (ring[ia as usize], ring[ib as usize], ring[ic as usize]);
+
+        let convex = cross(a, b, c) > 0.;
+        let is_ear = convex
+            && !remaining.iter().any(|&j| {
+                let p = ring[j as usize];
+                j != ia && j != ib && j != ic && cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.
+            });
+        if is_ear {
+            triangles.push([ia, ib, ic]);
+            remaining.remove(i % n);
+            since_last_ear = 0;
+        } else {
+            i += 1;
+            since_last_ear += 1;
+        }
+        i %= remaining.len();
+    }
+    if remaining.len() == 3 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::vector::LocalVectorSource;
+    use std::path::PathBuf;
+
+    const CHUNK_SIZE: f32 = 1000.;
+    const GROUND: f32 = 10.;
+    const SCALE: f32 = 0.5;
+
+    fn area() -> ChunkArea {
+        ChunkArea {
+            zoom: 14,
+            tile_x: 2582,
+            tile_y: 5917,
+            vector_zoom: 14,
+            size: CHUNK_SIZE,
+            vertical_scale: SCALE,
+        }
+    }
+
+    //twice the area covered by the triangles, positive for the vector tile winding
+    fn triangles_area(ring: &[Vec2], triangles: &[[u32; 3]]) -> f32 {
+        triangles
+            .iter()
+            .map(|[a, b, c]| {
+                let (a, b, c) = (ring[*a as usize], ring[*b as usize], ring[*c as usize]);
+                (b - a).perp_dot(c - b)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn triangulates_convex_and_concave_rings() {
+        let square = [
+            Vec2::new(0., 0.),
+            Vec2::new(10., 0.),
+            Vec2::new(10., 10.),
+            Vec2::new(0., 10.),
+        ];
+        let triangles = triangulate(&square);
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles_area(&square, &triangles), 200.);
+
+        let l_shape = [
+            (0., 0.),
+            (10., 0.),
+            (10., 5.),
+            (5., 5.),
+            (5., 10.),
+            (0., 10.),
+        ]
+        .map(|(x, y)| Vec2::new(x, y));
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 4);
+        assert_eq!(triangles_area(&l_shape, &triangles), 150.);
+        //no triangle may cover the missing corner
+        for [a, b, c] in &triangles {
+            let centre = (l_shape[*a as usize] + l_shape[*b as usize] + l_shape[*c as usize]) / 3.;
+            assert!(centre.x < 5. || centre.y < 5.);
+        }
+    }
+
+    #[test]
+    fn heights_from_tags() {
+        let tile =
+            Tile::decode(include_bytes!("../tests/fixtures/vector/14/2582/5917.mvt")).unwrap();
+        let features = &tile.layers[0].features;
+        assert_eq!(building_heights(&features[0]), (20., 0.));
+        assert_eq!(building_heights(&features[1]), (4. * LEVEL_HEIGHT, 0.));
+        assert_eq!(building_heights(&features[2]), (12., 5.5));
+        assert_eq!(
+            building_heights(&tile.layers[1].features[0]),
+            (DEFAULT_BUILDING_HEIGHT, 0.)
+        );
+    }
+
+    #[test]
+    fn extrudes_fixture_buildings() {
+        let source = LocalVectorSource {
+            root: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vector"),
+        };
+        let tile = source.fetch_tile(14, 2582, 5917).unwrap();
+        let ground = GroundGrid::flat(GROUND, 17, CHUNK_SIZE);
+        let mut builder = BuildingMeshBuilder::default();
+        let to_local = |p: IVec2, extent: u32| {
+            p.as_vec2() / extent as f32 * CHUNK_SIZE - Vec2::splat(CHUNK_SIZE * 0.5)
+        };
+        add_tile_buildings(&mut builder, &tile, to_local, &area(), &ground);
+
+        //the block has 8 walls counting its courtyard and the L has 6, the building over the edge is left
+        //to the next tile
+        assert_eq!(builder.positions.len(), (8 + 6) * 4 + 4 + 6);
+        assert_eq!(builder.indices.len(), (8 + 6) * 6 + (2 + 4) * 3);
+        let bottom = GROUND - FOUNDATION_DEPTH * SCALE;
+        let tops = [GROUND + 20. * SCALE, GROUND + 12. * SCALE];
+        assert!(builder
+            .positions
+            .iter()
+            .all(|p| p.y == bottom || tops.contains(&p.y)));
+        assert!(builder
+            .positions
+            .iter()
+            .all(|p| p.x.abs() <= CHUNK_SIZE * 0.5 && p.z.abs() <= CHUNK_SIZE * 0.5));
+
+        for triangle in builder.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| builder.positions[triangle[i] as usize]);
+            let facing = (b - a).cross(c - a).normalize();
+            let normal = builder.normals[triangle[0] as usize];
+            //every triangle faces the way its normal points, roofs up and walls out
+            assert!(facing.dot(normal) > 0.99, "{facing} against {normal}");
+        }
+        //walls face away from the block's centre, and into its courtyard
+        let block_centre = to_local(IVec2::splat(1500), 4096);
+        for (i, p) in builder.positions[..8 * 4].iter().enumerate().step_by(4) {
+            let outward = (Vec2::new(p.x, p.z) - block_centre).dot(builder.normals[i].xz());
+            assert!(if i < 16 { outward > 0. } else { outward < 0. });
+        }
+        assert!(builder.build().is_some());
+    }
+
+    #[test]
+    fn min_height_lifts_the_walls() {
+        let tile =
+            Tile::decode(include_bytes!("../tests/fixtures/vector/14/2582/5917.mvt")).unwrap();
+        let feature = &tile.layers[0].features[2];
+        let (height, min_height) = building_heights(feature);
+        let rings: Vec<Vec<Vec2>> = feature.polygons()[0]
+            .iter()
+            .map(|ring| ring.iter().map(|p| p.as_vec2() / 10.).collect())
+            .collect();
+        let mut builder = BuildingMeshBuilder::default();
+        builder.add(
+            &rings,
+            height * SCALE,
+            min_height * SCALE,
+            1.,
+            &GroundGrid::flat(GROUND, 17, CHUNK_SIZE),
+            SCALE,
+        );
+        assert!(builder
+            .positions
+            .iter()
+            .all(|p| p.y == GROUND + 5.5 * SCALE || p.y == GROUND + 12. * SCALE));
+        assert!(BuildingMeshBuilder::default().build().is_none());
+    }
+}
@@ -13,7 +13,7 @@ fn spawn_camera(mut commands: Commands) {
     let camera = (
         Camera3dBundle {
             transform: Transform::from_xyz(0.,0.,0.),
-            projection: Projection::Perspective(PerspectiveProjection { fov: (1.22173), aspect_ratio: (16./9.), near: (0.1), far: (10000.) }),
+            projection: Projection::Perspective(PerspectiveProjection { fov: (1.22173), aspect_ratio: (16./9.), near: (0.1), far: (150000.) }),
             ..default()
         },
         ThirdPersonCamera{
@@ -27,7 +27,7 @@ fn spawn_camera(mut commands: Commands) {
             directional_light_color: Color::rgba(1.0, 0.95, 0.85, 0.5) * 2.5,
             directional_light_exponent: 30.0,
             falloff: FogFalloff::from_visibility_colors(
-                200.0, // distance in world units up to which objects retain visibility (>= 5% contrast)
+                20000.0, // distance in world units up to which objects retain visibility (>= 5% contrast)
                 Color::rgb(1.0,1.0,1.0), // atmospheric extinction color (after light is lost due to absorption by atmospheric particles)
                 Color::rgb(0.8, 0.844, 0.86) * 0.5, // atmospheric inscattering color (light gained due to scattering from the sun)
             ),
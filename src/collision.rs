@@ -0,0 +1,190 @@
+//Aircraft - terrain collision
+//A handful of probe points around the plane are checked against the loaded terrain every frame.
+//Touching down slowly and level rests the plane on the ground, anything harder is a crash that
+//freezes the sim until the player restarts. Coming down on water is always a ditching.
+
+use bevy::prelude::*;
+
+use crate::geo::{GeoOrigin, SpawnPoint};
+use crate::player::{start_position, start_velocity, MovementSettings, PendingSpawnHeight, Player};
+use crate::terrain::settings::TerrainSettings;
+use crate::terrain::query::TerrainQuery;
+use crate::ui::PauseState;
+use crate::water::SEA_LEVEL;
+
+//points on the plane that can touch the ground, in the plane's local space (forward is -z)
+const CONTACT_PROBES: [Vec3; 6] = [
+    Vec3::new(0., -3., 0.),    //belly
+    Vec3::new(0., -1., -28.),  //nose
+    Vec3::new(0., 0., 28.),    //tail
+    Vec3::new(-30., 0., 2.),   //left wingtip
+    Vec3::new(30., 0., 2.),    //right wingtip
+    Vec3::new(0., 15., 26.),   //top of the fin
+];
+
+//a touchdown is gentle when all of these hold, otherwise it's a crash
+const GENTLE_SINK_RATE: f32 = 3.; //m/s into the ground
+const GENTLE_MAX_SPEED: f32 = 100.; //m/s
+const GENTLE_MAX_ROLL: f32 = 10.; //degrees
+const GENTLE_MIN_PITCH: f32 = -5.; //degrees
+const GENTLE_MAX_PITCH: f32 = 15.; //degrees
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CrashState>()
+            .add_systems(PreUpdate, terrain_collision.after(crate::player::player_movement))
+            .add_systems(Update, restart_after_crash);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Surface {
+    Ground,
+    Water,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crash {
+    //what the plane hit
+    pub surface: Surface,
+    pub speed: f32,
+    //speed into the ground
+    pub sink_rate: f32,
+    //degrees, nose up is positive
+    pub pitch: f32,
+    //degrees, right wing down is positive
+    pub roll: f32,
+    //which part of the plane hit first
+    pub probe: usize,
+}
+
+impl Crash {
+    pub fn reason(&self) -> &'static str {
+        if self.surface == Surface::Water {
+            "ditched in water"
+        } else if self.sink_rate > GENTLE_SINK_RATE {
+            "descending too fast"
+        } else if self.speed > GENTLE_MAX_SPEED {
+            "touched down too fast"
+        } else if self.roll.abs() > GENTLE_MAX_ROLL {
+            "wings not level"
+        } else if self.pitch < GENTLE_MIN_PITCH {
+            "nose down"
+        } else if self.pitch > GENTLE_MAX_PITCH {
+            "nose too high"
+        } else {
+            match self.probe {
+                1 => "nose hit the ground",
+                2 | 5 => "tail hit the ground",
+                _ => "wingtip hit the ground",
+            }
+        }
+    }
+
+    fn is_gentle(&self) -> bool {
+        //only the belly is allowed to touch, and only on land
+        self.surface == Surface::Ground
+            && self.probe == 0
+            && self.sink_rate <= GENTLE_SINK_RATE
+            && self.speed <= GENTLE_MAX_SPEED
+            && self.roll.abs() <= GENTLE_MAX_ROLL
+            && (GENTLE_MIN_PITCH..=GENTLE_MAX_PITCH).contains(&self.pitch)
+    }
+}
+
+//Some while the plane is wrecked, the sim is frozen until restart
+#[derive(Resource, Default)]
+pub struct CrashState(pub Option<Crash>);
+
+impl CrashState {
+    pub fn is_crashed(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+fn terrain_collision(
+    mut player_q: Query<&mut Transform, With<Player>>,
+    mut settings: ResMut<MovementSettings>,
+    mut crash_state: ResMut<CrashState>,
+    pause: Res<PauseState>,
+    terrain: TerrainQuery,
+) {
+    if pause.is_paused || crash_state.is_crashed() {
+        return;
+    }
+    let Ok(mut transform) = player_q.get_single_mut() else {
+        return;
+    };
+
+    //deepest probe below the ground or water, terrain that isn't loaded can't be hit
+    let mut contact = None;
+    for (i, probe) in CONTACT_PROBES.iter().enumerate() {
+        let point = transform.transform_point(*probe);
+        let Some(ground) = terrain.sample(point.x, point.z) else {
+            continue;
+        };
+        let (surface, height, normal) = if ground.water {
+            (Surface::Water, SEA_LEVEL.max(ground.height), Vec3::Y)
+        } else {
+            (Surface::Ground, ground.height, ground.normal)
+        };
+        let depth = height - point.y;
+        if depth > 0. && contact.map_or(true, |(_, d, _, _)| depth > d) {
+            contact = Some((i, depth, normal, surface));
+        }
+    }
+    let Some((probe, depth, normal, surface)) = contact else {
+        return;
+    };
+
+    let velocity = settings.velocity;
+    let forward = transform.forward();
+    let right = transform.right();
+    let up = transform.up();
+    let crash = Crash {
+        surface,
+        speed: velocity.length(),
+        sink_rate: (-velocity.dot(normal)).max(0.),
+        pitch: forward.y.clamp(-1., 1.).asin().to_degrees(),
+        roll: (-right.y).atan2(up.y).to_degrees(),
+        probe,
+    };
+
+    if crash.is_gentle() {
+        //rest on the ground and slide along it
+        transform.translation.y += depth;
+        let into_ground = velocity.dot(normal).min(0.);
+        settings.velocity = velocity - normal * into_ground;
+    } else {
+        info!("crashed: {} at {:.0} m/s", crash.reason(), crash.speed);
+        settings.velocity = Vec3::ZERO;
+        crash_state.0 = Some(crash);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restart_after_crash(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut crash_state: ResMut<CrashState>,
+    mut player_q: Query<&mut Transform, With<Player>>,
+    mut settings: ResMut<MovementSettings>,
+    mut pending: ResMut<PendingSpawnHeight>,
+    spawn_point: Res<SpawnPoint>,
+    geo_origin: Res<GeoOrigin>,
+    terrain_settings: Res<TerrainSettings>,
+) {
+    if !crash_state.is_crashed() || !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    for mut transform in player_q.iter_mut() {
+        let start = start_position(&spawn_point, &geo_origin, &terrain_settings, &mut pending);
+        *transform = Transform::from_translation(start);
+    }
+    *settings = MovementSettings {
+        velocity: start_velocity(&spawn_point),
+        ..default()
+    };
+    crash_state.0 = None;
+}
@@ -0,0 +1,588 @@
+//Scene export
+//Press P while paused to write the loaded terrain chunks, with their world transforms, to an OBJ or
+//binary glTF (.glb) file for Blender and other tools. The plane's position and the path it flew can
+//be added as extra objects. World units are metres with y up in both formats.
+//https://www.khronos.org/registry/glTF/specs/2.0/glTF-2.0.html
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::tasks::IoTaskPool;
+use serde_json::json;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::geo::GeoOrigin;
+use crate::player::Player;
+use crate::terrain::chunk_manager::{ChunkManager, ChunkState};
+use crate::ui::PauseState;
+
+const DEFAULT_EXPORT_DIR: &str = "./exports";
+
+//distance flown between recorded path points, in metres
+const PATH_SPACING: f32 = 50.;
+//jumps longer than this are a restart or a zoom change, the old path no longer lines up
+const PATH_BREAK_DISTANCE: f32 = 5000.;
+const MAX_PATH_POINTS: usize = 20000;
+
+//size of the exported plane marker and the height of the path ribbon, in metres
+const MARKER_SIZE: f32 = 20.;
+const PATH_RIBBON_HEIGHT: f32 = 4.;
+
+//glb chunk types, "JSON" and "BIN\0" little endian
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+const GL_FLOAT: u32 = 5126;
+const GL_UNSIGNED_INT: u32 = 5125;
+const GL_ARRAY_BUFFER: u32 = 34962;
+const GL_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FlightPath>()
+            .init_resource::<ExportSettings>()
+            .add_systems(Update, (record_flight_path, export_scene));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Obj,
+    Gltf,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Obj => "obj",
+            ExportFormat::Gltf => "glb",
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct ExportSettings {
+    pub dir: PathBuf,
+    pub format: ExportFormat,
+    //add the plane and its path to the export
+    pub include_player: bool,
+}
+
+impl ExportSettings {
+    pub fn from_env() -> Self {
+        let format = match env::var("EXPORT_FORMAT").unwrap_or_default().trim().to_lowercase().as_str() {
+            "gltf" | "glb" => ExportFormat::Gltf,
+            _ => ExportFormat::Obj,
+        };
+        Self {
+            dir: PathBuf::from(env::var("EXPORT_DIR").unwrap_or(DEFAULT_EXPORT_DIR.to_string())),
+            format,
+            include_player: env::var("EXPORT_PLAYER").map(|v| v.trim() != "false" && v.trim() != "0").unwrap_or(true),
+        }
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+//where the plane has been since it last spawned
+#[derive(Resource, Default)]
+pub struct FlightPath(pub Vec<Vec3>);
+
+fn record_flight_path(
+    mut path: ResMut<FlightPath>,
+    player_q: Query<&Transform, With<Player>>,
+    pause: Res<PauseState>,
+) {
+    if pause.is_paused {
+        return;
+    }
+    let Ok(transform) = player_q.get_single() else {
+        return;
+    };
+    let position = transform.translation;
+    match path.0.last() {
+        Some(last) if last.distance(position) > PATH_BREAK_DISTANCE => path.0 = vec![position],
+        Some(last) if last.distance(position) < PATH_SPACING => {}
+        _ if path.0.len() >= MAX_PATH_POINTS => {}
+        _ => path.0.push(position),
+    }
+}
+
+//a mesh copied out of the asset store so it can be written on another thread
+struct ExportMesh {
+    name: String,
+    transform: Transform,
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl ExportMesh {
+    fn from_mesh(name: String, transform: Transform, mesh: &Mesh) -> Option<Self> {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return None;
+        };
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => normals.clone(),
+            _ => vec![[0., 1., 0.]; positions.len()],
+        };
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs.clone(),
+            _ => vec![[0., 0.]; positions.len()],
+        };
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        Some(Self {
+            name,
+            transform,
+            positions: positions.clone(),
+            normals,
+            uvs,
+            indices,
+        })
+    }
+
+    //a small diamond pointing along the plane's heading
+    fn marker(transform: Transform) -> Self {
+        let s = MARKER_SIZE;
+        let points = [
+            Vec3::new(0., 0., -s * 2.),
+            Vec3::new(s, 0., 0.),
+            Vec3::new(0., 0., s),
+            Vec3::new(-s, 0., 0.),
+            Vec3::new(0., s * 0.5, 0.),
+            Vec3::new(0., -s * 0.5, 0.),
+        ];
+        let faces = [[4, 1, 0], [4, 2, 1], [4, 3, 2], [4, 0, 3], [5, 0, 1], [5, 1, 2], [5, 2, 3], [5, 3, 0]];
+        let mut mesh = Self::empty("player".to_string(), Transform::from_translation(transform.translation).with_rotation(transform.rotation));
+        //flat shaded, every face gets its own vertices
+        for face in faces {
+            let [a, b, c] = face.map(|i| points[i]);
+            let normal = (b - a).cross(c - a).normalize();
+            for p in [a, b, c] {
+                mesh.indices.push(mesh.positions.len() as u32);
+                mesh.positions.push(p.to_array());
+                mesh.normals.push(normal.to_array());
+                mesh.uvs.push([0., 0.]);
+            }
+        }
+        mesh
+    }
+
+    //a thin vertical ribbon along the path, lines don't survive the trip through every tool
+    fn ribbon(path: &[Vec3]) -> Option<Self> {
+        if path.len() < 2 {
+            return None;
+        }
+        let mut mesh = Self::empty("flight_path".to_string(), Transform::IDENTITY);
+        for (i, point) in path.iter().enumerate() {
+            let next = path[(i + 1).min(path.len() - 1)];
+            let prev = path[i.saturating_sub(1)];
+            let side = (next - prev).cross(Vec3::Y).normalize_or_zero();
+            let u = i as f32 / (path.len() - 1) as f32;
+            for (y, v) in [(-PATH_RIBBON_HEIGHT * 0.5, 0.), (PATH_RIBBON_HEIGHT * 0.5, 1.)] {
+                mesh.positions.push((*point + Vec3::Y * y).to_array());
+                mesh.normals.push(side.to_array());
+                mesh.uvs.push([u, v]);
+            }
+            if i + 1 < path.len() {
+                let base = (i * 2) as u32;
+                mesh.indices.extend([base, base + 2, base + 1, base + 1, base + 2, base + 3]);
+            }
+        }
+        Some(mesh)
+    }
+
+    fn empty(name: String, transform: Transform) -> Self {
+        Self {
+            name,
+            transform,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+}
+
+//press P while paused to export, the file is written in the background
+#[allow(clippy::too_many_arguments)]
+fn export_scene(
+    keys: Res<ButtonInput<KeyCode>>,
+    pause: Res<PauseState>,
+    settings: Res<ExportSettings>,
+    chunk_manager: Res<ChunkManager>,
+    meshes: Res<Assets<Mesh>>,
+    transforms: Query<&GlobalTransform>,
+    player_q: Query<&Transform, With<Player>>,
+    path: Res<FlightPath>,
+    geo_origin: Res<GeoOrigin>,
+) {
+    if !pause.is_paused || !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+
+    let mut export = Vec::new();
+    for chunk in chunk_manager.chunks().filter(|chunk| chunk.state == ChunkState::Ready) {
+        let (Some(heights), Ok(transform)) = (chunk_manager.heights(chunk.coord), transforms.get(chunk.entity)) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get(&heights.mesh) else {
+            continue;
+        };
+        let name = format!("chunk_{}_{}", chunk.coord.x, chunk.coord.y);
+        export.extend(ExportMesh::from_mesh(name, transform.compute_transform(), mesh));
+    }
+    if export.is_empty() {
+        println!("no terrain loaded to export");
+        return;
+    }
+    if settings.include_player {
+        if let Ok(transform) = player_q.get_single() {
+            export.push(ExportMesh::marker(*transform));
+        }
+        export.extend(ExportMesh::ribbon(&path.0));
+    }
+
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let file = settings.dir.join(format!("terrain-{stamp}.{}", settings.format.extension()));
+    let format = settings.format;
+    let origin = *geo_origin;
+    IoTaskPool::get()
+        .spawn(async move {
+            let bytes = match format {
+                ExportFormat::Obj => write_obj(&export, &origin).into_bytes(),
+                ExportFormat::Gltf => write_glb(&export, &origin),
+            };
+            let result = fs::create_dir_all(file.parent().unwrap_or(&file)).and_then(|_| fs::write(&file, bytes));
+            match result {
+                Ok(()) => println!("exported {} meshes to {}", export.len(), file.display()),
+                Err(e) => println!("failed to export to {}: {e}", file.display()),
+            }
+        })
+        .detach();
+}
+
+fn origin_description(origin: &GeoOrigin) -> String {
+    let centre = origin.world_to_lat_lon(Vec3::ZERO);
+    format!(
+        "world origin lat {:.6} lon {:.6}, tile {}/{}/{}, chunk size {:.1} m",
+        centre.lat, centre.lon, origin.zoom, origin.tile_x, origin.tile_y, origin.chunk_size
+    )
+}
+
+//transforms are baked into the vertices since obj has no node hierarchy, each mesh is its own object
+fn write_obj(meshes: &[ExportMesh], origin: &GeoOrigin) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}", origin_description(origin));
+    let mut offset = 1;
+    for mesh in meshes {
+        let _ = writeln!(out, "o {}", mesh.name);
+        for p in &mesh.positions {
+            let p = mesh.transform.transform_point(Vec3::from(*p));
+            let _ = writeln!(out, "v {} {} {}", p.x, p.y, p.z);
+        }
+        for n in &mesh.normals {
+            let n = mesh.transform.rotation * Vec3::from(*n);
+            let _ = writeln!(out, "vn {} {} {}", n.x, n.y, n.z);
+        }
+        //obj texture coordinates start at the bottom
+        for uv in &mesh.uvs {
+            let _ = writeln!(out, "vt {} {}", uv[0], 1. - uv[1]);
+        }
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0] + offset, face[1] + offset, face[2] + offset];
+            let _ = writeln!(out, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}");
+        }
+        offset += mesh.positions.len() as u32;
+    }
+    out
+}
+
+//one node per mesh carrying its transform, all vertex data in the single binary chunk
+fn write_glb(meshes: &[ExportMesh], origin: &GeoOrigin) -> Vec<u8> {
+    let mut bin: Vec<u8> = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut gltf_meshes = Vec::new();
+    let mut nodes = Vec::new();
+
+    //every attribute is made of 4 byte values so views stay aligned
+    let mut push_view = |bin: &mut Vec<u8>, bytes: Vec<u8>, target: u32| {
+        views.push(json!({ "buffer": 0, "byteOffset": bin.len(), "byteLength": bytes.len(), "target": target }));
+        bin.extend(bytes);
+        views.len() - 1
+    };
+
+    for mesh in meshes {
+        let (min, max) = mesh.positions.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+            ([min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])], [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])])
+        });
+
+        let view = push_view(&mut bin, float_bytes(mesh.positions.iter().flatten()), GL_ARRAY_BUFFER);
+        accessors.push(json!({ "bufferView": view, "componentType": GL_FLOAT, "count": mesh.positions.len(), "type": "VEC3", "min": min, "max": max }));
+        let view = push_view(&mut bin, float_bytes(mesh.normals.iter().flatten()), GL_ARRAY_BUFFER);
+        accessors.push(json!({ "bufferView": view, "componentType": GL_FLOAT, "count": mesh.normals.len(), "type": "VEC3" }));
+        let view = push_view(&mut bin, float_bytes(mesh.uvs.iter().flatten()), GL_ARRAY_BUFFER);
+        accessors.push(json!({ "bufferView": view, "componentType": GL_FLOAT, "count": mesh.uvs.len(), "type": "VEC2" }));
+        let view = push_view(&mut bin, mesh.indices.iter().flat_map(|i| i.to_le_bytes()).collect(), GL_ELEMENT_ARRAY_BUFFER);
+        accessors.push(json!({ "bufferView": view, "componentType": GL_UNSIGNED_INT, "count": mesh.indices.len(), "type": "SCALAR" }));
+
+        let first = accessors.len() - 4;
+        gltf_meshes.push(json!({
+            "name": mesh.name,
+            "primitives": [{
+                "attributes": { "POSITION": first, "NORMAL": first + 1, "TEXCOORD_0": first + 2 },
+                "indices": first + 3,
+            }],
+        }));
+        nodes.push(json!({
+            "name": mesh.name,
+            "mesh": gltf_meshes.len() - 1,
+            "translation": mesh.transform.translation.to_array(),
+            "rotation": mesh.transform.rotation.to_array(),
+            "scale": mesh.transform.scale.to_array(),
+        }));
+    }
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": env!("CARGO_PKG_NAME"), "extras": { "origin": origin_description(origin) } },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": gltf_meshes,
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "byteLength": bin.len() }],
+    });
+
+    //both chunks are padded to 4 bytes, json with spaces and the binary with zeros
+    let mut json = serde_json::to_vec(&document).unwrap_or_default();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    let mut out = Vec::with_capacity(total);
+    for word in [GLB_MAGIC, 2, total as u32, json.len() as u32, GLB_JSON] {
+        out.extend(word.to_le_bytes());
+    }
+    out.extend(json);
+    for word in [bin.len() as u32, GLB_BIN] {
+        out.extend(word.to_le_bytes());
+    }
+    out.extend(bin);
+    out
+}
+
+//little endian bytes of a run of floats
+fn float_bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    values.flat_map(|v| v.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn quad(name: &str, transform: Transform) -> ExportMesh {
+        ExportMesh {
+            name: name.to_string(),
+            transform,
+            positions: vec![[-1., 0., -1.], [1., 0., -1.], [-1., 0., 1.], [1., 0., 1.]],
+            normals: vec![[0., 1., 0.]; 4],
+            uvs: vec![[0., 0.], [1., 0.], [0., 1.], [1., 1.]],
+            indices: vec![0, 2, 1, 1, 2, 3],
+        }
+    }
+
+    fn meshes() -> Vec<ExportMesh> {
+        let moved = Transform::from_xyz(100., 5., -20.)
+            .with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2))
+            .with_scale(Vec3::splat(2.));
+        vec![
+            quad("first", Transform::IDENTITY),
+            quad("second", moved),
+            ExportMesh::marker(moved),
+        ]
+    }
+
+    fn word(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    //the json document and binary chunk of a glb, checking the header and chunk framing on the way
+    fn read_glb(glb: &[u8]) -> (Value, &[u8]) {
+        assert_eq!(word(glb, 0), GLB_MAGIC);
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(word(glb, 4), 2);
+        assert_eq!(word(glb, 8) as usize, glb.len());
+
+        let json_len = word(glb, 12) as usize;
+        assert_eq!(word(glb, 16), GLB_JSON);
+        assert_eq!(json_len % 4, 0);
+        let json = &glb[20..20 + json_len];
+        let bin_start = 20 + json_len;
+        let bin_len = word(glb, bin_start) as usize;
+        assert_eq!(word(glb, bin_start + 4), GLB_BIN);
+        assert_eq!(bin_len % 4, 0);
+        assert_eq!(bin_start + 8 + bin_len, glb.len());
+        (serde_json::from_slice(json).unwrap(), &glb[bin_start + 8..])
+    }
+
+    #[test]
+    fn glb_header_and_chunks() {
+        let glb = write_glb(&meshes(), &GeoOrigin::new(12, 645, 1480));
+        let (document, bin) = read_glb(&glb);
+        assert_eq!(document["asset"]["version"], "2.0");
+        assert_eq!(
+            document["buffers"][0]["byteLength"].as_u64().unwrap() as usize,
+            bin.len()
+        );
+        assert_eq!(document["nodes"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn glb_buffer_views_fill_the_buffer() {
+        let meshes = meshes();
+        let glb = write_glb(&meshes, &GeoOrigin::new(12, 645, 1480));
+        let (document, bin) = read_glb(&glb);
+        let views = document["bufferViews"].as_array().unwrap();
+        assert_eq!(views.len(), meshes.len() * 4);
+
+        //views follow each other without gaps and stay 4 byte aligned
+        let mut end = 0;
+        for view in views {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert_eq!(offset, end);
+            assert_eq!(offset % 4, 0);
+            end = offset + length;
+        }
+        assert_eq!(end, bin.len());
+
+        //every accessor fits its view
+        for accessor in document["accessors"].as_array().unwrap() {
+            let view = &views[accessor["bufferView"].as_u64().unwrap() as usize];
+            let components = match accessor["type"].as_str().unwrap() {
+                "SCALAR" => 1,
+                "VEC2" => 2,
+                _ => 3,
+            };
+            assert_eq!(
+                accessor["count"].as_u64().unwrap() * components * 4,
+                view["byteLength"].as_u64().unwrap()
+            );
+        }
+
+        //the second mesh's positions are written untransformed, its node carries the transform
+        let positions = &views[4];
+        let start = positions["byteOffset"].as_u64().unwrap() as usize;
+        let first: Vec<f32> = (0..3)
+            .map(|i| f32::from_le_bytes(bin[start + i * 4..start + i * 4 + 4].try_into().unwrap()))
+            .collect();
+        assert_eq!(first, meshes[1].positions[0]);
+        assert_eq!(document["nodes"][1]["translation"], json!([100., 5., -20.]));
+        assert_eq!(document["nodes"][1]["scale"], json!([2., 2., 2.]));
+    }
+
+    //obj lines starting with a keyword, split into their values
+    fn obj_lines<'a>(obj: &'a str, keyword: &'a str) -> impl Iterator<Item = Vec<&'a str>> + 'a {
+        obj.lines()
+            .filter(move |line| line.split_whitespace().next() == Some(keyword))
+            .map(|line| line.split_whitespace().skip(1).collect())
+    }
+
+    #[test]
+    fn obj_objects_continue_the_vertex_numbering() {
+        let meshes = meshes();
+        let obj = write_obj(&meshes[..2], &GeoOrigin::new(12, 645, 1480));
+        let names: Vec<_> = obj_lines(&obj, "o").map(|v| v[0]).collect();
+        assert_eq!(names, ["first", "second"]);
+
+        //the second object's faces start after the first object's four vertices
+        let second = &obj[obj.find("o second").unwrap()..];
+        let indices: Vec<u32> = obj_lines(second, "f")
+            .flatten()
+            .map(|vertex| {
+                let parts: Vec<u32> = vertex.split('/').map(|i| i.parse().unwrap()).collect();
+                assert!(parts.iter().all(|i| *i == parts[0]));
+                parts[0]
+            })
+            .collect();
+        assert_eq!(indices, [5, 7, 6, 6, 7, 8]);
+        assert_eq!(obj_lines(&obj, "v").count(), 8);
+
+        //vertices have the transform baked in
+        let positions: Vec<Vec3> = obj_lines(second, "v")
+            .map(|v| {
+                Vec3::new(
+                    v[0].parse().unwrap(),
+                    v[1].parse().unwrap(),
+                    v[2].parse().unwrap(),
+                )
+            })
+            .collect();
+        for (written, original) in positions.iter().zip(&meshes[1].positions) {
+            assert!(
+                written.distance(meshes[1].transform.transform_point(Vec3::from(*original))) < 1e-4
+            );
+        }
+        assert!(positions[0].distance(Vec3::new(98., 5., -18.)) < 1e-4);
+        let normals: Vec<_> = obj_lines(second, "vn").collect();
+        assert!(normals
+            .iter()
+            .all(|n| (n[1].parse::<f32>().unwrap() - 1.).abs() < 1e-6));
+    }
+
+    #[test]
+    fn obj_loads_back_with_bevy_obj() {
+        let meshes = meshes();
+        let obj = write_obj(&meshes, &GeoOrigin::new(12, 645, 1480));
+        let loaded = bevy_obj::load_obj_from_bytes(obj.as_bytes()).unwrap();
+
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            loaded.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("no positions");
+        };
+        let Some(VertexAttributeValues::Float32x2(uvs)) = loaded.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("no uvs");
+        };
+        let Some(Indices::U32(indices)) = loaded.indices() else {
+            panic!("no indices");
+        };
+        let vertex_count: usize = meshes.iter().map(|mesh| mesh.positions.len()).sum();
+        let index_count: usize = meshes.iter().map(|mesh| mesh.indices.len()).sum();
+        assert_eq!(positions.len(), vertex_count);
+        assert_eq!(indices.len(), index_count);
+
+        //every triangle comes back with the same corners, uvs flipped back the right way up
+        let expected = meshes.iter().flat_map(|mesh| {
+            mesh.indices.iter().map(|&i| {
+                (
+                    mesh.transform
+                        .transform_point(Vec3::from(mesh.positions[i as usize])),
+                    Vec2::from(mesh.uvs[i as usize]),
+                )
+            })
+        });
+        for (&i, (position, uv)) in indices.iter().zip(expected) {
+            assert!(Vec3::from(positions[i as usize]).distance(position) < 1e-3);
+            assert!(Vec2::from(uvs[i as usize]).distance(uv) < 1e-6);
+        }
+    }
+}
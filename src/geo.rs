@@ -0,0 +1,396 @@
+//Geographic coordinates for the terrain world
+//Chunks line up 1:1 with Web Mercator (slippy map) tiles at the zoom in TerrainSettings.
+//Chunk (0, 0) is centred on the origin tile, +x points east and +z points south, like tile x/y.
+//World units are metres at the origin's latitude, so a chunk is as wide as the origin tile is on the ground.
+//https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames
+
+use bevy::math::DVec2;
+use bevy::prelude::*;
+use std::env;
+use std::f64::consts::PI;
+
+use crate::airports::{airport_spawn, AirportDatabase, Airports};
+use crate::scene::{get_chunk_space_position, get_world_space_position};
+use crate::terrain::settings::TerrainSettings;
+
+//Web Mercator cuts off at about 85.0511 degrees
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+//WGS84 equator length in metres
+pub const EARTH_CIRCUMFERENCE: f64 = 40_075_016.686;
+
+//a handful of well known airports to spawn at by name
+//ident, name, latitude, longitude, elevation in meters
+pub const NAMED_AIRPORTS: &[(&str, &str, f64, f64, f32)] = &[
+    ("KCVO", "Corvallis Municipal", 44.4972, -123.2894, 76.),
+    ("KEUG", "Eugene Mahlon Sweet Field", 44.1246, -123.2119, 114.),
+    ("KPDX", "Portland International", 45.5887, -122.5975, 9.),
+    ("KSEA", "Seattle Tacoma International", 47.4490, -122.3093, 132.),
+    ("KSFO", "San Francisco International", 37.6190, -122.3748, 4.),
+    ("KLAX", "Los Angeles International", 33.9425, -118.4081, 38.),
+    ("KDEN", "Denver International", 39.8617, -104.6731, 1656.),
+    ("KJFK", "John F Kennedy International", 40.6398, -73.7789, 4.),
+    ("EGLL", "London Heathrow", 51.4706, -0.4619, 25.),
+    ("LSZH", "Zurich", 47.4647, 8.5492, 432.),
+    ("RJTT", "Tokyo Haneda", 35.5523, 139.7800, 6.),
+    ("PHNL", "Honolulu International", 21.3187, -157.9225, 4.),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self {
+            lat: lat.clamp(-MAX_LATITUDE, MAX_LATITUDE),
+            lon,
+        }
+    }
+}
+
+pub fn tiles_at_zoom(zoom: u32) -> f64 {
+    f64::powi(2.0, zoom as i32)
+}
+
+//fractional tile coordinates of a lat/lon at the given zoom
+pub fn lat_lon_to_tile(point: GeoPoint, zoom: u32) -> DVec2 {
+    let n = tiles_at_zoom(zoom);
+    let lat = point.lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (point.lon + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n;
+    DVec2::new(x, y)
+}
+
+//width of a tile on the ground in metres, tiles shrink towards the poles
+pub fn tile_ground_size(lat: f64, zoom: u32) -> f64 {
+    EARTH_CIRCUMFERENCE * lat.to_radians().cos() / tiles_at_zoom(zoom)
+}
+
+//latitude of the middle of a row of tiles
+fn tile_row_latitude(tile_y: f64, zoom: u32) -> f64 {
+    tile_to_lat_lon(DVec2::new(0.0, tile_y + 0.5), zoom).lat
+}
+
+pub fn tile_to_lat_lon(tile: DVec2, zoom: u32) -> GeoPoint {
+    let n = tiles_at_zoom(zoom);
+    let lon = tile.x / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * tile.y / n)).sinh().atan().to_degrees();
+    //wrap longitude back into -180..180 after crossing the antimeridian
+    GeoPoint::new(lat, (lon + 180.0).rem_euclid(360.0) - 180.0)
+}
+
+//ties chunk space to the tile grid
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct GeoOrigin {
+    pub zoom: u32,
+    pub tile_x: i32,
+    pub tile_y: i32,
+    //world size of a chunk, the ground size of the origin tile
+    pub chunk_size: f32,
+}
+
+impl GeoOrigin {
+    pub fn new(zoom: u32, tile_x: i32, tile_y: i32) -> Self {
+        Self {
+            zoom,
+            tile_x,
+            tile_y,
+            chunk_size: tile_ground_size(tile_row_latitude(tile_y as f64, zoom), zoom) as f32,
+        }
+    }
+
+    //origin whose chunk (0, 0) holds the given point
+    pub fn containing(point: GeoPoint, zoom: u32) -> Self {
+        let tile = lat_lon_to_tile(point, zoom);
+        Self::new(zoom, tile.x.floor() as i32, tile.y.floor() as i32)
+    }
+
+    //metres to world units for a row of tiles
+    //Mercator draws every tile as big as the origin tile, heights are stretched by the same amount
+    //so slopes stay true away from the origin's latitude
+    pub fn vertical_scale(&self, tile_y: u32) -> f32 {
+        let ground_size = tile_ground_size(tile_row_latitude(tile_y as f64, self.zoom), self.zoom);
+        (self.chunk_size as f64 / ground_size) as f32
+    }
+
+    //slippy map tile for a chunk, x wraps around the globe, None past the poles
+    pub fn chunk_to_tile(&self, chunk_x: i32, chunk_y: i32) -> Option<(u32, u32)> {
+        let n = tiles_at_zoom(self.zoom) as i32;
+        let y = self.tile_y + chunk_y;
+        if y < 0 || y >= n {
+            return None;
+        }
+        let x = (self.tile_x + chunk_x).rem_euclid(n);
+        Some((x as u32, y as u32))
+    }
+
+    pub fn world_to_tile(&self, position: Vec3) -> DVec2 {
+        let chunk = get_chunk_space_position(position, self.chunk_size);
+        let offset = position - get_world_space_position(chunk, self.chunk_size);
+        //chunks are centred on their position, tiles start at their corner
+        DVec2::new(
+            (self.tile_x as f32 + chunk.x) as f64 + offset.x as f64 / self.chunk_size as f64 + 0.5,
+            (self.tile_y as f32 + chunk.z) as f64 + offset.z as f64 / self.chunk_size as f64 + 0.5,
+        )
+    }
+
+    pub fn tile_to_world(&self, tile: DVec2) -> Vec3 {
+        let local = DVec2::new(tile.x - self.tile_x as f64 - 0.5, tile.y - self.tile_y as f64 - 0.5);
+        let chunk = Vec3::new(local.x.round() as f32, 0.0, local.y.round() as f32);
+        let fraction = local - DVec2::new(chunk.x as f64, chunk.z as f64);
+        get_world_space_position(chunk, self.chunk_size)
+            + Vec3::new(fraction.x as f32 * self.chunk_size, 0.0, fraction.y as f32 * self.chunk_size)
+    }
+
+    pub fn world_to_lat_lon(&self, position: Vec3) -> GeoPoint {
+        tile_to_lat_lon(self.world_to_tile(position), self.zoom)
+    }
+
+    //world position of a lat/lon, at y = 0
+    pub fn lat_lon_to_world(&self, point: GeoPoint) -> Vec3 {
+        let mut tile = lat_lon_to_tile(point, self.zoom);
+        //take the copy of the point nearest the origin when crossing the antimeridian
+        let n = tiles_at_zoom(self.zoom);
+        tile.x -= ((tile.x - self.tile_x as f64) / n).round() * n;
+        self.tile_to_world(tile)
+    }
+}
+
+impl FromWorld for GeoOrigin {
+    fn from_world(world: &mut World) -> Self {
+        let settings = *world.get_resource_or_insert_with(TerrainSettings::from_env);
+        world.init_resource::<SpawnPoint>();
+        match &world.resource::<SpawnPoint>().0 {
+            Some(spawn) => GeoOrigin::containing(spawn.point, settings.zoom),
+            None => {
+                //centre of the map, where the world has always been
+                let max = tiles_at_zoom(settings.zoom) - 1.0;
+                let centre = (max * 0.5) as i32;
+                GeoOrigin::new(settings.zoom, centre, centre)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Spawn {
+    pub name: String,
+    pub point: GeoPoint,
+    //ground elevation in metres, None when it isn't known and the plane is put above the ground once
+    //it loads
+    pub elevation: Option<f32>,
+    //true heading of the runway to line up with, the plane starts on its approach
+    pub heading: Option<f32>,
+}
+
+//where the player starts, from SPAWN_AIRPORT (an ident from the airport database or NAMED_AIRPORTS,
+//optionally with a SPAWN_RUNWAY) or SPAWN_LAT and SPAWN_LON. None keeps the old random spawn
+#[derive(Resource, Clone, Debug)]
+pub struct SpawnPoint(pub Option<Spawn>);
+
+impl SpawnPoint {
+    pub fn from_env(airports: &AirportDatabase) -> Self {
+        if let Ok(ident) = env::var("SPAWN_AIRPORT") {
+            let ident = ident.trim().to_uppercase();
+            let runway = env::var("SPAWN_RUNWAY").ok();
+            match airport_spawn(airports, &ident, runway.as_deref()).or_else(|| find_airport(&ident)) {
+                Some(spawn) => return SpawnPoint(Some(spawn)),
+                None => println!("unknown SPAWN_AIRPORT {ident}"),
+            }
+        }
+
+        let lat = env::var("SPAWN_LAT").ok().and_then(|s| s.trim().parse::<f64>().ok());
+        let lon = env::var("SPAWN_LON").ok().and_then(|s| s.trim().parse::<f64>().ok());
+        match (lat, lon) {
+            (Some(lat), Some(lon)) => SpawnPoint(Some(Spawn {
+                name: format!("{lat:.4}, {lon:.4}"),
+                point: GeoPoint::new(lat, lon),
+                elevation: None,
+                heading: None,
+            })),
+            _ => SpawnPoint(None),
+        }
+    }
+}
+
+impl FromWorld for SpawnPoint {
+    fn from_world(world: &mut World) -> Self {
+        world.init_resource::<Airports>();
+        Self::from_env(&world.resource::<Airports>().0)
+    }
+}
+
+pub fn find_airport(ident: &str) -> Option<Spawn> {
+    NAMED_AIRPORTS
+        .iter()
+        .find(|(id, ..)| id.eq_ignore_ascii_case(ident))
+        .map(|(id, name, lat, lon, elevation)| Spawn {
+            name: format!("{id} {name}"),
+            point: GeoPoint::new(*lat, *lon),
+            elevation: Some(*elevation),
+            heading: None,
+        })
+}
+
+pub struct GeoPlugin;
+
+impl Plugin for GeoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnPoint>()
+            .init_resource::<GeoOrigin>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn known_tiles() {
+        assert_eq!(
+            lat_lon_to_tile(GeoPoint::new(0., 0.), 1),
+            DVec2::new(1., 1.)
+        );
+        assert_eq!(
+            lat_lon_to_tile(GeoPoint::new(0., -180.), 3),
+            DVec2::new(0., 4.)
+        );
+        //Corvallis on the OpenStreetMap tile grid
+        let corvallis = lat_lon_to_tile(GeoPoint::new(44.5646, -123.262), 14);
+        assert_eq!((corvallis.x.floor(), corvallis.y.floor()), (2582., 5921.));
+        //the top and bottom of the map are at the mercator cut off, points past it are clamped
+        assert_near(
+            lat_lon_to_tile(GeoPoint { lat: 89., lon: 0. }, 4).y,
+            0.,
+            1e-9,
+        );
+        let top = tile_to_lat_lon(DVec2::ZERO, 5);
+        assert_near(top.lat, MAX_LATITUDE, 1e-9);
+        assert_near(top.lon, -180., 1e-9);
+        assert_near(tile_to_lat_lon(DVec2::splat(16.), 5).lat, 0., 1e-9);
+    }
+
+    #[test]
+    fn lat_lon_round_trip() {
+        for (lat, lon) in [
+            (0., 0.),
+            (44.5646, -123.262),
+            (-33.9, 151.2),
+            (78.2, 15.6),
+            (-84.9, -179.9),
+            (60., 179.99),
+        ] {
+            for zoom in [0, 8, 12, 18] {
+                let point = tile_to_lat_lon(lat_lon_to_tile(GeoPoint::new(lat, lon), zoom), zoom);
+                assert_near(point.lat, lat, 1e-9);
+                assert_near(point.lon, lon, 1e-9);
+            }
+        }
+        //one tile past the antimeridian comes back on the other side
+        assert_near(tile_to_lat_lon(DVec2::new(4.5, 2.), 2).lon, -135., 1e-9);
+    }
+
+    #[test]
+    fn ground_sizes() {
+        assert_near(tile_ground_size(0., 0), EARTH_CIRCUMFERENCE, 1e-6);
+        assert_near(tile_ground_size(60., 1), EARTH_CIRCUMFERENCE / 4., 1e-6);
+        //a zoom 12 tile in Oregon is a little under 7 km across
+        assert_near(tile_ground_size(44.56, 12), 6971., 1.);
+    }
+
+    #[test]
+    fn chunks_are_as_wide_as_the_origin_tile() {
+        let origin = GeoOrigin::containing(GeoPoint::new(44.5646, -123.262), 12);
+        assert_eq!((origin.tile_x, origin.tile_y), (645, 1480));
+        let row = tile_to_lat_lon(DVec2::new(0., 1480.5), 12).lat;
+        assert_near(origin.chunk_size as f64, tile_ground_size(row, 12), 1e-2);
+
+        //heights on the origin's row are true, tiles further north are drawn as wide as the origin so
+        //their heights are stretched as much
+        assert_near(origin.vertical_scale(1480) as f64, 1., 1e-6);
+        let north = origin.vertical_scale(1400);
+        assert!(north > 1.);
+        assert_near(
+            north as f64,
+            origin.chunk_size as f64
+                / tile_ground_size(tile_to_lat_lon(DVec2::new(0., 1400.5), 12).lat, 12),
+            1e-5,
+        );
+        assert!(origin.vertical_scale(1600) < 1.);
+    }
+
+    #[test]
+    fn chunk_tiles_wrap_east_west_and_stop_at_the_poles() {
+        let origin = GeoOrigin::new(3, 7, 4);
+        assert_eq!(origin.chunk_to_tile(0, 0), Some((7, 4)));
+        assert_eq!(origin.chunk_to_tile(1, 0), Some((0, 4)));
+        assert_eq!(origin.chunk_to_tile(-9, 3), Some((6, 7)));
+        assert_eq!(origin.chunk_to_tile(0, -4), Some((7, 0)));
+        assert_eq!(origin.chunk_to_tile(0, -5), None);
+        assert_eq!(origin.chunk_to_tile(0, 4), None);
+    }
+
+    #[test]
+    fn world_round_trip() {
+        let origin = GeoOrigin::containing(GeoPoint::new(44.5646, -123.262), 12);
+        //the middle of the origin tile is the middle of the world
+        let centre = tile_to_lat_lon(DVec2::new(645.5, 1480.5), 12);
+        assert!(origin.lat_lon_to_world(centre).length() < 1.);
+
+        for (lat, lon) in [
+            (44.5646, -123.262),
+            (44.4972, -123.2894),
+            (45.5887, -122.5975),
+            (43.9, -124.4),
+        ] {
+            let world = origin.lat_lon_to_world(GeoPoint::new(lat, lon));
+            assert_eq!(world.y, 0.);
+            let back = origin.world_to_lat_lon(world);
+            //world positions are f32 metres, a few centimetres out at most this close to the origin
+            assert!(
+                distance_metres(back, GeoPoint::new(lat, lon)) < 0.5,
+                "{lat}, {lon} came back as {back:?}"
+            );
+        }
+        //east is +x and south is +z
+        let east = origin.lat_lon_to_world(GeoPoint::new(44.5646, -123.2));
+        let south = origin.lat_lon_to_world(GeoPoint::new(44.5, -123.262));
+        let here = origin.lat_lon_to_world(GeoPoint::new(44.5646, -123.262));
+        assert!(east.x > here.x && (east.z - here.z).abs() < 1.);
+        assert!(south.z > here.z && (south.x - here.x).abs() < 1.);
+        //a kilometre north is a kilometre of world units on the origin's row
+        let north = origin.lat_lon_to_world(GeoPoint::new(44.5646 + 1000. / 111_132., -123.262));
+        assert_near(
+            (here.z - north.z) as f64,
+            1000. * origin.vertical_scale(1480) as f64,
+            10.,
+        );
+    }
+
+    #[test]
+    fn world_positions_cross_the_antimeridian() {
+        let origin = GeoOrigin::containing(GeoPoint::new(-17.5, 179.95), 12);
+        let west = origin.lat_lon_to_world(GeoPoint::new(-17.5, -179.95));
+        let east = origin.lat_lon_to_world(GeoPoint::new(-17.5, 179.95));
+        //the point just past the antimeridian is a few km east, not the whole way round the world
+        assert!(west.x > east.x && west.x - east.x < 20_000.);
+        assert_near(origin.world_to_lat_lon(west).lon, -179.95, 1e-5);
+    }
+
+    fn distance_metres(a: GeoPoint, b: GeoPoint) -> f64 {
+        let metres_per_degree = EARTH_CIRCUMFERENCE / 360.;
+        let dx = (a.lon - b.lon) * metres_per_degree * a.lat.to_radians().cos();
+        let dy = (a.lat - b.lat) * metres_per_degree;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
@@ -0,0 +1,362 @@
+//Instanced meshes
+//Draws every copy of a mesh held by an entity in a single draw call, used for the plants scattered over
+//the chunks. Each copy has its own position, size, heading and shade in a per instance vertex buffer that
+//shaders/instancing.wgsl reads, the fragment shader then runs the standard pbr lighting and fog so copies
+//are lit like the rest of the scene. Copies don't cast shadows.
+//Frustum culling uses the entity's Aabb, which has to cover all of its copies.
+//Based on https://github.com/bevyengine/bevy/blob/v0.13.2/examples/shader/shader_instancing.rs
+
+use bevy::core_pipeline::core_3d::Opaque3d;
+use bevy::core_pipeline::prepass::{DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass};
+use bevy::core_pipeline::tonemapping::{DebandDither, Tonemapping};
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::SystemParamItem;
+use bevy::pbr::{
+    tonemapping_pipeline_key, MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup,
+    ShadowFilteringMethod,
+};
+use bevy::prelude::*;
+use bevy::render::mesh::{GpuBufferInfo, MeshVertexBufferLayout};
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_phase::{
+    AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+    TrackedRenderPass,
+};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::view::{ExtractedView, VisibleEntities};
+use bevy::render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+use bevy::ecs::entity::EntityHashMap;
+use std::sync::Arc;
+
+//shader locations 0 to 7 are the mesh attributes
+const INSTANCE_LOCATION: u32 = 8;
+//floats per instance, see MeshInstance::floats
+const INSTANCE_FLOATS: usize = 8;
+
+pub struct InstancingPlugin;
+
+impl Plugin for InstancingPlugin {
+    fn build(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .add_render_command::<Opaque3d, DrawInstanced>()
+            .init_resource::<SpecializedMeshPipelines<InstancingPipeline>>()
+            .init_resource::<InstanceBuffers>()
+            .add_systems(ExtractSchedule, extract_instances)
+            .add_systems(
+                Render,
+                (
+                    queue_instanced_meshes.in_set(RenderSet::QueueMeshes),
+                    prepare_instance_buffers.in_set(RenderSet::PrepareResources),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.init_resource::<InstancingPipeline>();
+        }
+    }
+}
+
+//one copy of the mesh, relative to the entity
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshInstance {
+    pub position: Vec3,
+    //scale across and up
+    pub width: f32,
+    pub height: f32,
+    //turn around y, in radians
+    pub heading: f32,
+    //multiplies the mesh's vertex colours
+    pub shade: f32,
+}
+
+impl MeshInstance {
+    //layout of the instance buffer: world position and height, then width, heading as cos and sin, and shade
+    fn floats(&self, transform: &GlobalTransform) -> [f32; INSTANCE_FLOATS] {
+        let position = transform.transform_point(self.position);
+        let scale = transform.compute_transform().scale;
+        [
+            position.x,
+            position.y,
+            position.z,
+            self.height * scale.y,
+            self.width * scale.x,
+            self.heading.cos(),
+            self.heading.sin(),
+            self.shade,
+        ]
+    }
+}
+
+//copies of the entity's mesh to draw instead of the mesh itself. Shared so extracting it every frame is
+//cheap, the gpu buffer is only rebuilt when the copies or the entity's transform change. Entities are only
+//moved and scaled, a rotation would be left out
+#[derive(Component, Clone)]
+pub struct MeshInstances(pub Arc<Vec<MeshInstance>>);
+
+#[derive(Component)]
+struct ExtractedInstances {
+    instances: Arc<Vec<MeshInstance>>,
+    transform: GlobalTransform,
+}
+
+fn extract_instances(
+    mut commands: Commands,
+    query: Extract<Query<(Entity, &MeshInstances, &GlobalTransform, &ViewVisibility)>>,
+) {
+    let extracted: Vec<_> = query
+        .iter()
+        .filter(|(_, instances, _, visibility)| visibility.get() && !instances.0.is_empty())
+        .map(|(entity, instances, transform, _)| {
+            (entity, ExtractedInstances { instances: instances.0.clone(), transform: *transform })
+        })
+        .collect();
+    commands.insert_or_spawn_batch(extracted);
+}
+
+struct InstanceBuffer {
+    instances: Arc<Vec<MeshInstance>>,
+    transform: GlobalTransform,
+    buffer: Buffer,
+    length: u32,
+}
+
+//instance buffers of the entities drawn this frame, kept while they don't change
+#[derive(Resource, Default)]
+struct InstanceBuffers(EntityHashMap<InstanceBuffer>);
+
+fn prepare_instance_buffers(
+    query: Query<(Entity, &ExtractedInstances)>,
+    mut buffers: ResMut<InstanceBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    buffers.0.retain(|entity, _| query.contains(*entity));
+    for (entity, extracted) in &query {
+        if let Some(old) = buffers.0.get(&entity) {
+            if Arc::ptr_eq(&old.instances, &extracted.instances) && old.transform == extracted.transform {
+                continue;
+            }
+        }
+        let contents: Vec<u8> = extracted
+            .instances
+            .iter()
+            .flat_map(|instance| instance.floats(&extracted.transform))
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("mesh instance buffer"),
+            contents: &contents,
+            usage: BufferUsages::VERTEX,
+        });
+        buffers.0.insert(
+            entity,
+            InstanceBuffer {
+                instances: extracted.instances.clone(),
+                transform: extracted.transform,
+                buffer,
+                length: extracted.instances.len() as u32,
+            },
+        );
+    }
+}
+
+//a view's visible entities and phase, and the settings that go into its pipeline key
+type ViewQuery = (
+    &'static ExtractedView,
+    &'static VisibleEntities,
+    Option<&'static Tonemapping>,
+    Option<&'static DebandDither>,
+    Option<&'static ShadowFilteringMethod>,
+    Option<&'static Projection>,
+    (Has<NormalPrepass>, Has<DepthPrepass>, Has<MotionVectorPrepass>, Has<DeferredPrepass>),
+    &'static mut RenderPhase<Opaque3d>,
+);
+
+//the same view key bits as the standard materials so the view bindings and lighting match
+#[allow(clippy::too_many_arguments)]
+fn queue_instanced_meshes(
+    draw_functions: Res<DrawFunctions<Opaque3d>>,
+    instancing_pipeline: Res<InstancingPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<InstancingPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    instanced: Query<(), With<ExtractedInstances>>,
+    mut views: Query<ViewQuery>,
+) {
+    let draw_instanced = draw_functions.read().id::<DrawInstanced>();
+    for (view, visible_entities, tonemapping, dither, shadow_filter_method, projection, prepasses, mut opaque_phase) in &mut views {
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples()) | MeshPipelineKey::from_hdr(view.hdr);
+        let (normal_prepass, depth_prepass, motion_vector_prepass, deferred_prepass) = prepasses;
+        for (enabled, key) in [
+            (normal_prepass, MeshPipelineKey::NORMAL_PREPASS),
+            (depth_prepass, MeshPipelineKey::DEPTH_PREPASS),
+            (motion_vector_prepass, MeshPipelineKey::MOTION_VECTOR_PREPASS),
+            (deferred_prepass, MeshPipelineKey::DEFERRED_PREPASS),
+        ] {
+            if enabled {
+                view_key |= key;
+            }
+        }
+        view_key |= match projection {
+            Some(Projection::Orthographic(_)) => MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC,
+            _ => MeshPipelineKey::VIEW_PROJECTION_PERSPECTIVE,
+        };
+        view_key |= match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+            ShadowFilteringMethod::Hardware2x2 => MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2,
+            ShadowFilteringMethod::Castano13 => MeshPipelineKey::SHADOW_FILTER_METHOD_CASTANO_13,
+            ShadowFilteringMethod::Jimenez14 => MeshPipelineKey::SHADOW_FILTER_METHOD_JIMENEZ_14,
+        };
+        if !view.hdr {
+            if let Some(tonemapping) = tonemapping {
+                view_key |= MeshPipelineKey::TONEMAP_IN_SHADER | tonemapping_pipeline_key(*tonemapping);
+            }
+            if let Some(DebandDither::Enabled) = dither {
+                view_key |= MeshPipelineKey::DEBAND_DITHER;
+            }
+        }
+
+        for &entity in visible_entities.entities.iter().filter(|entity| instanced.contains(**entity)) {
+            let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+            let pipeline = match pipelines.specialize(&pipeline_cache, &instancing_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(e) => {
+                    error!("{e}");
+                    continue;
+                }
+            };
+            opaque_phase.add(Opaque3d {
+                asset_id: mesh_instance.mesh_asset_id,
+                pipeline,
+                entity,
+                draw_function: draw_instanced,
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+struct InstancingPipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for InstancingPipeline {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            shader: world.resource::<AssetServer>().load("shaders/instancing.wgsl"),
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for InstancingPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(&self, key: Self::Key, layout: &MeshVertexBufferLayout) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push(VertexBufferLayout {
+            array_stride: (INSTANCE_FLOATS * 4) as u64,
+            step_mode: VertexStepMode::Instance,
+            attributes: vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: INSTANCE_LOCATION,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: VertexFormat::Float32x4.size(),
+                    shader_location: INSTANCE_LOCATION + 1,
+                },
+            ],
+        });
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = self.shader.clone();
+        }
+        descriptor.label = Some("instanced_mesh_pipeline".into());
+        Ok(descriptor)
+    }
+}
+
+type DrawInstanced = (SetItemPipeline, SetMeshViewBindGroup<0>, SetMeshBindGroup<1>, DrawMeshInstanced);
+
+struct DrawMeshInstanced;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<InstanceBuffers>);
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        _entity: Option<()>,
+        (meshes, render_mesh_instances, buffers): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(instances) = buffers.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instances.buffer.slice(..));
+        match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instances.length);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..gpu_mesh.vertex_count, 0..instances.length);
+            }
+        }
+        RenderCommandResult::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_moved_and_scaled_with_their_entity() {
+        let instance = MeshInstance {
+            position: Vec3::new(3., -1., 4.),
+            width: 2.,
+            height: 10.,
+            heading: std::f32::consts::FRAC_PI_2,
+            shade: 0.9,
+        };
+        let floats = instance.floats(&GlobalTransform::from_translation(Vec3::new(
+            1000., 50., -200.,
+        )));
+        assert_eq!(&floats[..5], &[1003., 49., -196., 10., 2.]);
+        assert!(floats[5].abs() < 1e-6 && (floats[6] - 1.).abs() < 1e-6);
+        assert_eq!(floats[7], 0.9);
+
+        let scaled =
+            GlobalTransform::from(Transform::from_xyz(10., 0., 0.).with_scale(Vec3::splat(2.)));
+        assert_eq!(&instance.floats(&scaled)[..5], &[16., -2., 8., 20., 4.]);
+    }
+}
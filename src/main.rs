@@ -3,10 +3,23 @@ use bevy_obj::ObjPlugin;
 use dotenv::dotenv;
 use bevy::DefaultPlugins;
 use bevy_third_person_camera::*;
+mod airports;
+mod buildings;
 mod camera;
+mod collision;
+mod export;
+mod geo;
+mod instancing;
+mod materials;
 mod player;
 mod scene;
+mod sky;
+mod terrain;
+#[cfg(test)]
+mod test_util;
 mod ui;
+mod vegetation;
+mod water;
 mod main_menu;
 
 //use start_menu::MainMenuPlugin;
@@ -20,18 +33,46 @@ fn main() {
             ThirdPersonCameraPlugin,
             ui::UiPlugin,
             camera::CameraPlugin,
+            geo::GeoPlugin,
             player::PlayerPlugin,
+            collision::CollisionPlugin,
+            water::WaterPlugin,
+            buildings::BuildingsPlugin,
+            airports::AirportsPlugin,
+            vegetation::VegetationPlugin,
+            sky::SkyPlugin,
+            export::ExportPlugin,
             main_menu::MainMenuPlugin
         ))
         .add_plugins(ObjPlugin)
+        .add_plugins(instancing::InstancingPlugin)
+        .add_plugins(MaterialPlugin::<materials::TerrainMaterial>::default())
+        .init_resource::<terrain::settings::TerrainSettings>()
+        .init_resource::<terrain::cache::TerrainTileCache>()
+        .init_resource::<terrain::source::TerrainSource>()
+        .init_resource::<terrain::imagery::ImagerySource>()
+        .init_resource::<terrain::vector::VectorSource>()
+        .init_resource::<terrain::chunk_manager::ChunkManager>()
+        .init_resource::<scene::ChunkBoundsDebug>()
+        .add_event::<terrain::chunk_manager::ChunkStateChanged>()
         .add_systems(Startup, (
             scene::setup,
             scene::generate_pre_chunks,
         ))
         .add_systems(Update, (
-            scene::generate_chunks_update,
+            (
+                terrain::settings::adjust_terrain_settings,
+                terrain::settings::apply_terrain_settings,
+                scene::resize_chunk_pool,
+                scene::generate_chunks_update,
+                scene::dispatch_chunk_loads,
+            ).chain(),
             scene::handle_terrain_data_threads,
             scene::update_sky_box,
+            scene::toggle_chunk_bounds,
+            scene::draw_chunk_bounds,
+            terrain::cache::clear_tile_cache,
+            terrain::chunk_manager::log_chunk_state_changes,
         ))
         .insert_state(AppState::MainMenu) //start app at main menu
         .run();
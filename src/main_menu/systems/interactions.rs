@@ -1,14 +1,17 @@
 use bevy::prelude::*;
 use rand::random;
 
+use crate::geo::{GeoOrigin, SpawnPoint};
 use crate::main_menu::components::*;
 use crate::main_menu::styles::{HOVERED_BUTTON_COLOR, NORMAL_BUTTON_COLOR, PRESSED_BUTTON_COLOR};
-use crate::player::Player;
+use crate::player::{start_position, start_velocity, MovementSettings, PendingSpawnHeight, Player};
+use crate::terrain::settings::TerrainSettings;
 use crate::ui::PauseState;
 use crate::AppState;
 
 
 //function to handle play button clicks. similar functions must be made for other buttons
+#[allow(clippy::too_many_arguments)]
 pub fn interact_with_play_button(
     mut button_query: Query<
         (&Interaction, &mut BackgroundColor),
@@ -16,18 +19,21 @@ pub fn interact_with_play_button(
     >,
     mut app_state_next_state: ResMut<NextState<AppState>>,
     mut player_q: Query<&mut Transform, With<Player>>,
-    mut pause_state: ResMut<PauseState>
+    mut pause_state: ResMut<PauseState>,
+    mut movement: ResMut<MovementSettings>,
+    mut pending: ResMut<PendingSpawnHeight>,
+    spawn_point: Res<SpawnPoint>,
+    geo_origin: Res<GeoOrigin>,
+    terrain_settings: Res<TerrainSettings>,
 ) {
     if let Ok((interaction, mut background_color)) = button_query.get_single_mut() {
         match *interaction {
             Interaction::Pressed => {
                 *background_color = PRESSED_BUTTON_COLOR.into();
                 for mut player_transform in player_q.iter_mut() {
-                    let x = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
-                    let y = 100.0 as f32;
-                    let z = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
-                    player_transform.translation = Vec3::new(x, y, z);
+                    player_transform.translation = start_position(&spawn_point, &geo_origin, &terrain_settings, &mut pending);
                 }
+                movement.velocity = start_velocity(&spawn_point);
                 
                 app_state_next_state.set(AppState::Game);
                 pause_state.is_paused = false;
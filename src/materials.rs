@@ -1,37 +1,100 @@
-//Taken from https://github.com/bevyengine/bevy/blob/main/examples/shader/shader_material.rs
-//WIP DOES NOT WORK YET
+//Terrain material
+//Extends the StandardMaterial so lighting, shadows and fog keep working, the shader only picks the
+//base colour: sand near sea level, grass above it, snow up high and rock on steep slopes, with the map
+//overlay on top.
+//Based on https://github.com/bevyengine/bevy/blob/main/examples/shader/extended_material.rs
 
 use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension},
     prelude::*,
-    pbr::{MaterialPipeline, MaterialPipelineKey},
     reflect::TypePath,
-    render::{
-        mesh::MeshVertexBufferLayout,
-        render_resource::{
-            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
-        },
-    },
+    render::render_resource::{AsBindGroup, ShaderRef},
 };
 
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, BasicTerrainMaterial>;
+
 //basic terrain shader
+//bindings start at 100 so they don't clash with the StandardMaterial's
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct BasicTerrainMaterial {
-    #[uniform(0)]
-    pub color: Color,
-    #[texture(1)]
-    #[sampler(2)]
-    pub color_texture: Option<Handle<Image>>,
-    pub alpha_mode: AlphaMode,
+    #[uniform(100)]
+    pub sand_color: Color,
+    #[uniform(100)]
+    pub grass_color: Color,
+    #[uniform(100)]
+    pub rock_color: Color,
+    #[uniform(100)]
+    pub snow_color: Color,
+    //world height where sand turns into grass
+    #[uniform(100)]
+    pub sand_height: f32,
+    //world height where the snow starts
+    #[uniform(100)]
+    pub snow_height: f32,
+    //height over which neighbouring bands blend into each other
+    #[uniform(100)]
+    pub height_blend: f32,
+    //slope, 1 - normal.y, where the ground turns to rock
+    #[uniform(100)]
+    pub rock_slope: f32,
+    //slope range over which grass and snow blend into rock
+    #[uniform(100)]
+    pub slope_blend: f32,
+    //1 when the chunk has imagery as its base colour texture, turns the height and slope colouring off
+    #[uniform(100)]
+    pub use_imagery: f32,
+    //1 on placeholder chunks whose tile failed to load, they get a warning checkerboard
+    #[uniform(100)]
+    pub failed: f32,
+    //1 when the overlay texture holds this chunk's land use, water and roads
+    #[uniform(100)]
+    pub use_overlay: f32,
+    //transparent map overlay laid over the ground colour, see terrain::overlay
+    #[texture(101)]
+    #[sampler(102)]
+    pub overlay: Option<Handle<Image>>,
+}
+
+impl Default for BasicTerrainMaterial {
+    fn default() -> Self {
+        Self {
+            sand_color: Color::hex("c2b280").unwrap(),
+            grass_color: Color::hex("38703b").unwrap(),
+            rock_color: Color::hex("6b6259").unwrap(),
+            snow_color: Color::hex("f2f4f7").unwrap(),
+            sand_height: 8.,
+            snow_height: 2800.,
+            height_blend: 60.,
+            //about 35 degrees
+            rock_slope: 0.18,
+            slope_blend: 0.08,
+            use_imagery: 0.,
+            failed: 0.,
+            use_overlay: 0.,
+            overlay: None,
+        }
+    }
 }
 
-/// The Material trait is very configurable, but comes with sensible defaults for all methods.
-/// You only need to implement functions for features that need non-default behavior. See the Material api docs for details!
-/// When using the GLSL shading language for your shader, the specialize method must be overridden.
-impl Material for BasicTerrainMaterial {
+impl MaterialExtension for BasicTerrainMaterial {
     fn fragment_shader() -> ShaderRef {
         "shaders/terrain_shader.wgsl".into()
     }
-    fn alpha_mode(&self) -> AlphaMode {
-        self.alpha_mode
+    fn deferred_fragment_shader() -> ShaderRef {
+        "shaders/terrain_shader.wgsl".into()
     }
-}
\ No newline at end of file
+}
+
+//terrain material for a chunk
+pub fn terrain_material() -> TerrainMaterial {
+    ExtendedMaterial {
+        base: StandardMaterial {
+            perceptual_roughness: 0.9,
+            metallic: 0.0,
+            reflectance: 0.2,
+            fog_enabled: true,
+            ..default()
+        },
+        extension: BasicTerrainMaterial::default(),
+    }
+}
@@ -10,6 +10,10 @@ use bevy::{
 use bevy_third_person_camera::ThirdPersonCameraTarget;
 use rand::{distributions::Normal, Rng};
 
+use crate::collision::CrashState;
+use crate::geo::{lat_lon_to_tile, GeoOrigin, SpawnPoint};
+use crate::terrain::query::TerrainQuery;
+use crate::terrain::settings::TerrainSettings;
 use crate::ui::PauseState;
 pub struct PlayerPlugin;
 
@@ -17,7 +21,8 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, spawn_player)
             .init_resource::<MovementSettings>()
-            .add_systems(PreUpdate, player_movement);
+            .init_resource::<PendingSpawnHeight>()
+            .add_systems(PreUpdate, (place_above_ground, player_movement).chain());
     }
 }
 
@@ -85,9 +90,84 @@ fn spawn_player(
     );
     commands.spawn(player);
 }
+
+//metres out from the threshold a runway spawn starts, lined up with the runway
+const APPROACH_DISTANCE: f32 = 10000.;
+//metres above the ground the plane starts at
+const SPAWN_HEIGHT: f32 = 1000.;
+
+//set when the plane started somewhere without a known elevation, it's lifted above the ground as soon
+//as the chunk under it loads
+#[derive(Resource, Default)]
+pub struct PendingSpawnHeight(pub bool);
+
+//direction a true heading points in the world, +z is south
+fn heading_direction(heading: f32) -> Vec3 {
+    let (sin, cos) = heading.to_radians().sin_cos();
+    Vec3::new(sin, 0., -cos)
+}
+
+//where the plane starts a flight, above the spawn point if one is set, out on the approach when it's a
+//runway, otherwise somewhere random. Heights are scaled like the terrain's, spawns without a known
+//elevation wait for the ground to load
+pub fn start_position(
+    spawn_point: &SpawnPoint,
+    geo_origin: &GeoOrigin,
+    settings: &TerrainSettings,
+    pending: &mut PendingSpawnHeight,
+) -> Vec3 {
+    if let Some(spawn) = &spawn_point.0 {
+        let mut position = geo_origin.lat_lon_to_world(spawn.point);
+        if let Some(heading) = spawn.heading {
+            position -= heading_direction(heading) * APPROACH_DISTANCE;
+        }
+        let tile_y = lat_lon_to_tile(spawn.point, geo_origin.zoom).y.floor().max(0.) as u32;
+        let scale = geo_origin.vertical_scale(tile_y);
+        position.y = (spawn.elevation.unwrap_or(0.) * settings.exaggeration + SPAWN_HEIGHT) * scale;
+        pending.0 = spawn.elevation.is_none();
+        info!("spawning at {}", spawn.name);
+        return position;
+    }
+    let x = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
+    let y = 100.0 as f32;
+    let z = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
+    pending.0 = true;
+    Vec3::new(x, y, z)
+}
+
+//puts the plane SPAWN_HEIGHT over the ground once the chunk under a spawn without an elevation is ready,
+//before collision can find it inside a mountain
+fn place_above_ground(
+    mut pending: ResMut<PendingSpawnHeight>,
+    mut player_q: Query<&mut Transform, With<Player>>,
+    geo_origin: Res<GeoOrigin>,
+    terrain: TerrainQuery,
+) {
+    if !pending.0 {
+        return;
+    }
+    let Ok(mut transform) = player_q.get_single_mut() else {
+        return;
+    };
+    let position = transform.translation;
+    let Some(ground) = terrain.sample(position.x, position.z) else {
+        return;
+    };
+    let tile_y = geo_origin.world_to_tile(position).y.floor().max(0.) as u32;
+    transform.translation.y = ground.height.max(0.) + SPAWN_HEIGHT * geo_origin.vertical_scale(tile_y);
+    pending.0 = false;
+}
+//starting velocity, pointed down the runway for runway spawns
+pub fn start_velocity(spawn_point: &SpawnPoint) -> Vec3 {
+    let velocity = MovementSettings::default().velocity;
+    match spawn_point.0.as_ref().and_then(|spawn| spawn.heading) {
+        Some(heading) => heading_direction(heading) * velocity.length(),
+        None => velocity,
+    }
+}
 static mut TIMER: f32 = 0.;
 
-fn player_movement(
+pub fn player_movement(
     time: Res<Time>,
     keys: Res<ButtonInput<KeyCode>>,
     primary_window: Query<&Window, With<PrimaryWindow>>,
@@ -96,29 +176,15 @@ fn player_movement(
     mut pause: ResMut<PauseState>,
     mut cam_q: Query<&Transform, (With<Camera3d>, Without<Player>)>,
     mut gizmos: Gizmos,
+    crash: Res<CrashState>,
 ) {
-    if pause.is_paused {
+    //the sim stays frozen after a crash until the player restarts
+    if pause.is_paused || crash.is_crashed() {
         return;
     }
 
     if let Ok(window) = primary_window.get_single() {
         for mut player_transform in player_q.iter_mut() {
-            if keys.just_pressed(KeyCode::KeyR) {
-                let x = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
-                let y = 100.0 as f32;
-                let z = ((rand::random::<u32>() as f32 / u32::MAX as f32) * 2. - 1.) * 10000.;
-                player_transform.translation = Vec3::new(x, y, z);
-            }
-
-            if player_transform.translation.y < 2.5 {
-                let y = 1000.0 as f32;
-                player_transform.translation = Vec3::new(
-                    player_transform.translation.x,
-                    y,
-                    player_transform.translation.z,
-                );
-            }
-
             let delta = time.delta().as_secs_f32();
 
             unsafe {
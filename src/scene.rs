@@ -7,55 +7,42 @@
 use bevy::math::vec3;
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::render::render_resource::PrimitiveTopology;
-use bevy::render::mesh::Indices;
-use bevy::utils::HashSet;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
 use image::{DynamicImage, GenericImageView};
-use std::borrow::Borrow;
 use std::path::Path;
-use std::collections::HashMap;
-use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
 use bevy::{
     pbr::{CascadeShadowConfigBuilder, NotShadowCaster},
     prelude::*,
 };
-use std::fs;
-use std::env;
 use bevy::tasks::AsyncComputeTaskPool;
 use bevy::tasks::Task;
 use futures_lite::future;
-use bevy::render::view::NoFrustumCulling;
-use crate::player::Player;
-
-#[derive(Copy, Clone)]
-struct Chunk{
-    position: Vec3,
-    remove_flag: bool,
-    entity: Entity,
-}
+use bevy::render::primitives::Aabb;
+use crate::airports::Airports;
+use crate::airports::runway::{chunk_runways, flatten_runways, ChunkRunway};
+use crate::geo::GeoOrigin;
+use crate::materials::{terrain_material, TerrainMaterial};
+use crate::player::{MovementSettings, Player};
+use crate::terrain::chunk_manager::{ChunkHeights, ChunkManager, ChunkMeshData, ChunkState, ChunkStateChanged};
+use crate::terrain::fetch::TerrainFetchError;
+use crate::terrain::heightfield::{HeightSampler, Heightfield};
+use crate::terrain::imagery::ImagerySource;
+use crate::terrain::load_queue::LoadFocus;
+use crate::terrain::lod::{build_lod_quadtree, lod_resolution};
+use crate::terrain::overlay::{land_use_size, overlay_tiles, overlay_size, rasterise_land_use, rasterise_overlay, LandUseMap};
+use crate::terrain::settings::TerrainSettings;
+use crate::terrain::source::{TerrainSource, TerrainTileSource};
+use crate::terrain::vector::{VectorSource, VectorTileSource};
+use crate::water::WaterMask;
 
 const INITIAL_HM_PATH: &str = "./assets/images/terrainhm.png";
-const HM_HEIGHT: f32 = 50.;
-
-//Chunk generation settings
-const CHUNK_SIZE: f32 = 2500.;          
-const CHUNK_RES: usize = 512;               //todo: have low resolution meshed along with high resolution meshes
-const CHUNK_VIEW_DISTANCE: u32 = 8;        //todo: make this mutable
-const TERRAIN_ZOOM: u32 = 8;        //todo: make this mutable
-
-//Used for chunk entity world placement
-static mut CREATED_CHUNKS: Vec<Chunk> = Vec::new();     //represents created chunks
-static mut NULL_CHUNKS: Vec<Chunk> = Vec::new();        //represents null chunks
-
-//used for terrain data fetching
-// static mut CHUNK_POS_THREAD_HANDLE: Lazy<HashMap<String, JoinHandle<Option<Mesh>>>> = Lazy::new(|| {
-//     let map = HashMap::new();
-//     map
-// });
-// static mut THREAD_COUNT: u32 = 0;
-static mut CHUNK_THREAD: Lazy<HashSet<String>> = Lazy::new(|| {
-    let map = HashSet::new();
-    map
-});
+
+//Chunk generation settings live in the TerrainSettings resource (see terrain::settings)
+//chunk sizes come from GeoOrigin, world units are metres
+//time per frame applying finished chunk meshes may take, on top of TerrainSettings::max_uploads
+const MESH_UPLOAD_BUDGET: Duration = Duration::from_millis(4);
+const SKIRT_DEPTH: f32 = 0.04;  //how far chunk skirts hang down to hide cracks between lod levels, as a fraction of the chunk size
 
 #[derive(Component)]
 pub struct SkyBoxComponent {}
@@ -112,129 +99,97 @@ pub fn setup(
     info!("Use the mouse to look around");
     info!("Press Esc to hide or show the mouse cursor");
 }
-fn get_pixel_height(height_map: &DynamicImage, x: u32, y: u32, is_nextzen: bool) -> f32 {
-    unsafe{
-        let (width, height) = height_map.dimensions();
-        let x = x.min(width - 1);
-        let y = y.min(height - 1);
-        let pixel = height_map.unsafe_get_pixel(x, y);
-    
-        if is_nextzen {
-            //height = (red * 256 + green + blue / 256) - 32768
-            let r = pixel[0] as f32;
-            let g = pixel[1] as f32;
-            let b = pixel[2] as f32;
-            let height = (r + g / 256. + b / (256. * 256.)) - 128.;
-            return height;
-        }
-        else {
-            return pixel[0] as f32 / 256.0;
+fn compute_world_space_normal(sampler: &HeightSampler, pixel_x: f32, pixel_y: f32, world_size: f32) -> Vec3 {
+    // Sample neighboring heights
+    let left_height = sampler.sample(pixel_x - 1., pixel_y);
+    let right_height = sampler.sample(pixel_x + 1., pixel_y);
+    let up_height = sampler.sample(pixel_x, pixel_y - 1.);
+    let down_height = sampler.sample(pixel_x, pixel_y + 1.);
+
+    //heights and pixel spacing are both in world units, +y in the image is +z in the world
+    let pixel_w = world_size / sampler.width() as f32;
+    let pixel_h = world_size / sampler.height() as f32;
+    let dx = (right_height - left_height) / (2. * pixel_w);
+    let dz = (down_height - up_height) / (2. * pixel_h);
+    Vec3::new(-dx, 1.0, -dz).normalize()
+}
+//height and normal of vertex x, y of a chunk_res x chunk_res grid
+fn vertex_height_and_normal(sampler: &HeightSampler, x: usize, y: usize, chunk_res: usize, world_size: f32) -> (f32, Vec3) {
+    //vertices run edge to edge over the tile, the first and last ones sit exactly on the tile border
+	let ratio_w = (sampler.width() as f32) / ((chunk_res - 1) as f32);
+	let ratio_h = (sampler.height() as f32) / ((chunk_res - 1) as f32);
+
+    let pixel_x = (x as f32) * ratio_w;
+    let pixel_y = (y as f32) * ratio_h;
+
+    //take 9 samples to smooth out height
+    let x_off = 1;
+    let y_off = 1;
+
+    let mut samples = 0.;
+    let mut height = 0.;
+    for i in -x_off..(x_off + 1){
+        for j in -y_off..(y_off + 1){
+            samples += 1.;
+            height += sampler.sample(pixel_x + i as f32 * ratio_w, pixel_y + j as f32 * ratio_h);
         }
     }
+    height /= samples;
+
+    (height, compute_world_space_normal(sampler, pixel_x, pixel_y, world_size))
 }
-fn compute_world_space_normal(height_map: &DynamicImage, x: u32, y: u32, is_nextzen: bool) -> Vec3 {
-    // Sample neighboring heights
-    let left_height = get_pixel_height(height_map, x.saturating_sub(1), y, is_nextzen);
-    let right_height = get_pixel_height(height_map, x + 1, y, is_nextzen);
-    let up_height = get_pixel_height(height_map, x, y.saturating_sub(1), is_nextzen);
-    let down_height = get_pixel_height(height_map, x, y + 1, is_nextzen);
-
-    // let world_normal = Vec3::new(right_height-left_height, 1.0,  up_height-down_height).normalize();
-    // return world_normal;
-
-    let scale_x = 8 as f32 / height_map.dimensions().0 as f32;
-    let scale_y = 8 as f32 / height_map.dimensions().1 as f32;
-    let dx = right_height - left_height;
-    let dy = up_height - down_height;
-    let va = Vec3::new(scale_x, 0.0, dx);
-    let vb = Vec3::new(0.0, scale_y, dy);
-    let normal = va.cross(vb);
-    let world_normal = Vec3::new(-normal.x, normal.z, -normal.y);
-    // Normalize the normal vector
-    world_normal.normalize()
-}
-fn create_terrain_mesh_from_path(path: &str, is_nextzen: bool) -> Mesh{
+fn create_terrain_mesh_from_path(path: &str, is_nextzen: bool, world_size: f32, chunk_res: usize) -> Mesh{
     if path.trim() == "" {
-        let (vertices, normals, indices) = generate_mesh_no_height(CHUNK_SIZE, CHUNK_RES);
+        let (vertices, normals, uvs, indices) = generate_mesh_no_height(world_size, chunk_res);
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
         let indi = Indices::U32(indices);
         mesh.insert_indices(indi);
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
         return mesh;
     }
 	let image_path = path; // Replace with the path to your image file
 	let img = image::open(&Path::new(image_path)).unwrap();
     // img.blur(16.0);
-    return create_terrain_mesh(img, is_nextzen, false);
+    let heights = Heightfield::from_image(&img, is_nextzen);
+    return create_terrain_mesh(&heights, chunk_res, world_size);
 }
-fn create_terrain_mesh(img: DynamicImage, is_nextzen: bool, is_flat: bool) -> Mesh{
-
-    if is_flat {
-        let (vertices, normals, indices) = generate_mesh_no_height(CHUNK_SIZE, CHUNK_RES);
-        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
-        let indi = Indices::U32(indices);
-        mesh.insert_indices(indi);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-        return mesh;
-    }
-
-	let (vertices, normals, indices) = generate_mesh(img, CHUNK_SIZE, CHUNK_RES, HM_HEIGHT, is_nextzen);
+//heights are expected in world units already
+fn create_terrain_mesh(heights: &Heightfield, chunk_res: usize, world_size: f32) -> Mesh{
+	let (mut vertices, mut normals, mut uvs, mut indices) = generate_mesh(&HeightSampler::new(heights), world_size, chunk_res);
+    add_skirts(&mut vertices, &mut normals, &mut uvs, &mut indices, chunk_res, world_size * SKIRT_DEPTH);
 	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
 	let indi = Indices::U32(indices);
 	mesh.insert_indices(indi);
 	mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
 	mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
     return mesh;
 }
-fn generate_mesh(texture_height_map: DynamicImage, world_size: f32, chunk_res: usize, height_scale: f32, is_nextzen: bool) -> (Vec<Vec3>, Vec<Vec3>, Vec<u32>) {
+//uvs run 0..1 over the chunk from the north west corner, matching the tile's pixels so imagery lines up
+fn generate_mesh(sampler: &HeightSampler, world_size: f32, chunk_res: usize) -> (Vec<Vec3>, Vec<Vec3>, Vec<[f32; 2]>, Vec<u32>) {
     let mut vertices: Vec<Vec3> = Vec::new();
     let mut normals = Vec::new();
+    let mut uvs = Vec::new();
     let mut indices = Vec::new();
 
 	let step = world_size / (chunk_res - 1) as f32;
 
-	let (width, height) = texture_height_map.dimensions();
-    // println!("Texture size: width: {} height: {}", width, height);
-	let ratio_w = (width as f32) / (chunk_res as f32);
-	let ratio_h = (height as f32) / (chunk_res as f32);
-    // println!("Texture ratio: width: {} height: {}", ratio_w, ratio_h);
-
     // Generate vertices for a nxn quad
     for y in 0..chunk_res {
         for x in 0..chunk_res {
-
-            let pixel_x = (x as f32) * ratio_w;
-            let pixel_y = (y as f32) * ratio_h;
-            //take 5 samples to smooth out height
-
-            let x_off = 1;
-            let y_off = 1;
-
-            let mut samples = 0.;
-            let mut height = 0.;
-            for i in -x_off..(x_off + 1){
-                for j in -y_off..(y_off + 1){
-                    let pixel_x = (x as f32 + i as f32) * ratio_w;
-                    let pixel_y = (y as f32 + j as f32) * ratio_h;
-                
-                    samples = samples + 1.;
-                    height = height + get_pixel_height(&texture_height_map, pixel_x as u32, pixel_y as u32, is_nextzen);
-                }
-            }
-            height = height / samples;
-
-            let normal = compute_world_space_normal(&texture_height_map, pixel_x as u32, pixel_y as u32, is_nextzen);
+            let (height, normal) = vertex_height_and_normal(sampler, x, y, chunk_res, world_size);
 
             // Calculate position for each vertex
             let position = Vec3::new(
 				x as f32 * step  - world_size / 2.0, 
-				height * height_scale,
+				height,
 				y as f32 * step - world_size / 2.0);
             
             vertices.push(position);
             normals.push(normal);
+            uvs.push([x as f32 / (chunk_res - 1) as f32, y as f32 / (chunk_res - 1) as f32]);
 
             // Create indices for the quad
             if x < chunk_res - 1 && y < chunk_res - 1 {
@@ -256,11 +211,85 @@ fn generate_mesh(texture_height_map: DynamicImage, world_size: f32, chunk_res: u
         }
     }
 
-    (vertices, normals, indices)
+    (vertices, normals, uvs, indices)
 }
-fn generate_mesh_no_height(world_size: f32, res: usize) -> (Vec<Vec3>, Vec<Vec3>, Vec<u32>) {
+//recompute the two outer rings of a chunk mesh, and the skirts hanging off them, from a sampler that
+//can see the neighbouring chunks. Called whenever a neighbour arrives so the shared edges line up
+fn patch_chunk_borders(mesh: &mut Mesh, sampler: &HeightSampler, chunk_res: usize, world_size: f32) {
+    let mut patched = Vec::new();
+    for y in 0..chunk_res {
+        for x in 0..chunk_res {
+            let inner = x >= 2 && x + 2 < chunk_res && y >= 2 && y + 2 < chunk_res;
+            if !inner {
+                patched.push((y * chunk_res + x, vertex_height_and_normal(sampler, x, y, chunk_res, world_size)));
+            }
+        }
+    }
+    let border = border_indices(chunk_res);
+    let first_skirt = chunk_res * chunk_res;
+
+    if let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
+        for (i, (height, _)) in patched.iter() {
+            positions[*i][1] = *height;
+        }
+        for (s, b) in border.iter().enumerate() {
+            positions[first_skirt + s][1] = positions[*b][1] - world_size * SKIRT_DEPTH;
+        }
+    }
+    if let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
+        for (i, (_, normal)) in patched.iter() {
+            normals[*i] = normal.to_array();
+        }
+        for (s, b) in border.iter().enumerate() {
+            normals[first_skirt + s] = normals[*b];
+        }
+    }
+}
+//vertex indices around the edge of a res x res grid: north edge left to right, then east, south and west
+fn border_indices(res: usize) -> Vec<usize> {
+    let mut border = Vec::new();
+    for x in 0..res - 1 {
+        border.push(x);
+    }
+    for y in 0..res - 1 {
+        border.push(y * res + res - 1);
+    }
+    for x in (1..res).rev() {
+        border.push((res - 1) * res + x);
+    }
+    for y in (1..res).rev() {
+        border.push(y * res);
+    }
+    border
+}
+//hang a vertical strip of triangles off every edge of the chunk
+//neighbouring chunks at a different lod don't share edge vertices, the skirts cover the gaps between them
+fn add_skirts(vertices: &mut Vec<Vec3>, normals: &mut Vec<Vec3>, uvs: &mut Vec<[f32; 2]>, indices: &mut Vec<u32>, res: usize, depth: f32) {
+    let border = border_indices(res);
+
+    let first_skirt = vertices.len() as u32;
+    for &b in border.iter() {
+        vertices.push(vertices[b] - Vec3::new(0.0, depth, 0.0));
+        normals.push(normals[b]);
+        uvs.push(uvs[b]);
+    }
+
+    for i in 0..border.len() {
+        let next = (i + 1) % border.len();
+        let top_a = border[i] as u32;
+        let top_b = border[next] as u32;
+        let bottom_a = first_skirt + i as u32;
+        let bottom_b = first_skirt + next as u32;
+
+        //wound to face away from the chunk
+        indices.extend_from_slice(&[top_a, top_b, bottom_b]);
+        indices.extend_from_slice(&[top_a, bottom_b, bottom_a]);
+    }
+}
+fn generate_mesh_no_height(world_size: f32, res: usize) -> (Vec<Vec3>, Vec<Vec3>, Vec<[f32; 2]>, Vec<u32>) {
     let mut vertices: Vec<Vec3> = Vec::new();
     let mut normals = Vec::new();
+    let mut uvs = Vec::new();
     let mut indices = Vec::new();
 
 	let step = world_size / (res - 1) as f32;
@@ -276,6 +305,7 @@ fn generate_mesh_no_height(world_size: f32, res: usize) -> (Vec<Vec3>, Vec<Vec3>
             let normal = Vec3::new(0.0, 1.0, 0.0);
             vertices.push(position);
             normals.push(normal);
+            uvs.push([x as f32 / (res - 1) as f32, y as f32 / (res - 1) as f32]);
             // Create indices for the quad
             if x < res - 1 && y < res - 1 {
                 let index = (y * res + x) as u32;
@@ -296,163 +326,150 @@ fn generate_mesh_no_height(world_size: f32, res: usize) -> (Vec<Vec3>, Vec<Vec3>
         }
     }
 
-    (vertices, normals, indices)
+    (vertices, normals, uvs, indices)
 }
 
 #[derive(Component)]
 pub struct ChunkComponent{}
+
+//placeholder mesh new chunk entities start out with
+#[derive(Resource)]
+pub struct ChunkAssets{
+    mesh: Handle<Mesh>,
+}
+fn spawn_chunk_entity(
+    commands: &mut Commands,
+    materials: &mut Assets<TerrainMaterial>,
+    chunk_assets: &ChunkAssets,
+) -> Entity{
+    let new_transform = Transform::from_translation(vec3(0.0, 0.0, 0.0));
+
+    //colour comes from height and slope, see materials.rs
+    let mat = terrain_material();
+
+    //create entity
+    let chunk_entity = 
+    commands.spawn((
+        //tag this entity as a chunk with chunk component
+        ChunkComponent{},
+        MaterialMeshBundle{
+            mesh: chunk_assets.mesh.clone(),
+            transform: new_transform,
+            material: materials.add(mat),
+            ..Default::default()
+        }
+    )).id();
+    chunk_entity
+}
 pub fn generate_pre_chunks(    
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    settings: Res<TerrainSettings>,
+    geo_origin: Res<GeoOrigin>,
 ){
-    
-    if !Path::new("./temp/").exists(){
-        fs::create_dir("./temp").unwrap();
-    }
-    else {
-        fs::remove_dir_all("./temp").unwrap();
-        fs::create_dir("./temp").unwrap();
-    } 
 
     //lets create a whole bunch of chunks
-    unsafe{
-        //generate chunk based on image file
-        let new_mesh = create_terrain_mesh_from_path(INITIAL_HM_PATH, false);
-        
-        let mesh_handle = meshes.add(new_mesh);
-
-        //generate chunk entities
-        for i in 0..(CHUNK_VIEW_DISTANCE * CHUNK_VIEW_DISTANCE){
-            let new_transform = Transform::from_translation(vec3(0.0, 0.0, 0.0));
-
-            let mut mat = StandardMaterial::default();
-            mat.perceptual_roughness = 0.5;
-            mat.metallic = 0.0;
-            mat.base_color = Color::hex("38703b").unwrap();
-            mat.emissive = Color::rgb(0.0, 0.0, 0.0);
-            mat.fog_enabled = true;
-
-            //create entity
-            let chunk_entity = 
-            commands.spawn((
-                //tag this entity as a chunk with chunk component
-                ChunkComponent{},
-                PbrBundle{
-                    mesh: mesh_handle.clone(),
-                    transform: new_transform,
-                    material: materials.add(mat),
-                    ..Default::default()
-                }
-            )).id();
-            commands.entity(chunk_entity).insert(NoFrustumCulling);
-            //save entity, transform, position, and flag
-            NULL_CHUNKS.push(Chunk{
-                position: vec3(0.0, 0.0, 0.0),
-                remove_flag: false,
-                entity: chunk_entity,
-            })
-        }
-        // println!("created {num} chunks", num = CHUNK_VIEW_DISTANCE * CHUNK_VIEW_DISTANCE);
+    //generate chunk based on image file
+    let new_mesh = create_terrain_mesh_from_path(INITIAL_HM_PATH, false, geo_origin.chunk_size, settings.chunk_res);
+    
+    let chunk_assets = ChunkAssets{ mesh: meshes.add(new_mesh) };
+
+    //generate chunk entities
+    for _ in 0..settings.chunk_count(){
+        let chunk_entity = spawn_chunk_entity(&mut commands, &mut materials, &chunk_assets);
+        //unused chunks wait in the pool until the view needs them
+        chunk_manager.add_to_pool(chunk_entity);
     }
+    commands.insert_resource(chunk_assets);
 }
-
-fn get_chunk_space_position(position: Vec3) -> Vec3{
-    let x;
-    let y;
-    let z;
-    unsafe{
-        x = (position.x / (CHUNK_SIZE)).round();
-        y = (position.y / (CHUNK_SIZE)).round();
-        z = (position.z / (CHUNK_SIZE)).round();
+//grow or shrink the pool when the view distance changes
+//chunks still in use are only despawned once they have been evicted and are back in the pool
+pub fn resize_chunk_pool(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    chunk_assets: Res<ChunkAssets>,
+    settings: Res<TerrainSettings>,
+){
+    let target = settings.chunk_count();
+    while chunk_manager.entity_count() < target {
+        let chunk_entity = spawn_chunk_entity(&mut commands, &mut materials, &chunk_assets);
+        chunk_manager.add_to_pool(chunk_entity);
     }
+    while chunk_manager.entity_count() > target {
+        let Some(chunk_entity) = chunk_manager.remove_from_pool() else {
+            break;
+        };
+        commands.entity(chunk_entity).despawn_recursive();
+    }
+}
+
+pub fn get_chunk_space_position(position: Vec3, chunk_size: f32) -> Vec3{
+    let x = (position.x / chunk_size).round();
+    let y = (position.y / chunk_size).round();
+    let z = (position.z / chunk_size).round();
     Vec3::new(x, y, z)
 }
-fn get_world_space_position(position: Vec3) -> Vec3{
-    let x;
-    let y;
-    let z;
-    unsafe{
-        x = (position.x * CHUNK_SIZE).round();
-        y = (position.y * CHUNK_SIZE).round();
-        z = (position.z * CHUNK_SIZE).round();
-    }
+pub fn get_world_space_position(position: Vec3, chunk_size: f32) -> Vec3{
+    let x = (position.x * chunk_size).round();
+    let y = (position.y * chunk_size).round();
+    let z = (position.z * chunk_size).round();
     Vec3::new(x, y, z)
 }
-fn chunk_exists(position: Vec3) -> (bool, usize){
-    let mut isit: bool = false;
-    let mut index: usize = 0;
-    unsafe{
-        for chunks in CREATED_CHUNKS.iter() {
-            if chunks.position == position{
-                isit = true;
-                break;
-            }
-            index = index + 1;
-        }
-    }
-    return (isit, index);
+fn chunk_world_position(coord: IVec2, chunk_size: f32) -> Vec3{
+    get_world_space_position(Vec3::new(coord.x as f32, 0.0, coord.y as f32), chunk_size)
 }
 
-static mut UPDATE_MESH_QUEUE: Vec<(Entity, Mesh, Vec3)> = Vec::new();
-pub fn fetch_terrain_data(chunk_x: i32, chunk_y: i32) -> Option<Mesh>{
-    let z = TERRAIN_ZOOM as i32;      //zoom
-    let max = f32::powf(2.0, z as f32) - 1.0;
-    let tilesize = 512;
-    let mut x = (chunk_x as f32 + max * 0.5) as i32;
-    let mut y = (chunk_y as f32 + max * 0.5) as i32;
-    // println!("max {}", max);
-    // println!("api x {}", x);
-    // println!("api y {}", y);
-
-    //xy position that is converted into xy space for map api
-    x = i32::clamp(x, 0, max as i32);
-    y = i32::clamp(y, 0, max as i32);
-
-    let new_chunk_x = chunk_x;
-    let new_chunk_y = chunk_y;
-    // let key = format!("{}_{}", new_chunk_x, new_chunk_y);
-    
-    //PUT YOUR OWN API!
-    let api_key = env::vars().find(|daw| daw.0 == "Nextzen_API" );
-    if api_key.is_none() {
-        println!("ERROR! NO API KEY!");
-        println!("Read README.md for more details.");
-        return None;
-    }
-
-    let api = api_key.unwrap().1;
-
-    // println!("SPAWNING thread terrain data! {}", key);
-    // THREAD_COUNT = THREAD_COUNT + 1;
-    // println!("THREADS ALIVE {}", THREAD_COUNT);
-
-    //start fetching new data on seperate thread so we dont stall main thread
-    let out_file = format!("./temp/image_{new_chunk_x}_{new_chunk_y}.png");             
+//lower lods use the parent tile a few zoom levels up and cut out the part covering this chunk
+fn fetch_chunk_tile(tile_x: u32, tile_y: u32, zoom: u32, lod: u32, source: &dyn TerrainTileSource) -> Result<DynamicImage, TerrainFetchError>{
+    let img = source.fetch_tile(zoom - lod, tile_x >> lod, tile_y >> lod)?;
+    let mask = (1 << lod) - 1;
+    let (width, height) = img.dimensions();
+    let sub_width = (width >> lod).max(1);
+    let sub_height = (height >> lod).max(1);
+    Ok(img.crop_imm((tile_x & mask) * sub_width, (tile_y & mask) * sub_height, sub_width, sub_height))
+}
 
-    //then check if a file exists already
-    //skip api call if available
-    let metadata_result = fs::metadata(out_file.clone());
-    if metadata_result.is_ok() {
-        // println!("found existing terrain data file! {}", mky);
-        let img = image::open(&Path::new(out_file.as_str())).unwrap();
-        // img.resize(256, 256,  FilterType::Gaussian);
-        let mesh = create_terrain_mesh(img, true, false);
-        return Some(mesh);
+//land use, water and roads of the chunk drawn into a texture and into a land use map. The vector tiles
+//come from the same zoom buildings use, VECTOR_TILE_ZOOM, and like the terrain go a zoom level up per
+//lod, so a chunk far away reads one tile or part of one
+fn fetch_chunk_overlay(tile_x: u32, tile_y: u32, zoom: u32, lod: u32, vector: &dyn VectorTileSource, vector_zoom: u32, geo_origin: &GeoOrigin) -> Result<(Option<Image>, Option<LandUseMap>), TerrainFetchError>{
+    let size = overlay_size(lod);
+    let pixels_per_metre = size as f32 / geo_origin.chunk_size * geo_origin.vertical_scale(tile_y);
+    let mut tiles = Vec::new();
+    for ((z, x, y), region) in overlay_tiles(zoom, tile_x, tile_y, vector_zoom.saturating_sub(lod), pixels_per_metre) {
+        match vector.fetch_tile(z, x, y) {
+            Ok(tile) => tiles.push((tile, region)),
+            Err(TerrainFetchError::NoData) => {}
+            Err(e) => return Err(e),
+        }
     }
+    let overlay = rasterise_overlay(&tiles, size)
+        .map(|img| Image::from_dynamic(DynamicImage::ImageRgba8(img), true, RenderAssetUsages::RENDER_WORLD));
+    Ok((overlay, rasterise_land_use(&tiles, land_use_size(lod))))
+}
 
-    //if checks fail, we call api to download terrain data
-
-    //API INFO:
-    //https://www.nextzen.org/
-    //https://developers.nextzen.org/about.html
-    //https://developers.nextzen.org/login
+//an optional layer of a chunk, left out on errors. Errors that may go away are kept so the chunk is
+//fetched again
+fn optional_layer<T>(what: &str, result: Result<T, TerrainFetchError>, retry: &mut Option<TerrainFetchError>) -> Option<T>{
+    match result {
+        Ok(value) => Some(value),
+        Err(TerrainFetchError::NoData) => None,
+        Err(e) => {
+            println!("{what}: {e}");
+            if e.is_retryable() {
+                *retry = Some(e);
+            }
+            None
+        }
+    }
+}
 
-    //JOSH API KEY: AEuTnCA5TvKSv-dI8lFQYw
-    //https://tile.nextzen.org/tilezen/terrain/v1/{tilesize}/terrarium/{z}/{x}/{y}.png?api_key=your-nextzen-api-key
+#[allow(clippy::too_many_arguments)]
+pub fn fetch_terrain_data(tile_x: u32, tile_y: u32, lod: u32, source: &dyn TerrainTileSource, imagery: Option<&dyn TerrainTileSource>, vector: Option<(&dyn VectorTileSource, u32)>, runways: &[ChunkRunway], geo_origin: &GeoOrigin, settings: &TerrainSettings) -> Result<ChunkMeshData, TerrainFetchError>{
     //Mercator projection
-    //height = (red * 256 + green + blue / 256) - 32768
-
     //2^z - 1
     //1 -> 1    2
     //2 -> 3    4
@@ -460,227 +477,369 @@ pub fn fetch_terrain_data(chunk_x: i32, chunk_y: i32) -> Option<Mesh>{
     //4 -> 15   16
     //5 -> 31   32
 
-    //fetch data from api
-    let url = format!("https://tile.nextzen.org/tilezen/terrain/v1/{tilesize}/terrarium/{z}/{x}/{y}.png?api_key={api}");
-    // println!("Fetching data at: ");
-    // println!("{url}");
-
-    //this only works on windows
-    //you need wget
-    // let cmd = format!("wget {url} -O {out_file}");
-    // let output = Command::new("cmd")
-    //     .args(["/c", cmd.as_str() ])
-    //     .output()
-    //     .expect("failed to execute process");
-    // let ret_str = output.status;
-    // println!("{cmd} returned {ret_str}");
-
-    let req = reqwest::blocking::Client::builder().cookie_store(true).build().unwrap();
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("authorization", "<authorization>".parse().unwrap());
-    headers.insert("user-agent","CUSTOM_NAME/1.0".parse().unwrap());
-    
-    let resp = req.get(url)
-        .headers(headers)
-        .send()
-        .unwrap();
-    
-    let resp_status = resp.status();
-    // println!("Response status: {resp_status}");
-
-    let resp_bytes = resp.bytes().unwrap();
-    fs::write(out_file.clone(), resp_bytes).unwrap();
-    // image::save_buffer(&Path::new(out_file.as_str()), &resp_bytes, tilesize, tilesize, image::ColorType::Rgb8).unwrap();
-
-    let metadata_result = fs::metadata(out_file.clone());
-    if metadata_result.is_err() {
-        return None;
-    }
-
-    let img = image::open(&Path::new(out_file.as_str())).unwrap();
-    // img.resize(256, 256,  FilterType::Gaussian);
-    let mesh = create_terrain_mesh(img, true, false);
-    return Some(mesh);
+    let zoom = geo_origin.zoom;
+
+    let lod = lod.min(zoom);
+    let img = fetch_chunk_tile(tile_x, tile_y, zoom, lod, source)?;
+
+    //metres to world units
+    let mut heights = Heightfield::from_image(&img, true);
+    heights.scale(geo_origin.vertical_scale(tile_y) * settings.exaggeration);
+    let res = lod_resolution(settings.chunk_res, lod);
+    flatten_runways(&mut heights, runways, geo_origin.chunk_size, geo_origin.chunk_size / (res - 1) as f32);
+    let mesh = create_terrain_mesh(&heights, res, geo_origin.chunk_size);
+    let water = WaterMask::from_heightfield(&heights);
+
+    //a chunk without imagery falls back to the terrain material's colours
+    let mut retry = None;
+    let imagery = imagery
+        .and_then(|imagery| optional_layer(&format!("imagery for tile {zoom}/{tile_x}/{tile_y}"), fetch_chunk_tile(tile_x, tile_y, zoom, lod, imagery), &mut retry))
+        .map(|img| Image::from_dynamic(img, true, RenderAssetUsages::RENDER_WORLD));
+    let (overlay, land_use) = vector
+        .and_then(|(vector, vector_zoom)| optional_layer(&format!("overlay for tile {zoom}/{tile_x}/{tile_y}"), fetch_chunk_overlay(tile_x, tile_y, zoom, lod, vector, vector_zoom, geo_origin), &mut retry))
+        .unwrap_or((None, None));
+    Ok(ChunkMeshData{ mesh, heights, res, water, imagery, overlay, land_use, failed: false, retry })
+}
 
-    //remember thread handle
-    // CHUNK_POS_THREAD_HANDLE.insert(key, handle);
+//flat ground at sea level for a chunk that has no tile, marked so the material can show it failed
+//a missing tile says nothing about the sea being there, so it's dry land rather than ocean
+fn placeholder_terrain_data(lod: u32, geo_origin: &GeoOrigin, settings: &TerrainSettings, failed: bool) -> ChunkMeshData{
+    let heights = Heightfield{ width: 2, height: 2, data: vec![0.; 4] };
+    let res = lod_resolution(settings.chunk_res, lod);
+    let mesh = create_terrain_mesh(&heights, res, geo_origin.chunk_size);
+    let water = WaterMask::dry();
+    ChunkMeshData{ mesh, heights, res, water, imagery: None, overlay: None, land_use: None, failed, retry: None }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_terrain_data_threads(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut chunk_query: Query<(Entity, &mut Transform), With<ChunkComponent>>,
-    mut gen_mesh_tasks: Query<&mut GenMesh>
+    mut gen_mesh_tasks: Query<(Entity, &mut GenMesh)>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut chunk_events: EventWriter<ChunkStateChanged>,
+    geo_origin: Res<GeoOrigin>,
+    chunk_materials: Query<&Handle<TerrainMaterial>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    chunk_meshes: Query<&Handle<Mesh>, With<ChunkComponent>>,
+    chunk_assets: Res<ChunkAssets>,
+    settings: Res<TerrainSettings>,
+    time: Res<Time>,
 ){
-    unsafe{
-        let mut chunk_data_results : HashMap<String, Option<Mesh>> = Default::default();
-        for mut task in &mut gen_mesh_tasks {
-            if let Some(value) = bevy::tasks::block_on(future::poll_once(&mut task.0)) {
-                
-                let cq = value.0;
-                commands.entity(cq).remove::<GenMesh>();
+    for (task_entity, mut task) in &mut gen_mesh_tasks {
+        if let Some((coord, lod, generation, mut data, error)) = bevy::tasks::block_on(future::poll_once(&mut task.0)) {
+            commands.entity(task_entity).despawn();
+            chunk_manager.finish_request(coord, lod, generation);
+
+            //results for a chunk that left the view, changed lod or was fetched with old settings are dropped
+            let wanted = chunk_manager.get(coord).is_some_and(|chunk| chunk.lod == lod && chunk.generation == generation);
+            if !wanted {
+                continue;
+            }
+            //failed chunks come with a placeholder instead of leaving a hole and are fetched again later
+            if let Some(e) = error {
+                match chunk_manager.mark_failed(coord, time.elapsed_seconds(), &e, true) {
+                    Some(delay) => println!("chunk {coord} failed to load: {e}, retrying in {delay:.1}s"),
+                    None => println!("chunk {coord} failed to load: {e}"),
+                }
+            } else if let Some(e) = data.retry.take() {
+                if let Some(delay) = chunk_manager.mark_failed(coord, time.elapsed_seconds(), &e, false) {
+                    println!("chunk {coord} is missing imagery or overlays, retrying in {delay:.1}s");
+                }
+            } else {
+                chunk_manager.mark_loaded(coord);
+            }
+            chunk_manager.upload_queue.push_back((coord, lod, generation, data));
+        }
+    }
 
-                let mesh = value.1;
-                let key = value.2;
+    //update meshes
+    //applying a mesh means patching borders and uploading it to the gpu, which spikes the frame time
+    //so only a few are applied per frame and the rest wait in the queue
+    let start = Instant::now();
+    let mut uploads = 0;
+    while uploads < settings.max_uploads && start.elapsed() < MESH_UPLOAD_BUDGET {
+        let Some((coord, lod, generation, mut data)) = chunk_manager.upload_queue.pop_front() else {
+            break;
+        };
+        let entity = match chunk_manager.get(coord) {
+            Some(chunk) if chunk.lod == lod && chunk.generation == generation => chunk.entity,
+            _ => continue,
+        };
+        uploads += 1;
+
+        //stitch this chunk's edges to the neighbours we already have
+        patch_chunk_borders(&mut data.mesh, &chunk_manager.neighbour_sampler(&data.heights, coord), data.res, geo_origin.chunk_size);
+
+        //bounds are only worked out automatically for entities without any, and the mesh asset is reused,
+        //so every new mesh brings its own. They span the real height range, skirts included
+        let bounds = data.mesh.compute_aabb();
+
+        //every chunk entity owns one mesh asset once it has shown a chunk, later chunks replace its
+        //contents instead of adding a new asset. Fresh entities still show the shared placeholder
+        let mesh_handle = match chunk_meshes.get(entity) {
+            Ok(handle) if *handle != chunk_assets.mesh && meshes.contains(handle) => {
+                let handle = handle.clone();
+                if let Some(mesh) = meshes.get_mut(&handle) {
+                    *mesh = data.mesh;
+                }
+                handle
+            }
+            _ => {
+                let handle = meshes.add(data.mesh);
+                commands.entity(entity).insert(handle.clone());
+                handle
+            }
+        };
+        chunk_manager.insert_heights(coord, ChunkHeights{
+            heights: data.heights,
+            res: data.res,
+            mesh: mesh_handle.clone(),
+            water: data.water,
+            land_use: data.land_use,
+        });
+
+        //and patch the neighbours' edges now that this chunk is here
+        for offset in ChunkManager::neighbour_offsets() {
+            let neighbour_coord = coord + offset;
+            if let Some(neighbour) = chunk_manager.heights(neighbour_coord) {
+                if let Some(mesh) = meshes.get_mut(&neighbour.mesh) {
+                    patch_chunk_borders(mesh, &chunk_manager.neighbour_sampler(&neighbour.heights, neighbour_coord), neighbour.res, geo_origin.chunk_size);
+                    //patched edges can move past the old bounds
+                    if let (Some(bounds), Some(neighbour_chunk)) = (mesh.compute_aabb(), chunk_manager.get(neighbour_coord)) {
+                        commands.entity(neighbour_chunk.entity).insert(bounds);
+                    }
+                }
+            }
+        }
 
-                chunk_data_results.insert(key.clone(), mesh);
-                CHUNK_THREAD.remove(&key.clone());
+        //recycled chunks may still hold the imagery and overlay of the chunk they showed before, their
+        //texture assets are reused the same way as the mesh
+        if let Some(material) = chunk_materials.get(entity).ok().and_then(|handle| materials.get_mut(handle)) {
+            material.extension.use_imagery = if data.imagery.is_some() { 1. } else { 0. };
+            material.extension.use_overlay = if data.overlay.is_some() { 1. } else { 0. };
+            material.extension.failed = if data.failed { 1. } else { 0. };
+            material.base.base_color_texture = replace_texture(&mut images, material.base.base_color_texture.take(), data.imagery);
+            material.extension.overlay = replace_texture(&mut images, material.extension.overlay.take(), data.overlay);
+        }
 
-                commands.entity(cq).despawn();
-            }
+        commands.entity(entity).insert(Transform::from_translation(chunk_world_position(coord, geo_origin.chunk_size)));
+        if let Some(bounds) = bounds {
+            commands.entity(entity).insert(bounds);
         }
+        chunk_manager.set_state(coord, ChunkState::Ready, &mut chunk_events);
+    }
+}
 
-        //update meshes
-        //the reason for not doing it multiple times a frame is because meshes.add causes a large spike in cpu time
-        //no way to fix it so we just do this to avoid it
-        while UPDATE_MESH_QUEUE.len() > 0 {
-            let to_update = UPDATE_MESH_QUEUE.pop();
-            if to_update.is_some(){
-                let unwrapped = to_update.unwrap();
-                let mesh_handle = meshes.add(unwrapped.1);
-                commands.entity(unwrapped.0).insert(mesh_handle);
-                let mut new_pos = unwrapped.2;
-                new_pos.y = 0.;
-                commands.entity(unwrapped.0).insert(Transform::from_translation(new_pos));
+//put a new image into a texture asset a chunk already owns, or drop the asset when there's no image
+fn replace_texture(images: &mut Assets<Image>, old: Option<Handle<Image>>, image: Option<Image>) -> Option<Handle<Image>>{
+    match (image, old) {
+        (Some(image), Some(old)) if images.contains(&old) => {
+            if let Some(texture) = images.get_mut(&old) {
+                *texture = image;
             }
+            Some(old)
         }
-
-        //apply new meshes to chunk entities
-        for (entity, transform) in chunk_query.iter_mut() {
-            let chunk_pos = get_chunk_space_position(transform.translation);
-            let mesh_key = format!("{}_{}", chunk_pos.x, chunk_pos.z);
-    
-            if chunk_data_results.contains_key(&mesh_key.clone()){
-                let new_mesh = chunk_data_results.remove(&mesh_key.clone()).unwrap();
-                if new_mesh.is_some(){
-                    //add to mesh update queue
-                    UPDATE_MESH_QUEUE.push((entity, new_mesh.unwrap(), transform.translation));
-                }
+        (Some(image), _) => Some(images.add(image)),
+        (None, old) => {
+            if let Some(old) = old {
+                images.remove(&old);
             }
+            None
         }
     }
 }
 
 #[derive(Component)]
-pub struct GenMesh(Task<(Entity, Option<Mesh>, String)>);
+pub struct GenMesh(Task<(IVec2, u32, u32, ChunkMeshData, Option<TerrainFetchError>)>);
+
+//start fetching the chunk on a seperate thread so we dont stall main thread
+#[allow(clippy::too_many_arguments)]
+fn request_chunk_mesh(
+    commands: &mut Commands,
+    chunk_manager: &mut ChunkManager,
+    chunk_events: &mut EventWriter<ChunkStateChanged>,
+    terrain_source: &TerrainSource,
+    imagery_source: &ImagerySource,
+    vector_source: &VectorSource,
+    airports: &Airports,
+    geo_origin: &GeoOrigin,
+    settings: &TerrainSettings,
+    coord: IVec2,
+    lod: u32,
+){
+    //chunks past the poles have no tile and stay hidden
+    let Some((tile_x, tile_y)) = geo_origin.chunk_to_tile(coord.x, coord.y) else {
+        return;
+    };
+
+    //a task for this chunk and lod may still be running from before, its result will be used
+    if !chunk_manager.is_in_flight(coord, lod) {
+        let generation = chunk_manager.generation();
+        let source = terrain_source.0.clone();
+        let imagery = imagery_source.0.clone();
+        let vector = vector_source.source.clone();
+        let vector_zoom = vector_source.zoom_for(geo_origin.zoom);
+        let runways = chunk_runways(&airports.0, geo_origin, coord, settings.exaggeration);
+        let geo_origin = *geo_origin;
+        let settings = *settings;
+        let task = AsyncComputeTaskPool::get().spawn(async move{
+            match fetch_terrain_data(tile_x, tile_y, lod, source.as_ref(), imagery.as_deref(), vector.as_deref().map(|vector| (vector, vector_zoom)), &runways, &geo_origin, &settings) {
+                Ok(data) => (coord, lod, generation, data, None),
+                Err(TerrainFetchError::NoData) => (coord, lod, generation, placeholder_terrain_data(lod, &geo_origin, &settings, false), None),
+                Err(e) => (coord, lod, generation, placeholder_terrain_data(lod, &geo_origin, &settings, true), Some(e)),
+            }
+        });
+        let task_entity = commands.spawn(GenMesh(task)).id();
+        chunk_manager.begin_request(coord, lod, task_entity);
+    }
+    chunk_manager.set_state(coord, ChunkState::Loading, chunk_events);
+}
 
-pub fn generate_chunks_update(
+//start fetches for the most urgent requested chunks, up to the in flight limit
+//fetches nobody needs anymore are cancelled first to make room
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_chunk_loads(
     mut commands: Commands,
-    camera_query: Query<(&Player, &Transform), Without<ChunkComponent>>, 
-    mut chunk_query: Query<(Entity, &mut Transform), With<ChunkComponent>>,
+    player_q: Query<&Transform, With<Player>>,
+    movement: Res<MovementSettings>,
+    terrain_source: Res<TerrainSource>,
+    imagery_source: Res<ImagerySource>,
+    vector_source: Res<VectorSource>,
+    airports: Res<Airports>,
+    geo_origin: Res<GeoOrigin>,
+    settings: Res<TerrainSettings>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut chunk_events: EventWriter<ChunkStateChanged>,
 ){
-    unsafe{
+    for task in chunk_manager.cancel_unneeded_requests() {
+        if let Some(mut entity) = commands.get_entity(task) {
+            entity.despawn();
+        }
+    }
 
-        //go through every created chunks and mark them as destroy
-        for chunks in CREATED_CHUNKS.iter_mut() {
-            chunks.remove_flag = true;
+    let Ok(player) = player_q.get_single() else {
+        return;
+    };
+    let max_lookahead = settings.view_distance as f32 * 0.5 * geo_origin.chunk_size;
+    let focus = LoadFocus::new(player.translation, movement.velocity, max_lookahead);
+
+    let mut queue: Vec<(f32, IVec2, u32)> = chunk_manager.chunks()
+        .filter(|chunk| chunk.state == ChunkState::Requested)
+        .map(|chunk| (focus.score(chunk_world_position(chunk.coord, geo_origin.chunk_size)), chunk.coord, chunk.lod))
+        .collect();
+    queue.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    for (_, coord, lod) in queue {
+        if chunk_manager.in_flight_count() >= settings.max_loads {
+            break;
         }
+        request_chunk_mesh(&mut commands, &mut chunk_manager, &mut chunk_events, &terrain_source, &imagery_source, &vector_source, &airports, &geo_origin, &settings, coord, lod);
+    }
+}
 
-        let item = camera_query.iter().nth(0);
-        let d = item.expect("no camera found!");
-        let camera_transform = *d.1;
+//work out which chunks the view needs at which lod, new and changed chunks go into the load queue
+#[allow(clippy::too_many_arguments)]
+pub fn generate_chunks_update(
+    camera_query: Query<(&Player, &Transform), Without<ChunkComponent>>, 
+    mut chunk_query: Query<(&mut Transform, &Handle<TerrainMaterial>), With<ChunkComponent>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    geo_origin: Res<GeoOrigin>,
+    settings: Res<TerrainSettings>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut chunk_events: EventWriter<ChunkStateChanged>,
+    time: Res<Time>,
+){
+    let item = camera_query.iter().next();
+    let d = item.expect("no camera found!");
+    let camera_transform = *d.1;
+
+    let position = camera_transform.translation;
+    //camera position in chunk space
+    let cp = get_chunk_space_position(position, geo_origin.chunk_size);
+
+    let half_chunk = (settings.view_distance as f32 * 0.5).ceil() as i32;
+    let view_min = IVec2::new(cp.x as i32 - half_chunk, cp.z as i32 - half_chunk);
+    let view_max = view_min + IVec2::splat(settings.view_distance as i32 - 1);
+    let lod_levels = build_lod_quadtree(IVec2::new(cp.x as i32, cp.z as i32), view_min, view_max);
+
+    //chunks that left the view go back to the pool first so their entities can be reused
+    let out_of_view: Vec<IVec2> = chunk_manager.chunks()
+        .map(|chunk| chunk.coord)
+        .filter(|coord| !lod_levels.contains_key(coord))
+        .collect();
+    for coord in out_of_view {
+        chunk_manager.evict(coord, &mut chunk_events);
+    }
 
-        let position = camera_transform.translation;
-        //camera position in chunk space
-        let cp = get_chunk_space_position(position);
-    
-        let half_chunk = (CHUNK_VIEW_DISTANCE as f32 * 0.5).ceil() as i32;
-        let mut chunks_to_create: Vec<Vec3> = Vec::new();
-        let thread_pool = AsyncComputeTaskPool::get();
-
-        //loop through chunk box
-        for x in 0..(CHUNK_VIEW_DISTANCE) {
-            for y in 0..(CHUNK_VIEW_DISTANCE) {
-
-                //get chunk position in chunk space
-                let cur_x = (x as i32 - half_chunk) as f32 + cp.x;
-                let cur_y = (y as i32 - half_chunk) as f32 + cp.z;
-                let cur_chunk_pos = Vec3::new(cur_x, 0.0, cur_y);
-                let (exists, index) = chunk_exists(cur_chunk_pos);
-
-                //check if this chunk already exists
-                if exists {
-                    //if it does reset remove flag
-                    CREATED_CHUNKS[index].remove_flag = false;
+    //loop through chunk box
+    for x in view_min.x..=view_max.x {
+        for y in view_min.y..=view_max.y {
+            let coord = IVec2::new(x, y);
+            let lod = lod_levels[&coord];
+
+            match chunk_manager.get(coord) {
+                //the player moved closer or further away, the settings changed or the last fetch failed,
+                //fetch it again. The old mesh stays up until the new one arrives
+                Some(chunk) if chunk.lod != lod || chunk_manager.is_stale(coord) || chunk_manager.should_retry(coord, time.elapsed_seconds()) => {
+                    chunk_manager.set_target(coord, lod);
+                    chunk_manager.set_state(coord, ChunkState::Requested, &mut chunk_events);
                 }
-                else{
-                    chunks_to_create.push(cur_chunk_pos);
+                Some(_) => {}
+                None => {
+                    let entity = chunk_manager.assign(coord, lod, &mut chunk_events).expect("null chunk error");
+                    if let Ok((mut transform, material)) = chunk_query.get_mut(entity) {
+                        transform.translation = chunk_world_position(coord, geo_origin.chunk_size);
+
+                        //put the chunk way down, perhaps below sea level to hide it until we get the chunk information
+                        transform.translation.y = -10000.;
+
+                        //a recycled entity still carries the overlay of the chunk it showed before, switch it off
+                        //until this chunk's own arrives. The texture asset is kept for reuse
+                        if let Some(material) = materials.get_mut(material) {
+                            material.extension.use_overlay = 0.;
+                        }
+                    }
                 }
             }
         }
-
-        //loop through all chunks and remove those who have the remove_flag set
-        let mut i = CREATED_CHUNKS.len();
-        while i > 0 {
-            i -= 1;
-            // Check condition and remove if necessary
-            if CREATED_CHUNKS[i].remove_flag == true {
-                // println!("Destroying chunk at {}", get_chunk_space_position(CREATED_CHUNKS[i].position));
-                let null_chunk = Chunk{
-                    entity: CREATED_CHUNKS[i].entity,
-                    position: CREATED_CHUNKS[i].position,
-                    remove_flag: CREATED_CHUNKS[i].remove_flag,
-                };
-                CREATED_CHUNKS.remove(i);
-                NULL_CHUNKS.push(null_chunk);
-            }
-        }
-
-        let mut entity_transform_hashmap: HashMap<Entity, Chunk> = HashMap::new();
-
-        //go through the chunks we need to create and use it from null chunks
-        for i in 0..chunks_to_create.len() {
-            // println!("Creating new chunk at {}", chunks_to_create[i]);
-            //pop null chunk
-            let chunk = NULL_CHUNKS.pop();
-            let mut chunk_exp = chunk.expect("null chunk error");
-
-            let x = chunks_to_create[i].x as i32;
-            let y = chunks_to_create[i].z as i32;
-            
-            let key = format!("{}_{}", x, y);
-            let mkey = key.clone();
-
-            if !CHUNK_THREAD.contains(&key) {
-                CHUNK_THREAD.insert(key);
-
-                let entity = commands.spawn_empty().id();
-                let task = thread_pool.spawn(async move{
-                    let mesh = fetch_terrain_data(x, y);
-                    return (entity, mesh, mkey);
-                });
-
-                commands.entity(entity).insert(GenMesh(task));
-            }
-
-            //update chunk position value
-            //update remove flag
-            chunk_exp.position = chunks_to_create[i];
-            chunk_exp.remove_flag = false;
-
-            entity_transform_hashmap.insert(chunk_exp.entity, chunk_exp);
-
-            //save new chunk
-            CREATED_CHUNKS.push(chunk_exp);
-        }
-
-        for (entity, mut transform) in chunk_query.iter_mut() {
-            if !entity_transform_hashmap.contains_key(entity.borrow()) {
-                continue;
-            }
-            let chunk_data: Option<&Chunk> = entity_transform_hashmap.get(entity.borrow());
-            transform.translation = get_world_space_position(chunk_data.expect("this shouldn't happen! no chunk data found!").position);
-
-            //put the chunk way down, perhaps below sea level to hide it until we get the chunk information
-            transform.translation.y = -10000.;
-        }
     }
+}
+//debug view of the chunk bounds used for frustum culling, toggled with B
+//green boxes are drawn this frame, red ones were culled
+#[derive(Resource, Default)]
+pub struct ChunkBoundsDebug(pub bool);
+
+pub fn toggle_chunk_bounds(keys: Res<ButtonInput<KeyCode>>, mut debug: ResMut<ChunkBoundsDebug>){
+    if keys.just_pressed(KeyCode::KeyB) {
+        debug.0 = !debug.0;
+    }
+}
 
+pub fn draw_chunk_bounds(
+    debug: Res<ChunkBoundsDebug>,
+    chunk_manager: Res<ChunkManager>,
+    chunks: Query<(&Aabb, &GlobalTransform, &ViewVisibility), With<ChunkComponent>>,
+    mut gizmos: Gizmos,
+){
+    if !debug.0 {
+        return;
+    }
+    for chunk in chunk_manager.chunks().filter(|chunk| chunk.state == ChunkState::Ready) {
+        let Ok((bounds, transform, visibility)) = chunks.get(chunk.entity) else {
+            continue;
+        };
+        let color = if visibility.get() { Color::GREEN } else { Color::RED };
+        let centre = transform.transform_point(Vec3::from(bounds.center));
+        gizmos.cuboid(Transform::from_translation(centre).with_scale(Vec3::from(bounds.half_extents) * 2.), color);
+    }
 }
+
+//the sun itself is turned by the sim clock, see sky.rs
 pub fn update_sky_box(
-    camera_query: Query<&Transform, (With<Player>, Without<SkyBoxComponent>, Without<Sun>)>, 
-    mut skybox: Query<&mut Transform, (With<SkyBoxComponent>, Without<Sun>)>,
-    mut sun: Query<&mut Transform, With<Sun>>,
+    camera_query: Query<&Transform, (With<Player>, Without<SkyBoxComponent>)>, 
+    mut skybox: Query<&mut Transform, With<SkyBoxComponent>>,
     ){
 
     if camera_query.is_empty() {
@@ -694,8 +853,5 @@ pub fn update_sky_box(
 
     let camera = camera_query.single();
     let mut skybox: Mut<'_, Transform> = skybox.single_mut();
-    // let mut sunt = sun.single_mut();
-    // let eu = sunt.rotation.to_euler(EulerRot::XYZ);
-    // sunt.rotation = Quat::from_euler(EulerRot::XYZ, eu.0 + 0.001, 0.5, eu.2);
     skybox.translation = camera.translation;
 }
@@ -0,0 +1,334 @@
+//Day and night
+//A simulation clock runs alongside the flight, from the real time or a date and time set in the .env
+//file, and can be sped up. The sun's azimuth and elevation for the plane's position at that time drive
+//the Sun light's direction, strength and colour, the fog and sky colours and the ambient light, so the
+//sun rises and sets where it really would and nights are dark.
+//Solar position from the Astronomical Almanac's low precision formulas, good to about 0.01 degrees
+//https://aa.usno.navy.mil/faq/sun_approx
+
+use bevy::prelude::*;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::geo::{GeoOrigin, GeoPoint};
+use crate::player::Player;
+use crate::scene::{SkyBoxComponent, Sun};
+use crate::terrain::settings::env_or;
+use crate::ui::PauseState;
+
+const SECONDS_PER_DAY: f64 = 86400.;
+//julian date of the unix epoch and of J2000
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+const J2000_JD: f64 = 2_451_545.;
+
+//sun illuminance with the sun well up, and ambient brightness by day and by night
+const DAY_ILLUMINANCE: f32 = 10000.;
+const DAY_AMBIENT: f32 = 80.;
+const NIGHT_AMBIENT: f32 = 6.;
+//fastest the clock can be sped up to
+const MAX_SPEED: f64 = 4096.;
+
+pub struct SkyPlugin;
+
+impl Plugin for SkyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimClock>()
+            .add_systems(Update, (adjust_sim_clock, advance_sim_clock, update_sun).chain());
+    }
+}
+
+//simulated time as seconds since 1970-01-01 UTC, set with SIM_DATE (YYYY-MM-DD) and SIM_TIME (HH:MM
+//UTC), now by default. SIM_SPEED sets how many simulated seconds pass per real one
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SimClock {
+    pub time: f64,
+    pub speed: f64,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0., |d| d.as_secs_f64());
+        let mut days = (now / SECONDS_PER_DAY).floor();
+        let mut seconds = now - days * SECONDS_PER_DAY;
+
+        if let Ok(date) = env::var("SIM_DATE") {
+            match parse_date(&date) {
+                Some(d) => days = d as f64,
+                None => println!("could not parse SIM_DATE {date}, expected YYYY-MM-DD"),
+            }
+        }
+        if let Ok(time) = env::var("SIM_TIME") {
+            match parse_time(&time) {
+                Some(t) => seconds = t,
+                None => println!("could not parse SIM_TIME {time}, expected HH:MM in UTC"),
+            }
+        }
+        Self {
+            time: days * SECONDS_PER_DAY + seconds,
+            speed: env_or("SIM_SPEED", 1f64).clamp(0., MAX_SPEED),
+        }
+    }
+}
+
+impl SimClock {
+    //date and time of day in UTC, for the HUD
+    pub fn label(&self) -> String {
+        let days = (self.time / SECONDS_PER_DAY).floor();
+        let (year, month, day) = civil_from_days(days as i64);
+        let minutes = ((self.time - days * SECONDS_PER_DAY) / 60.) as u32;
+        format!("{year:04}-{month:02}-{day:02} {:02}:{:02} UTC", minutes / 60, minutes % 60)
+    }
+}
+
+//"YYYY-MM-DD" to days since 1970-01-01
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.trim().split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then(|| days_from_civil(year, month, day))
+}
+
+//"HH:MM" or "HH:MM:SS" to seconds into the day
+fn parse_time(time: &str) -> Option<f64> {
+    let parts: Vec<f64> = time.trim().split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [h, m] => (h, m, 0.),
+        [h, m, s] => (h, m, s),
+        _ => return None,
+    };
+    (hours < 24. && minutes < 60. && seconds < 60.).then_some(hours * 3600. + minutes * 60. + seconds)
+}
+
+//days since 1970-01-01 of a gregorian date
+//http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+//azimuth clockwise from true north and elevation above the horizon of the sun, in degrees
+pub fn solar_position(unix_time: f64, point: GeoPoint) -> (f64, f64) {
+    let n = unix_time / SECONDS_PER_DAY + UNIX_EPOCH_JD - J2000_JD;
+    //mean longitude and mean anomaly, then the ecliptic longitude and obliquity
+    let mean_longitude = (280.460 + 0.985_647_4 * n).rem_euclid(360.);
+    let anomaly = (357.528 + 0.985_600_3 * n).rem_euclid(360.).to_radians();
+    let longitude = (mean_longitude + 1.915 * anomaly.sin() + 0.020 * (2. * anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    let right_ascension = (obliquity.cos() * longitude.sin()).atan2(longitude.cos());
+    let declination = (obliquity.sin() * longitude.sin()).asin();
+    //sidereal time at greenwich to the local hour angle
+    let sidereal = (18.697_374_558 + 24.065_709_824_419_08 * n).rem_euclid(24.) * 15.;
+    let hour_angle = (sidereal + point.lon).to_radians() - right_ascension;
+
+    let lat = point.lat.to_radians();
+    let elevation = (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos()).asin();
+    let azimuth = (-hour_angle.sin()).atan2(declination.tan() * lat.cos() - lat.sin() * hour_angle.cos());
+    (azimuth.to_degrees().rem_euclid(360.), elevation.to_degrees())
+}
+
+//[ and ] step the clock an hour, - and = halve and double its speed, 0 stops it
+fn adjust_sim_clock(keys: Res<ButtonInput<KeyCode>>, mut clock: ResMut<SimClock>) {
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        clock.time -= 3600.;
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        clock.time += 3600.;
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        clock.speed *= 0.5;
+    }
+    if keys.just_pressed(KeyCode::Equal) {
+        clock.speed = (clock.speed * 2.).clamp(1., MAX_SPEED);
+    }
+    if keys.just_pressed(KeyCode::Digit0) {
+        clock.speed = 0.;
+    }
+}
+
+//the clock stands still while paused, like the plane
+fn advance_sim_clock(time: Res<Time>, pause: Res<PauseState>, mut clock: ResMut<SimClock>) {
+    if !pause.is_paused {
+        clock.time += time.delta_seconds_f64() * clock.speed;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_sun(
+    clock: Res<SimClock>,
+    geo_origin: Res<GeoOrigin>,
+    player_q: Query<&Transform, (With<Player>, Without<Sun>)>,
+    mut sun_q: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut fog_q: Query<&mut FogSettings>,
+    sky_q: Query<&Handle<StandardMaterial>, With<SkyBoxComponent>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut ambient: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    let Ok(player) = player_q.get_single() else {
+        return;
+    };
+    let (azimuth, elevation) = solar_position(clock.time, geo_origin.world_to_lat_lon(player.translation));
+    let (azimuth, elevation) = (azimuth.to_radians() as f32, elevation as f32);
+
+    //+z is south, so north is -z and east is +x
+    let to_sun = Vec3::new(
+        azimuth.sin() * elevation.to_radians().cos(),
+        elevation.to_radians().sin(),
+        -azimuth.cos() * elevation.to_radians().cos(),
+    );
+    //how much daylight there is, fading out through civil twilight
+    let daylight = smoothstep(-6., 8., elevation);
+    //how close the sun is to the horizon, for the warm colours of sunrise and sunset
+    let low_sun = 1. - smoothstep(0., 20., elevation.abs());
+
+    let sun_color = lerp_color(Color::rgb(1., 0.98, 0.95), Color::rgb(1., 0.55, 0.3), low_sun);
+    if let Ok((mut transform, mut light)) = sun_q.get_single_mut() {
+        //lights shine along their -z, down from the sun
+        transform.rotation = Quat::from_rotation_arc(Vec3::NEG_Z, -to_sun);
+        light.illuminance = DAY_ILLUMINANCE * smoothstep(-1., 10., elevation);
+        light.color = sun_color;
+    }
+
+    let day_sky = Color::rgba_u8(61, 151, 255, 255) * 2.0;
+    let twilight_sky = Color::rgb(0.9, 0.5, 0.35);
+    let night_sky = Color::rgb(0.015, 0.02, 0.05);
+    let sky = lerp_color(lerp_color(night_sky, day_sky, daylight), twilight_sky, low_sun * daylight * 0.6);
+    for mut fog in &mut fog_q {
+        fog.color = sky;
+        fog.directional_light_color = Color::rgba(1.0, 0.95, 0.85, 0.5) * 2.5 * daylight;
+    }
+    if let Some(material) = sky_q.get_single().ok().and_then(|handle| materials.get_mut(handle)) {
+        material.base_color = sky;
+    }
+    clear_color.0 = sky;
+
+    ambient.color = lerp_color(Color::rgb(0.6, 0.7, 1.), Color::WHITE, daylight);
+    ambient.brightness = NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight;
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let (a, b) = (a.as_rgba_f32(), b.as_rgba_f32());
+    Color::rgba(
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+        a[3] + (b[3] - a[3]) * t,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix_time(date: &str, time: &str) -> f64 {
+        parse_date(date).unwrap() as f64 * SECONDS_PER_DAY + parse_time(time).unwrap()
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn civil_days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(civil_from_days(19000), (2022, 1, 8));
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month as i64, day as i64), days);
+        }
+    }
+
+    #[test]
+    fn parses_dates_and_times() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date(" 2024-06-21 "), Some(19895));
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-06"), None);
+        assert_eq!(parse_date("June 21"), None);
+        assert_eq!(parse_time("06:30"), Some(23400.));
+        assert_eq!(parse_time("23:59:30"), Some(86370.));
+        assert_eq!(parse_time("25:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("12"), None);
+    }
+
+    #[test]
+    fn clock_label() {
+        let clock = SimClock {
+            time: unix_time("2024-06-21", "09:05:59"),
+            speed: 1.,
+        };
+        assert_eq!(clock.label(), "2024-06-21 09:05 UTC");
+        assert_eq!(
+            SimClock {
+                time: -60.,
+                speed: 1.
+            }
+            .label(),
+            "1969-12-31 23:59 UTC"
+        );
+    }
+
+    //against almanac values, the approximation is good to a few hundredths of a degree
+    #[test]
+    fn sun_at_midsummer_noon_in_greenwich() {
+        let (azimuth, elevation) =
+            solar_position(unix_time("2024-06-21", "12:00"), GeoPoint::new(51.48, 0.));
+        assert_near(azimuth, 179.1, 0.3);
+        assert_near(elevation, 61.95, 0.1);
+    }
+
+    #[test]
+    fn sun_rising_due_east_at_the_equinox() {
+        let (azimuth, elevation) =
+            solar_position(unix_time("2024-03-20", "06:07"), GeoPoint::new(0., 0.));
+        assert_near(azimuth, 90., 0.5);
+        assert_near(elevation, 0., 0.5);
+        let (_, evening) = solar_position(
+            unix_time("2024-03-20", "20:10"),
+            GeoPoint::new(44.5, -123.3),
+        );
+        assert_near(evening, 45.7, 0.3);
+    }
+
+    #[test]
+    fn sun_below_the_horizon_at_night() {
+        let (_, elevation) =
+            solar_position(unix_time("2024-12-21", "06:00"), GeoPoint::new(40., -74.));
+        assert_near(elevation, -68.4, 0.5);
+        //the midnight sun stays up in the arctic summer, in Longyearbyen
+        let (azimuth, elevation) =
+            solar_position(unix_time("2024-06-21", "00:00"), GeoPoint::new(78.2, 15.6));
+        assert!(elevation > 5. && elevation < 15.);
+        //an hour past local midnight it is east of north
+        assert_near(azimuth, 15.6, 2.);
+    }
+}
@@ -0,0 +1,460 @@
+//Persistent on disk tile cache
+//Tiles are stored as {root}/{layer}/{z}/{x}/{y}.{ext} and survive restarts.
+//The file modification time doubles as the last access time, so the LRU order is kept across sessions too.
+
+use bevy::prelude::*;
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::terrain::fetch::TerrainFetchError;
+use crate::terrain::source::TerrainTileSource;
+use crate::ui::PauseState;
+
+const DEFAULT_CACHE_DIR: &str = "./cache/tiles";
+const DEFAULT_CACHE_MAX_MB: u64 = 1024;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_IEND: [u8; 8] = [b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TileKey {
+    pub layer: String,
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    pub ext: &'static str,
+}
+
+impl TileKey {
+    pub fn png(layer: &str, z: u32, x: u32, y: u32) -> Self {
+        Self {
+            layer: layer.to_string(),
+            z,
+            x,
+            y,
+            ext: "png",
+        }
+    }
+
+    fn relative_path(&self) -> PathBuf {
+        PathBuf::from(format!(
+            "{}/{}/{}/{}.{}",
+            self.layer, self.z, self.x, self.y, self.ext
+        ))
+    }
+}
+
+struct CacheEntry {
+    bytes: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<TileKey, CacheEntry>,
+    total_bytes: u64,
+}
+
+pub struct TileCache {
+    root: PathBuf,
+    max_bytes: u64,
+    index: Mutex<CacheIndex>,
+}
+
+impl TileCache {
+    pub fn open(root: PathBuf, max_bytes: u64) -> Self {
+        let cache = Self {
+            root,
+            max_bytes,
+            index: Mutex::new(CacheIndex::default()),
+        };
+        cache.rebuild_index();
+        cache
+    }
+
+    pub fn from_env() -> Self {
+        let root = env::var("TERRAIN_CACHE_DIR").unwrap_or(DEFAULT_CACHE_DIR.to_string());
+        let max_mb = env::var("TERRAIN_CACHE_MAX_MB")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_MAX_MB);
+        Self::open(PathBuf::from(root), max_mb * 1024 * 1024)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.index.lock().unwrap().total_bytes
+    }
+
+    //scan the cache directory, picking up tiles written by earlier sessions
+    fn rebuild_index(&self) {
+        let mut index = self.index.lock().unwrap();
+        *index = CacheIndex::default();
+        let Ok(layers) = fs::read_dir(&self.root) else {
+            return;
+        };
+        for layer in layers.flatten() {
+            let layer_name = layer.file_name().to_string_lossy().to_string();
+            for (z, z_path) in numbered_entries(&layer.path()) {
+                for (x, x_path) in numbered_entries(&z_path) {
+                    let Ok(files) = fs::read_dir(&x_path) else {
+                        continue;
+                    };
+                    for file in files.flatten() {
+                        let path = file.path();
+                        let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) else {
+                            continue;
+                        };
+                        let Ok(y) = stem.to_string_lossy().parse::<u32>() else {
+                            continue;
+                        };
+                        let Some(ext) = known_extension(&ext.to_string_lossy()) else {
+                            continue;
+                        };
+                        let Ok(meta) = file.metadata() else {
+                            continue;
+                        };
+                        let key = TileKey {
+                            layer: layer_name.clone(),
+                            z,
+                            x,
+                            y,
+                            ext,
+                        };
+                        index.total_bytes += meta.len();
+                        index.entries.insert(
+                            key,
+                            CacheEntry {
+                                bytes: meta.len(),
+                                last_used: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        info!(
+            "tile cache at {} holds {} tiles ({} MB)",
+            self.root.display(),
+            index.entries.len(),
+            index.total_bytes / (1024 * 1024)
+        );
+    }
+
+    pub fn read(&self, key: &TileKey) -> Option<Vec<u8>> {
+        let path = self.root.join(key.relative_path());
+        let bytes = fs::read(&path).ok()?;
+
+        let now = SystemTime::now();
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(now);
+        }
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.entries.get_mut(key) {
+            entry.last_used = now;
+        }
+        Some(bytes)
+    }
+
+    pub fn write(&self, key: &TileKey, bytes: &[u8]) {
+        let path = self.root.join(key.relative_path());
+        if let Some(parent) = path.parent() {
+            if fs::create_dir_all(parent).is_err() {
+                return;
+            }
+        }
+
+        //write next to the final file and rename so a crash never leaves a half written tile behind
+        let tmp_path = path.with_extension(format!("{}.part", key.ext));
+        if fs::write(&tmp_path, bytes).is_err() || fs::rename(&tmp_path, &path).is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return;
+        }
+
+        let mut index = self.index.lock().unwrap();
+        let old = index.entries.insert(
+            key.clone(),
+            CacheEntry {
+                bytes: bytes.len() as u64,
+                last_used: SystemTime::now(),
+            },
+        );
+        if let Some(old) = old {
+            index.total_bytes -= old.bytes;
+        }
+        index.total_bytes += bytes.len() as u64;
+        self.evict(&mut index);
+    }
+
+    pub fn remove(&self, key: &TileKey) {
+        let _ = fs::remove_file(self.root.join(key.relative_path()));
+        let mut index = self.index.lock().unwrap();
+        if let Some(old) = index.entries.remove(key) {
+            index.total_bytes -= old.bytes;
+        }
+    }
+
+    //drop least recently used tiles until we fit under the size cap
+    fn evict(&self, index: &mut CacheIndex) {
+        if index.total_bytes <= self.max_bytes {
+            return;
+        }
+        let mut by_age: Vec<(TileKey, SystemTime)> = index
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.last_used))
+            .collect();
+        by_age.sort_by_key(|(_, last_used)| *last_used);
+
+        for (key, _) in by_age {
+            if index.total_bytes <= self.max_bytes {
+                break;
+            }
+            let _ = fs::remove_file(self.root.join(key.relative_path()));
+            if let Some(old) = index.entries.remove(&key) {
+                index.total_bytes -= old.bytes;
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut index = self.index.lock().unwrap();
+        if self.root.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.root) {
+                println!("failed to clear tile cache: {e}");
+            }
+        }
+        *index = CacheIndex::default();
+    }
+
+    //cached png tile, decoded. Truncated or corrupt files are thrown away and reported as a miss
+    pub fn load_image(&self, key: &TileKey) -> Option<DynamicImage> {
+        let bytes = self.read(key)?;
+        if is_complete_png(&bytes) {
+            if let Ok(img) = image::load_from_memory_with_format(&bytes, ImageFormat::Png) {
+                return Some(img);
+            }
+        }
+        println!("discarding corrupt cached tile {:?}", key.relative_path());
+        self.remove(key);
+        None
+    }
+
+    pub fn store_image(&self, key: &TileKey, img: &DynamicImage) {
+        let mut bytes = Vec::new();
+        if img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).is_ok() {
+            self.write(key, &bytes);
+        }
+    }
+}
+
+fn numbered_entries(dir: &Path) -> Vec<(u32, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let n = e.file_name().to_string_lossy().parse::<u32>().ok()?;
+            Some((n, e.path()))
+        })
+        .collect()
+}
+
+fn known_extension(ext: &str) -> Option<&'static str> {
+    match ext {
+        "png" => Some("png"),
+        "mvt" => Some("mvt"),
+        _ => None,
+    }
+}
+
+//cache layer of a source. Sources on a tile server get a short hash of their url template in it, so
+//pointing one at another server doesn't serve the old server's tiles
+pub fn source_layer(kind: &str, name: &str, url_template: Option<&str>) -> String {
+    match url_template {
+        Some(template) => format!("{kind}-{name}-{:08x}", fnv1a(template.as_bytes()) as u32),
+        None => format!("{kind}-{name}"),
+    }
+}
+
+//64 bit FNV-1a, unlike std's hasher it stays the same between builds so layer names are stable
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+//a png is only complete if it starts with the signature and ends with the IEND chunk
+pub fn is_complete_png(bytes: &[u8]) -> bool {
+    bytes.len() > PNG_SIGNATURE.len() + PNG_IEND.len()
+        && bytes.starts_with(&PNG_SIGNATURE)
+        && bytes.ends_with(&PNG_IEND)
+}
+
+//wraps a tile source and serves repeat requests from the disk cache
+pub struct CachedTileSource {
+    pub inner: Arc<dyn TerrainTileSource>,
+    pub cache: Arc<TileCache>,
+    layer: String,
+}
+
+impl CachedTileSource {
+    pub fn new(inner: Arc<dyn TerrainTileSource>, cache: Arc<TileCache>) -> Self {
+        let layer = source_layer("terrain", inner.name(), inner.url_template());
+        Self::with_layer(inner, cache, layer)
+    }
+
+    //sources of other kinds of tiles keep them in their own layer
+    pub fn with_layer(inner: Arc<dyn TerrainTileSource>, cache: Arc<TileCache>, layer: String) -> Self {
+        Self {
+            inner,
+            cache,
+            layer,
+        }
+    }
+}
+
+impl TerrainTileSource for CachedTileSource {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    //only tiles that decoded fine are stored, failures are never cached
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        let key = TileKey::png(&self.layer, z, x, y);
+        if let Some(img) = self.cache.load_image(&key) {
+            return Ok(img);
+        }
+        let img = self.inner.fetch_tile(z, x, y)?;
+        self.cache.store_image(&key, &img);
+        Ok(img)
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct TerrainTileCache(pub Arc<TileCache>);
+
+impl Default for TerrainTileCache {
+    fn default() -> Self {
+        TerrainTileCache(Arc::new(TileCache::from_env()))
+    }
+}
+
+//press C while paused to wipe the tile cache
+pub fn clear_tile_cache(
+    keys: Res<ButtonInput<KeyCode>>,
+    pause: Res<PauseState>,
+    cache: Res<TerrainTileCache>,
+) {
+    if pause.is_paused && keys.just_pressed(KeyCode::KeyC) {
+        let freed_mb = cache.0.total_bytes() / (1024 * 1024);
+        cache.0.clear();
+        println!("cleared tile cache at {} ({freed_mb} MB)", cache.0.root().display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use image::RgbImage;
+    use std::thread;
+    use std::time::Duration;
+
+    //empty cache directory of its own for each test, gone when the TempDir is dropped
+    fn temp_cache(name: &str, max_bytes: u64) -> (TempDir, TileCache) {
+        let dir = TempDir::new(&format!("tile-cache-{name}"));
+        let cache = TileCache::open(dir.join("cache"), max_bytes);
+        (dir, cache)
+    }
+
+    fn key(y: u32) -> TileKey {
+        TileKey {
+            layer: "test".to_string(),
+            z: 1,
+            x: 0,
+            y,
+            ext: "png",
+        }
+    }
+
+    fn png_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn evicts_least_recently_used_tiles_over_the_cap() {
+        let (_dir, cache) = temp_cache("lru", 250);
+        cache.write(&key(0), &[0; 100]);
+        thread::sleep(Duration::from_millis(20));
+        cache.write(&key(1), &[1; 100]);
+        thread::sleep(Duration::from_millis(20));
+        //reading a tile makes it the most recently used
+        assert!(cache.read(&key(0)).is_some());
+        thread::sleep(Duration::from_millis(20));
+        cache.write(&key(2), &[2; 100]);
+
+        assert_eq!(cache.total_bytes(), 200);
+        assert!(cache.read(&key(0)).is_some());
+        assert!(cache.read(&key(1)).is_none());
+        assert!(cache.read(&key(2)).is_some());
+    }
+
+    #[test]
+    fn index_survives_reopening() {
+        let (_dir, cache) = temp_cache("reopen", 1000);
+        cache.write(&key(0), &[0; 100]);
+        cache.write(&key(1), &[1; 50]);
+
+        let reopened = TileCache::open(cache.root().to_path_buf(), 1000);
+        assert_eq!(reopened.total_bytes(), 150);
+        assert_eq!(reopened.read(&key(1)), Some(vec![1; 50]));
+    }
+
+    #[test]
+    fn png_completeness() {
+        let png = png_bytes();
+        assert!(is_complete_png(&png));
+        assert!(!is_complete_png(&png[..png.len() - 1]));
+        assert!(!is_complete_png(&png[1..]));
+        assert!(!is_complete_png(b"<html>rate limited</html>"));
+        assert!(!is_complete_png(&[]));
+    }
+
+    #[test]
+    fn truncated_cached_png_is_a_miss_and_removed() {
+        let (_dir, cache) = temp_cache("truncated", 1000);
+        let key = TileKey::png("test", 1, 0, 0);
+        let png = png_bytes();
+        cache.write(&key, &png[..png.len() / 2]);
+
+        assert!(cache.load_image(&key).is_none());
+        assert!(cache.read(&key).is_none());
+        assert_eq!(cache.total_bytes(), 0);
+
+        cache.write(&key, &png);
+        assert!(cache.load_image(&key).is_some());
+    }
+
+    #[test]
+    fn layers_differ_by_url_template() {
+        let a = source_layer("terrain", "nextzen", Some("https://a.example/{z}/{x}/{y}.png?api_key={api_key}"));
+        let b = source_layer("terrain", "nextzen", Some("https://b.example/{z}/{x}/{y}.png?api_key={api_key}"));
+        assert_ne!(a, b);
+        assert_eq!(a, source_layer("terrain", "nextzen", Some("https://a.example/{z}/{x}/{y}.png?api_key={api_key}")));
+        assert!(a.starts_with("terrain-nextzen-"));
+        assert_eq!(source_layer("terrain", "local", None), "terrain-local");
+    }
+}
@@ -0,0 +1,569 @@
+//Chunk bookkeeping
+//Every chunk entity is either sitting in the pool or assigned to a chunk coordinate.
+//Assigned chunks move through Requested -> Loading -> Ready, and go Evicting -> Pooled when they leave the view.
+//A ChunkStateChanged event is sent on every transition so other plugins can react to chunk loads.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::VecDeque;
+
+use crate::terrain::fetch::{TerrainFetchError, DOWNLOADER};
+use crate::terrain::heightfield::{HeightSampler, Heightfield};
+use crate::terrain::overlay::LandUseMap;
+use crate::water::WaterMask;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChunkState {
+    //unused entity waiting in the pool
+    Pooled,
+    //assigned to a coordinate, waiting in the load queue for a fetch to start
+    Requested,
+    //fetch running, a Ready chunk changing lod keeps showing its old mesh while in this state
+    Loading,
+    //mesh applied
+    Ready,
+    //left the view, about to go back to the pool
+    Evicting,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkRecord {
+    pub entity: Entity,
+    pub coord: IVec2,
+    pub lod: u32,
+    pub state: ChunkState,
+    //settings generation the chunk was last requested with, see ChunkManager::invalidate
+    pub generation: u32,
+    //time its last fetch failed, the chunk shows a placeholder until a retry works
+    pub failed_at: Option<f32>,
+    //fetches in a row that failed or came back without their imagery or overlay
+    pub failures: u32,
+    //time the chunk is fetched again after a failure
+    pub retry_at: Option<f32>,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ChunkStateChanged {
+    pub entity: Entity,
+    pub coord: IVec2,
+    pub lod: u32,
+    pub previous: ChunkState,
+    pub state: ChunkState,
+}
+
+//a finished chunk mesh along with the heights it was built from
+pub struct ChunkMeshData {
+    pub mesh: Mesh,
+    pub heights: Heightfield,
+    pub res: usize,
+    pub water: WaterMask,
+    //imagery to drape over the chunk, if there is an imagery source
+    pub imagery: Option<Image>,
+    //land use, water and roads drawn over the ground, if there is a vector tile source
+    pub overlay: Option<Image>,
+    //what covers the ground, from the same vector tile as the overlay
+    pub land_use: Option<LandUseMap>,
+    //flat placeholder for a chunk whose tile could not be fetched
+    pub failed: bool,
+    //imagery or overlay fetch that failed but may work later
+    pub retry: Option<TerrainFetchError>,
+}
+
+//heights of a chunk with a mesh applied, so neighbours can stitch their edges to it
+pub struct ChunkHeights {
+    pub heights: Heightfield,
+    pub res: usize,
+    pub mesh: Handle<Mesh>,
+    pub water: WaterMask,
+    pub land_use: Option<LandUseMap>,
+}
+
+//how long a chunk that failed to load waits before it is fetched again
+const FAILED_CHUNK_RETRY_SECS: f32 = 30.;
+
+const NEIGHBOUR_OFFSETS: [IVec2; 8] = [
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+];
+
+#[derive(Resource, Default)]
+pub struct ChunkManager {
+    chunks: HashMap<IVec2, ChunkRecord>,
+    pool: Vec<Entity>,
+    //fetch task entities that are running, by coordinate, lod and generation
+    in_flight: HashMap<(IVec2, u32, u32), Entity>,
+    //bumped when the terrain settings change, chunks from an older generation get fetched again
+    generation: u32,
+    heights: HashMap<IVec2, ChunkHeights>,
+    //finished meshes waiting to be applied to their chunk
+    pub(crate) upload_queue: VecDeque<(IVec2, u32, u32, ChunkMeshData)>,
+}
+
+impl ChunkManager {
+    pub fn get(&self, coord: IVec2) -> Option<&ChunkRecord> {
+        self.chunks.get(&coord)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &ChunkRecord> {
+        self.chunks.values()
+    }
+
+    pub fn heights(&self, coord: IVec2) -> Option<&ChunkHeights> {
+        self.heights.get(&coord)
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn add_to_pool(&mut self, entity: Entity) {
+        self.pool.push(entity);
+    }
+
+    //pooled plus assigned entities
+    pub fn entity_count(&self) -> usize {
+        self.pool.len() + self.chunks.len()
+    }
+
+    //take an unused entity out of the pool for good, so it can be despawned
+    pub fn remove_from_pool(&mut self) -> Option<Entity> {
+        self.pool.pop()
+    }
+
+    pub fn set_state(
+        &mut self,
+        coord: IVec2,
+        state: ChunkState,
+        events: &mut EventWriter<ChunkStateChanged>,
+    ) {
+        let Some(chunk) = self.chunks.get_mut(&coord) else {
+            return;
+        };
+        if chunk.state == state {
+            return;
+        }
+        events.send(ChunkStateChanged {
+            entity: chunk.entity,
+            coord,
+            lod: chunk.lod,
+            previous: chunk.state,
+            state,
+        });
+        chunk.state = state;
+    }
+
+    //the lod and generation a chunk is being fetched at
+    pub fn set_target(&mut self, coord: IVec2, lod: u32) {
+        if let Some(chunk) = self.chunks.get_mut(&coord) {
+            chunk.lod = lod;
+            chunk.generation = self.generation;
+            chunk.failed_at = None;
+            chunk.retry_at = None;
+        }
+    }
+
+    //schedule another fetch of a chunk whose fetch failed, soon with a growing backoff for errors that may
+    //go away, after FAILED_CHUNK_RETRY_SECS otherwise. A placeholder chunk is always fetched again, a chunk
+    //that only missed its imagery or overlay gives up once the retries are used up.
+    //Returns how long the retry waits
+    pub fn mark_failed(&mut self, coord: IVec2, now: f32, error: &TerrainFetchError, placeholder: bool) -> Option<f32> {
+        let chunk = self.chunks.get_mut(&coord)?;
+        chunk.failures += 1;
+        if placeholder {
+            chunk.failed_at = Some(now);
+        }
+        let backoff = error.is_retryable().then(|| DOWNLOADER.retry_delay(chunk.failures)).flatten();
+        let delay = match backoff {
+            Some(delay) => Some(delay.as_secs_f32()),
+            None if placeholder => Some(FAILED_CHUNK_RETRY_SECS),
+            None => None,
+        };
+        chunk.retry_at = delay.map(|delay| now + delay);
+        delay
+    }
+
+    //a fetch came back complete
+    pub fn mark_loaded(&mut self, coord: IVec2) {
+        if let Some(chunk) = self.chunks.get_mut(&coord) {
+            chunk.failures = 0;
+            chunk.retry_at = None;
+        }
+    }
+
+    //true once a failed chunk has waited long enough to be fetched again
+    pub fn should_retry(&self, coord: IVec2, now: f32) -> bool {
+        self.chunks
+            .get(&coord)
+            .and_then(|chunk| chunk.retry_at)
+            .is_some_and(|retry_at| now >= retry_at)
+    }
+
+    //take an entity out of the pool and assign it to a coordinate, None if the pool is empty
+    pub fn assign(
+        &mut self,
+        coord: IVec2,
+        lod: u32,
+        events: &mut EventWriter<ChunkStateChanged>,
+    ) -> Option<Entity> {
+        let entity = self.pool.pop()?;
+        self.chunks.insert(
+            coord,
+            ChunkRecord {
+                entity,
+                coord,
+                lod,
+                state: ChunkState::Pooled,
+                generation: self.generation,
+                failed_at: None,
+                failures: 0,
+                retry_at: None,
+            },
+        );
+        self.set_state(coord, ChunkState::Requested, events);
+        Some(entity)
+    }
+
+    //send a chunk back to the pool
+    pub fn evict(&mut self, coord: IVec2, events: &mut EventWriter<ChunkStateChanged>) {
+        self.set_state(coord, ChunkState::Evicting, events);
+        self.heights.remove(&coord);
+        if let Some(chunk) = self.chunks.remove(&coord) {
+            events.send(ChunkStateChanged {
+                entity: chunk.entity,
+                coord,
+                lod: chunk.lod,
+                previous: ChunkState::Evicting,
+                state: ChunkState::Pooled,
+            });
+            self.pool.push(chunk.entity);
+        }
+    }
+
+    //send every chunk back to the pool
+    pub fn evict_all(&mut self, events: &mut EventWriter<ChunkStateChanged>) {
+        let coords: Vec<IVec2> = self.chunks.keys().copied().collect();
+        for coord in coords {
+            self.evict(coord, events);
+        }
+        self.invalidate();
+    }
+
+    //mark every chunk as out of date, they keep their meshes until the new ones arrive
+    //results of tasks started before this are dropped
+    pub fn invalidate(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    //true if the chunk was requested with older settings and has to be fetched again
+    pub fn is_stale(&self, coord: IVec2) -> bool {
+        self.chunks
+            .get(&coord)
+            .is_some_and(|chunk| chunk.generation != self.generation)
+    }
+
+    //true if a task for this chunk and lod is already running with the current settings
+    pub fn is_in_flight(&self, coord: IVec2, lod: u32) -> bool {
+        self.in_flight.contains_key(&(coord, lod, self.generation))
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn begin_request(&mut self, coord: IVec2, lod: u32, task: Entity) {
+        self.in_flight.insert((coord, lod, self.generation), task);
+    }
+
+    pub fn finish_request(&mut self, coord: IVec2, lod: u32, generation: u32) {
+        self.in_flight.remove(&(coord, lod, generation));
+    }
+
+    //forget tasks whose chunk left the view or wants another lod or generation now, the caller
+    //despawns the returned task entities which cancels them if they haven't started yet
+    pub fn cancel_unneeded_requests(&mut self) -> Vec<Entity> {
+        let chunks = &self.chunks;
+        let mut cancelled = Vec::new();
+        self.in_flight.retain(|&(coord, lod, generation), task| {
+            let needed = chunks
+                .get(&coord)
+                .is_some_and(|chunk| chunk.lod == lod && chunk.generation == generation);
+            if !needed {
+                cancelled.push(*task);
+            }
+            needed
+        });
+        cancelled
+    }
+
+    pub fn insert_heights(&mut self, coord: IVec2, heights: ChunkHeights) {
+        self.heights.insert(coord, heights);
+    }
+
+    pub fn neighbour_offsets() -> [IVec2; 8] {
+        NEIGHBOUR_OFFSETS
+    }
+
+    //sampler for a chunk that can see whichever of its neighbours are loaded
+    pub fn neighbour_sampler<'a>(&'a self, heights: &'a Heightfield, coord: IVec2) -> HeightSampler<'a> {
+        let mut sampler = HeightSampler::new(heights);
+        for offset in NEIGHBOUR_OFFSETS {
+            if let Some(neighbour) = self.heights.get(&(coord + offset)) {
+                sampler = sampler.with_neighbour(offset.x, offset.y, &neighbour.heights);
+            }
+        }
+        sampler
+    }
+}
+
+pub fn log_chunk_state_changes(mut events: EventReader<ChunkStateChanged>) {
+    for event in events.read() {
+        debug!(
+            "chunk {} ({:?}, lod {}) {:?} -> {:?}",
+            event.coord, event.entity, event.lod, event.previous, event.state
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::SystemState;
+
+    //a world holding the manager and its events, with a pool of chunk entities
+    struct Harness {
+        world: World,
+        state: SystemState<(
+            ResMut<'static, ChunkManager>,
+            EventWriter<'static, ChunkStateChanged>,
+        )>,
+    }
+
+    impl Harness {
+        fn new(pool: usize) -> Self {
+            let mut world = World::new();
+            world.init_resource::<Events<ChunkStateChanged>>();
+            let mut manager = ChunkManager::default();
+            for _ in 0..pool {
+                manager.add_to_pool(world.spawn_empty().id());
+            }
+            world.insert_resource(manager);
+            let state = SystemState::new(&mut world);
+            Self { world, state }
+        }
+
+        fn run<R>(
+            &mut self,
+            f: impl FnOnce(&mut ChunkManager, &mut EventWriter<ChunkStateChanged>) -> R,
+        ) -> R {
+            let (mut manager, mut events) = self.state.get_mut(&mut self.world);
+            f(&mut manager, &mut events)
+        }
+
+        fn manager(&self) -> &ChunkManager {
+            self.world.resource::<ChunkManager>()
+        }
+
+        //state changes sent since the last call
+        fn transitions(&mut self) -> Vec<(IVec2, ChunkState, ChunkState)> {
+            self.world
+                .resource_mut::<Events<ChunkStateChanged>>()
+                .drain()
+                .map(|event| (event.coord, event.previous, event.state))
+                .collect()
+        }
+    }
+
+    const A: IVec2 = IVec2::new(2, -1);
+
+    #[test]
+    fn chunk_lifecycle() {
+        let mut h = Harness::new(1);
+        let entity = h
+            .run(|manager, events| manager.assign(A, 1, events))
+            .unwrap();
+        assert_eq!(h.manager().get(A).unwrap().entity, entity);
+        assert_eq!(h.manager().get(A).unwrap().lod, 1);
+        //the pool is empty now
+        assert!(h
+            .run(|manager, events| manager.assign(IVec2::ZERO, 0, events))
+            .is_none());
+
+        h.run(|manager, events| {
+            manager.set_state(A, ChunkState::Loading, events);
+            manager.set_state(A, ChunkState::Ready, events);
+            //no event when nothing changes
+            manager.set_state(A, ChunkState::Ready, events);
+        });
+        assert_eq!(h.manager().get(A).unwrap().state, ChunkState::Ready);
+        h.run(|manager, events| manager.evict(A, events));
+        assert!(h.manager().get(A).is_none());
+        assert_eq!(h.manager().entity_count(), 1);
+
+        assert_eq!(
+            h.transitions(),
+            [
+                (A, ChunkState::Pooled, ChunkState::Requested),
+                (A, ChunkState::Requested, ChunkState::Loading),
+                (A, ChunkState::Loading, ChunkState::Ready),
+                (A, ChunkState::Ready, ChunkState::Evicting),
+                (A, ChunkState::Evicting, ChunkState::Pooled),
+            ]
+        );
+        //the entity went back to the pool and can be used again
+        assert_eq!(
+            h.run(|manager, events| manager.assign(IVec2::ZERO, 0, events)),
+            Some(entity)
+        );
+    }
+
+    #[test]
+    fn invalidated_chunks_are_stale_until_requested_again() {
+        let mut h = Harness::new(2);
+        h.run(|manager, events| {
+            manager.assign(A, 0, events);
+            manager.assign(IVec2::ZERO, 0, events);
+        });
+        assert!(!h.manager().is_stale(A));
+
+        h.run(|manager, _| manager.invalidate());
+        assert!(h.manager().is_stale(A) && h.manager().is_stale(IVec2::ZERO));
+        h.run(|manager, _| manager.set_target(A, 0));
+        assert!(!h.manager().is_stale(A));
+        assert!(h.manager().is_stale(IVec2::ZERO));
+        //chunks that aren't assigned are never stale
+        assert!(!h.manager().is_stale(IVec2::new(9, 9)));
+
+        //evicting everything starts a new generation too
+        let generation = h.manager().generation();
+        h.run(|manager, events| manager.evict_all(events));
+        assert_ne!(h.manager().generation(), generation);
+        assert_eq!(h.manager().chunks().count(), 0);
+        assert_eq!(h.manager().entity_count(), 2);
+    }
+
+    #[test]
+    fn requests_are_tracked_by_lod_and_generation() {
+        let mut h = Harness::new(1);
+        let task = h.world.spawn_empty().id();
+        h.run(|manager, events| {
+            manager.assign(A, 2, events);
+            manager.begin_request(A, 2, task);
+        });
+        assert!(h.manager().is_in_flight(A, 2));
+        assert!(!h.manager().is_in_flight(A, 1));
+        assert_eq!(h.manager().in_flight_count(), 1);
+
+        //a task from before the settings changed no longer counts, and finishing it uses its own generation
+        let generation = h.manager().generation();
+        h.run(|manager, _| manager.invalidate());
+        assert!(!h.manager().is_in_flight(A, 2));
+        h.run(|manager, _| manager.finish_request(A, 2, generation));
+        assert_eq!(h.manager().in_flight_count(), 0);
+    }
+
+    #[test]
+    fn cancels_requests_that_are_no_longer_wanted() {
+        let mut h = Harness::new(3);
+        let (b, c) = (IVec2::new(0, 1), IVec2::new(5, 5));
+        let tasks: Vec<Entity> = (0..4).map(|_| h.world.spawn_empty().id()).collect();
+        h.run(|manager, events| {
+            manager.assign(A, 0, events);
+            manager.assign(b, 1, events);
+            manager.assign(c, 0, events);
+            manager.begin_request(A, 0, tasks[0]);
+            manager.begin_request(b, 1, tasks[1]);
+            manager.begin_request(c, 0, tasks[2]);
+            //a chunk that was never assigned, or has left the view
+            manager.begin_request(IVec2::new(-8, 3), 0, tasks[3]);
+        });
+        assert_eq!(
+            h.run(|manager, _| manager.cancel_unneeded_requests()),
+            [tasks[3]]
+        );
+
+        //b now wants a coarser lod, c left the view
+        h.run(|manager, events| {
+            manager.set_target(b, 2);
+            manager.evict(c, events);
+        });
+        let mut cancelled = h.run(|manager, _| manager.cancel_unneeded_requests());
+        cancelled.sort();
+        let mut expected = vec![tasks[1], tasks[2]];
+        expected.sort();
+        assert_eq!(cancelled, expected);
+        assert!(h.manager().is_in_flight(A, 0));
+        assert_eq!(h.manager().in_flight_count(), 1);
+
+        //after invalidating, a task from the older generation goes once its chunk is requested again
+        h.run(|manager, _| manager.invalidate());
+        assert!(h
+            .run(|manager, _| manager.cancel_unneeded_requests())
+            .is_empty());
+        h.run(|manager, _| manager.set_target(A, 0));
+        assert_eq!(
+            h.run(|manager, _| manager.cancel_unneeded_requests()),
+            [tasks[0]]
+        );
+    }
+
+    #[test]
+    fn failed_chunks_wait_before_retrying() {
+        let mut h = Harness::new(2);
+        let b = IVec2::new(0, 1);
+        h.run(|manager, events| {
+            manager.assign(A, 0, events);
+            manager.assign(b, 0, events);
+        });
+        let timeout = TerrainFetchError::Timeout {
+            url: "https://example.com/1/0/0.png".to_string(),
+        };
+
+        //a placeholder is always fetched again, soon for errors that may go away on their own
+        let delay = h
+            .run(|manager, _| manager.mark_failed(A, 10., &timeout, true))
+            .unwrap();
+        let expected = DOWNLOADER
+            .retry_delay(1)
+            .map_or(FAILED_CHUNK_RETRY_SECS, |d| d.as_secs_f32());
+        assert_eq!(delay, expected);
+        assert_eq!(h.manager().get(A).unwrap().failed_at, Some(10.));
+        assert!(!h.manager().should_retry(A, 10. + delay * 0.5));
+        assert!(h.manager().should_retry(A, 10. + delay));
+
+        //errors that won't go away wait the long delay
+        let delay =
+            h.run(|manager, _| manager.mark_failed(b, 0., &TerrainFetchError::NoData, true));
+        assert_eq!(delay, Some(FAILED_CHUNK_RETRY_SECS));
+        //a chunk that only missed its overlay doesn't retry errors like that at all
+        h.run(|manager, _| manager.mark_loaded(b));
+        assert_eq!(
+            h.run(|manager, _| manager.mark_failed(b, 0., &TerrainFetchError::NoData, false)),
+            None
+        );
+        assert!(h
+            .manager()
+            .get(b)
+            .unwrap()
+            .failed_at
+            .is_some_and(|t| t == 0.));
+        assert!(!h.manager().should_retry(b, 1000.));
+
+        //requesting the chunk again clears the failure
+        h.run(|manager, _| {
+            manager.set_target(A, 0);
+            manager.mark_loaded(A);
+        });
+        let chunk = h.manager().get(A).unwrap();
+        assert_eq!(
+            (chunk.failed_at, chunk.retry_at, chunk.failures),
+            (None, None, 0)
+        );
+        assert!(!h.manager().should_retry(A, 1000.));
+    }
+}
@@ -0,0 +1,396 @@
+//Local DEM files
+//SRTM .hgt and single band GeoTIFF elevation files, loaded once at startup and resampled onto the
+//slippy map tile grid on demand. Tiles come out terrarium encoded like every other source, so the
+//chunk mesh path is the same as for downloaded tiles and no network is needed.
+//https://www.usgs.gov/centers/eros/science/usgs-eros-archive-digital-elevation-shuttle-radar-topography-mission-srtm-1
+//http://docs.opengeospatial.org/is/19-008r4/19-008r4.html
+
+use bevy::math::DVec2;
+use image::{DynamicImage, RgbImage};
+use std::env;
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+
+use crate::geo::{tile_to_lat_lon, GeoPoint};
+use crate::terrain::fetch::TerrainFetchError;
+use crate::terrain::source::{encode_terrarium, TerrainTileSource, TILE_SIZE};
+
+const DEFAULT_DEM_PATH: &str = "./dem";
+
+//srtm marks missing samples with this
+const HGT_VOID: i16 = -32768;
+
+//WGS84 / Web Mercator sphere radius in metres
+const MERCATOR_RADIUS: f64 = 6_378_137.0;
+
+//geotiff keys, see the spec above
+const GT_MODEL_TYPE_KEY: u16 = 1024;
+const GT_RASTER_TYPE_KEY: u16 = 1025;
+const PROJECTED_CS_TYPE_KEY: u16 = 3072;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+const EPSG_WEB_MERCATOR: [u16; 2] = [3857, 3785];
+
+//coordinate system a DEM's grid is laid out in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DemCrs {
+    //x is longitude and y latitude in degrees
+    Geographic,
+    //EPSG:3857, metres
+    WebMercator,
+}
+
+impl DemCrs {
+    fn project(&self, point: GeoPoint) -> DVec2 {
+        match self {
+            DemCrs::Geographic => DVec2::new(point.lon, point.lat),
+            DemCrs::WebMercator => DVec2::new(
+                MERCATOR_RADIUS * point.lon.to_radians(),
+                MERCATOR_RADIUS * (PI / 4.0 + point.lat.to_radians() / 2.0).tan().ln(),
+            ),
+        }
+    }
+}
+
+//a grid of elevations in metres, NaN where the file has no data
+pub struct Dem {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+    pub crs: DemCrs,
+    //position of the centre of the top left sample
+    pub origin: DVec2,
+    //distance between samples, y grows downwards (southwards)
+    pub step: DVec2,
+}
+
+impl Dem {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        match ext.as_str() {
+            "hgt" => Self::load_hgt(path),
+            "tif" | "tiff" => Self::load_geotiff(path),
+            _ => Err(format!("unsupported DEM file type .{ext}")),
+        }
+    }
+
+    //srtm tiles are named after their south west corner, e.g. N44W124.hgt, and hold a square grid of
+    //big endian i16 samples covering one degree with the edge samples shared with the next tile
+    pub fn load_hgt(path: &Path) -> Result<Self, String> {
+        let name = path.file_stem().and_then(|n| n.to_str()).unwrap_or("");
+        let (lat, lon) = parse_hgt_name(name).ok_or(format!("can't read the corner from the file name {name}"))?;
+
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+        if size < 2 || size * size * 2 != bytes.len() {
+            return Err(format!("{} bytes is not a square grid of samples", bytes.len()));
+        }
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| match i16::from_be_bytes([b[0], b[1]]) {
+                HGT_VOID => f32::NAN,
+                h => h as f32,
+            })
+            .collect();
+
+        let step = 1.0 / (size - 1) as f64;
+        Ok(Self {
+            width: size,
+            height: size,
+            data,
+            crs: DemCrs::Geographic,
+            origin: DVec2::new(lon, lat + 1.0),
+            step: DVec2::new(step, step),
+        })
+    }
+
+    //only the first band is used, the grid is placed with the pixel scale and tie point tags
+    pub fn load_geotiff(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+        let (width, height) = decoder.dimensions().map_err(|e| e.to_string())?;
+
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).map_err(|_| "no pixel scale tag".to_string())?;
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).map_err(|_| "no tie point tag".to_string())?;
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            return Err("malformed georeferencing tags".to_string());
+        }
+        let keys = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap_or_default();
+        let crs = match (geo_key(&keys, GT_MODEL_TYPE_KEY), geo_key(&keys, PROJECTED_CS_TYPE_KEY)) {
+            (Some(MODEL_TYPE_PROJECTED), Some(code)) if EPSG_WEB_MERCATOR.contains(&code) => DemCrs::WebMercator,
+            (Some(MODEL_TYPE_PROJECTED), code) => {
+                return Err(format!("projection {code:?} is not supported, reproject to EPSG:4326 or EPSG:3857"))
+            }
+            (Some(MODEL_TYPE_GEOGRAPHIC) | None, _) => DemCrs::Geographic,
+            (Some(other), _) => return Err(format!("unknown model type {other}")),
+        };
+        let nodata = decoder
+            .get_tag_ascii_string(Tag::GdalNodata)
+            .ok()
+            .and_then(|s| s.trim().trim_end_matches('\0').parse::<f64>().ok());
+
+        let data: Vec<f64> = match decoder.read_image().map_err(|e| e.to_string())? {
+            DecodingResult::U8(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::U16(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::U32(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::I8(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::I16(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::I32(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::F32(v) => v.into_iter().map(f64::from).collect(),
+            DecodingResult::F64(v) => v,
+            DecodingResult::U64(_) | DecodingResult::I64(_) => return Err("64 bit integer samples are not supported".to_string()),
+        };
+        let (width, height) = (width as usize, height as usize);
+        if width < 2 || height < 2 {
+            return Err(format!("{width}x{height} is too small to interpolate"));
+        }
+        if data.len() != width * height {
+            return Err("only single band files are supported".to_string());
+        }
+        let data = data
+            .into_iter()
+            .map(|h| if Some(h) == nodata || !h.is_finite() { f32::NAN } else { h as f32 })
+            .collect();
+
+        //the tie point ties raster position (i, j) to model position (x, y), by default it refers to
+        //the corner of a pixel rather than its centre
+        let step = DVec2::new(scale[0], scale[1]);
+        let mut origin = DVec2::new(tiepoint[3] - tiepoint[0] * step.x, tiepoint[4] + tiepoint[1] * step.y);
+        if geo_key(&keys, GT_RASTER_TYPE_KEY) != Some(RASTER_PIXEL_IS_POINT) {
+            origin += DVec2::new(step.x, -step.y) * 0.5;
+        }
+        Ok(Self { width, height, data, crs, origin, step })
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[y * self.width + x]
+    }
+
+    //fractional sample position of a point, may be outside the grid
+    fn sample_position(&self, point: GeoPoint) -> DVec2 {
+        let p = self.crs.project(point);
+        DVec2::new((p.x - self.origin.x) / self.step.x, (self.origin.y - p.y) / self.step.y)
+    }
+
+    //bilinear height at a point, None outside the grid or where there is no data
+    pub fn sample(&self, point: GeoPoint) -> Option<f32> {
+        let pos = self.sample_position(point);
+        let max = DVec2::new((self.width - 1) as f64, (self.height - 1) as f64);
+        if pos.x < 0.0 || pos.y < 0.0 || pos.x > max.x || pos.y > max.y {
+            return None;
+        }
+        let x0 = (pos.x.floor() as usize).min(self.width - 2);
+        let y0 = (pos.y.floor() as usize).min(self.height - 2);
+        let fx = (pos.x - x0 as f64) as f32;
+        let fy = (pos.y - y0 as f64) as f32;
+
+        //voids are left out and the remaining samples weighted up
+        let mut height = 0.;
+        let mut weight = 0.;
+        for (dx, dy, w) in [(0, 0, (1. - fx) * (1. - fy)), (1, 0, fx * (1. - fy)), (0, 1, (1. - fx) * fy), (1, 1, fx * fy)] {
+            let h = self.get(x0 + dx, y0 + dy);
+            if !h.is_nan() && w > 0. {
+                height += h * w;
+                weight += w;
+            }
+        }
+        (weight > 0.).then(|| height / weight)
+    }
+
+    //whether any of the area between the two corners is covered
+    fn overlaps(&self, north_west: GeoPoint, south_east: GeoPoint) -> bool {
+        let a = self.sample_position(north_west);
+        let b = self.sample_position(south_east);
+        a.x <= (self.width - 1) as f64 && b.x >= 0.0 && a.y <= (self.height - 1) as f64 && b.y >= 0.0
+    }
+}
+
+//N44W124 -> (44, -124)
+fn parse_hgt_name(name: &str) -> Option<(f64, f64)> {
+    let name = name.to_uppercase();
+    let lon_at = name.find(['E', 'W'])?;
+    let (lat, lon) = name.split_at(lon_at);
+    let lat_sign = match lat.chars().next()? {
+        'N' => 1.0,
+        'S' => -1.0,
+        _ => return None,
+    };
+    let lon_sign = if lon.starts_with('E') { 1.0 } else { -1.0 };
+    //srtm file names sometimes carry a suffix like N44W124.SRTMGL1
+    let lon_digits: String = lon[1..].chars().take_while(|c| c.is_ascii_digit()).collect();
+    Some((lat_sign * lat[1..].parse::<f64>().ok()?, lon_sign * lon_digits.parse::<f64>().ok()?))
+}
+
+//value of a key in a geotiff key directory, only keys stored inline are needed here
+fn geo_key(keys: &[u16], key: u16) -> Option<u16> {
+    keys.get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] == key && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+//every DEM file found at TERRAIN_DEM_PATH, a single file or a directory of them
+pub struct DemTileSource {
+    pub dems: Vec<Dem>,
+}
+
+impl DemTileSource {
+    pub fn from_env() -> Self {
+        let path = PathBuf::from(env::var("TERRAIN_DEM_PATH").unwrap_or(DEFAULT_DEM_PATH.to_string()));
+        let files = if path.is_dir() {
+            let mut files: Vec<PathBuf> = fs::read_dir(&path)
+                .map(|dir| dir.filter_map(|entry| entry.ok().map(|e| e.path())).collect())
+                .unwrap_or_default();
+            files.sort();
+            files
+        } else {
+            vec![path.clone()]
+        };
+
+        let mut dems = Vec::new();
+        for file in files {
+            let ext = file.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+            if !["hgt", "tif", "tiff"].contains(&ext.as_str()) {
+                continue;
+            }
+            match Dem::load(&file) {
+                Ok(dem) => {
+                    println!("loaded DEM {} ({}x{})", file.display(), dem.width, dem.height);
+                    dems.push(dem);
+                }
+                Err(e) => println!("failed to read DEM {}: {e}", file.display()),
+            }
+        }
+        if dems.is_empty() {
+            println!("no DEM files found at {}", path.display());
+        }
+        Self { dems }
+    }
+}
+
+impl TerrainTileSource for DemTileSource {
+    fn name(&self) -> &str {
+        "dem"
+    }
+
+    //already on disk
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    //tiles outside every DEM have no data, the parts of a tile a DEM doesn't cover sit at sea level
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        let north_west = tile_to_lat_lon(DVec2::new(x as f64, y as f64), z);
+        let south_east = tile_to_lat_lon(DVec2::new(x as f64 + 1.0, y as f64 + 1.0), z);
+        let dems: Vec<&Dem> = self.dems.iter().filter(|dem| dem.overlaps(north_west, south_east)).collect();
+        if dems.is_empty() {
+            return Err(TerrainFetchError::NoData);
+        }
+
+        //latitude only changes per row and longitude per column
+        let pixel_centre = |p: u32| (p as f64 + 0.5) / TILE_SIZE as f64;
+        let lons: Vec<f64> = (0..TILE_SIZE)
+            .map(|px| tile_to_lat_lon(DVec2::new(x as f64 + pixel_centre(px), y as f64), z).lon)
+            .collect();
+        let lats: Vec<f64> = (0..TILE_SIZE)
+            .map(|py| tile_to_lat_lon(DVec2::new(x as f64, y as f64 + pixel_centre(py)), z).lat)
+            .collect();
+
+        let img = RgbImage::from_fn(TILE_SIZE, TILE_SIZE, |px, py| {
+            let point = GeoPoint { lat: lats[py as usize], lon: lons[px as usize] };
+            let height = dems.iter().find_map(|dem| dem.sample(point)).unwrap_or(0.);
+            encode_terrarium(height)
+        });
+        Ok(DynamicImage::ImageRgb8(img))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::lat_lon_to_tile;
+    use crate::test_util::TempDir;
+
+    //3x3 samples over the degree north east of 44N 124W, heights rising 100 m per sample eastwards and
+    //1000 m per sample southwards, with the middle sample missing
+    fn write_hgt(dir: &TempDir) -> PathBuf {
+        let path = dir.join("N44W124.hgt");
+        let bytes: Vec<u8> = (0..9)
+            .flat_map(|i| {
+                let h = if i == 4 { HGT_VOID } else { (i % 3) as i16 * 100 + (i / 3) as i16 * 1000 };
+                h.to_be_bytes()
+            })
+            .collect();
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn terrarium_height(img: &DynamicImage, px: u32, py: u32) -> f32 {
+        let p = img.to_rgb8().get_pixel(px, py).0;
+        p[0] as f32 * 256. + p[1] as f32 + p[2] as f32 / 256. - 32768.
+    }
+
+    #[test]
+    fn hgt_names() {
+        assert_eq!(parse_hgt_name("N44W124"), Some((44., -124.)));
+        assert_eq!(parse_hgt_name("s12e005"), Some((-12., 5.)));
+        assert_eq!(parse_hgt_name("N44W124.SRTMGL1"), Some((44., -124.)));
+        assert_eq!(parse_hgt_name("X44W124"), None);
+        assert_eq!(parse_hgt_name("N44"), None);
+        assert_eq!(parse_hgt_name("dem"), None);
+    }
+
+    #[test]
+    fn samples_hgt_grid() {
+        let dir = TempDir::new("dem-sample");
+        let path = write_hgt(&dir);
+        let dem = Dem::load(&path).unwrap();
+        assert_eq!((dem.width, dem.height), (3, 3));
+
+        //north west and south east corners, the first row is the northern edge
+        assert_eq!(dem.sample(GeoPoint::new(45., -124.)), Some(0.));
+        assert_eq!(dem.sample(GeoPoint::new(44., -123.)), Some(2200.));
+        //halfway along the northern edge
+        let north = dem.sample(GeoPoint::new(45., -123.75)).unwrap();
+        assert!((north - 50.).abs() < 0.01, "{north}");
+        //the void in the middle is left out rather than read as -32768, the other samples weighted up
+        let near_middle = dem.sample(GeoPoint::new(44.6, -123.6)).unwrap();
+        assert!((near_middle - (0.16 * 100. + 0.16 * 1000.) / 0.36).abs() < 0.1, "{near_middle}");
+        assert_eq!(dem.sample(GeoPoint::new(44.5, -123.5)), None);
+        assert_eq!(dem.sample(GeoPoint::new(46., -123.5)), None);
+    }
+
+    #[test]
+    fn rejects_non_square_hgt() {
+        let dir = TempDir::new("dem-bad");
+        let path = dir.join("N44W124.hgt");
+        fs::write(&path, [0u8; 10]).unwrap();
+        assert!(Dem::load(&path).is_err());
+    }
+
+    #[test]
+    fn tiles_from_dem() {
+        let dir = TempDir::new("dem-tiles");
+        let path = write_hgt(&dir);
+        let source = DemTileSource { dems: vec![Dem::load(&path).unwrap()] };
+
+        //the tile holding the south east corner of the grid at zoom 10
+        let zoom = 10;
+        let tile = lat_lon_to_tile(GeoPoint::new(44.01, -123.01), zoom);
+        let img = source.fetch_tile(zoom, tile.x as u32, tile.y as u32).unwrap();
+        //where the tile's pixel lands, against the grid sampled directly
+        let (px, py) = ((tile.x.fract() * TILE_SIZE as f64) as u32, (tile.y.fract() * TILE_SIZE as f64) as u32);
+        let centre = DVec2::new(tile.x.floor() + (px as f64 + 0.5) / TILE_SIZE as f64, tile.y.floor() + (py as f64 + 0.5) / TILE_SIZE as f64);
+        let expected = source.dems[0].sample(tile_to_lat_lon(centre, zoom)).unwrap();
+        assert!((terrarium_height(&img, px, py) - expected).abs() < 0.01);
+
+        //far away from the grid there is nothing
+        let outside = lat_lon_to_tile(GeoPoint::new(10., 10.), zoom);
+        assert_eq!(source.fetch_tile(zoom, outside.x as u32, outside.y as u32).err(), Some(TerrainFetchError::NoData));
+    }
+}
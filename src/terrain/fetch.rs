@@ -0,0 +1,179 @@
+//Tile downloads
+//Every http tile request goes through one shared client with timeouts and a cap on how many downloads
+//run at once. Responses are checked before they are decoded, so an error page or a rate limit never
+//ends up in the cache or panics a task. A failed download isn't waited on here, that would hold a task
+//pool thread, the chunk manager fetches the chunk again after retry_delay instead.
+//Urls in errors have their query string cut off, it holds the api key.
+
+use image::DynamicImage;
+use once_cell::sync::Lazy;
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+use reqwest::StatusCode;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::terrain::settings::env_or;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_MAX_DOWNLOADS: usize = 4;
+//first wait before a retry, doubled every attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+const CLIENT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TerrainFetchError {
+    //the source needs a key that isn't set
+    MissingApiKey,
+    //the source has nothing at this tile, not an error worth retrying or reporting
+    NoData,
+    Timeout { url: String },
+    Network { url: String, message: String },
+    Http { url: String, status: u16 },
+    //the server answered with something that isn't a tile, usually an html error page
+    UnexpectedContent { url: String, content_type: String },
+    Decode { what: String, message: String },
+}
+
+impl TerrainFetchError {
+    //timeouts, dropped connections, rate limits and server errors can go away on their own
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TerrainFetchError::Timeout { .. } | TerrainFetchError::Network { .. } => true,
+            TerrainFetchError::Http { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TerrainFetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TerrainFetchError::MissingApiKey => write!(f, "no API key set, read README.md for more details"),
+            TerrainFetchError::NoData => write!(f, "no data for this tile"),
+            TerrainFetchError::Timeout { url } => write!(f, "timed out fetching {url}"),
+            TerrainFetchError::Network { url, message } => write!(f, "failed to fetch {url}: {message}"),
+            TerrainFetchError::Http { url, status } => write!(f, "{url} answered with HTTP {status}"),
+            TerrainFetchError::UnexpectedContent { url, content_type } => write!(f, "{url} sent {content_type} instead of a tile"),
+            TerrainFetchError::Decode { what, message } => write!(f, "failed to decode {what}: {message}"),
+        }
+    }
+}
+
+//a url as it may be printed, without the query string and the api key in it
+pub fn redact_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((path, _)) => format!("{path}?..."),
+        None => url.to_string(),
+    }
+}
+
+impl std::error::Error for TerrainFetchError {}
+
+//counting semaphore, a slot is given back when the permit is dropped
+struct DownloadSlots {
+    free: Mutex<usize>,
+    released: Condvar,
+}
+
+struct DownloadPermit<'a>(&'a DownloadSlots);
+
+impl DownloadSlots {
+    fn acquire(&self) -> DownloadPermit<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.released.wait(free).unwrap();
+        }
+        *free -= 1;
+        DownloadPermit(self)
+    }
+}
+
+impl Drop for DownloadPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.free.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
+
+pub struct TileDownloader {
+    //None if the client could not be built, every download then fails instead of panicking
+    client: Option<Client>,
+    retries: u32,
+    slots: DownloadSlots,
+}
+
+impl TileDownloader {
+    pub fn from_env() -> Self {
+        let timeout = Duration::from_secs(env_or("TERRAIN_FETCH_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS));
+        let client = Client::builder()
+            .cookie_store(true)
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()
+            .map_err(|e| println!("failed to set up the http client: {e}"))
+            .ok();
+        Self {
+            client,
+            retries: env_or("TERRAIN_FETCH_RETRIES", DEFAULT_RETRIES),
+            slots: DownloadSlots {
+                free: Mutex::new(env_or("TERRAIN_MAX_DOWNLOADS", DEFAULT_MAX_DOWNLOADS).max(1)),
+                released: Condvar::new(),
+            },
+        }
+    }
+
+    //wait before fetching again after a retryable error, doubled with every failure in a row. None once
+    //the retries are used up
+    pub fn retry_delay(&self, failures: u32) -> Option<Duration> {
+        (failures >= 1 && failures <= self.retries).then(|| RETRY_BACKOFF * 2u32.pow(failures - 1))
+    }
+
+    //download and decode an image
+    pub fn fetch_image(&self, url: &str) -> Result<DynamicImage, TerrainFetchError> {
+        let bytes = self.fetch_bytes(url)?;
+        if image::guess_format(&bytes).is_err() {
+            return Err(TerrainFetchError::UnexpectedContent { url: redact_url(url), content_type: "unrecognised data".to_string() });
+        }
+        image::load_from_memory(&bytes).map_err(|e| TerrainFetchError::Decode { what: redact_url(url), message: e.to_string() })
+    }
+
+    //download the raw body of a tile, only the transfer holds a slot, decoding happens outside it
+    pub fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, TerrainFetchError> {
+        let Some(client) = &self.client else {
+            return Err(TerrainFetchError::Network { url: redact_url(url), message: "no http client".to_string() });
+        };
+        let network_error = |e: reqwest::Error| {
+            if e.is_timeout() {
+                TerrainFetchError::Timeout { url: redact_url(url) }
+            } else {
+                //reqwest puts the url in its own messages
+                TerrainFetchError::Network { url: redact_url(url), message: e.without_url().to_string() }
+            }
+        };
+
+        let _permit = self.slots.acquire();
+        let resp = client.get(url).header(USER_AGENT, CLIENT_USER_AGENT).send().map_err(network_error)?;
+        let status = resp.status();
+        if status != StatusCode::OK {
+            return Err(TerrainFetchError::Http { url: redact_url(url), status: status.as_u16() });
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        //some tile servers send tiles as octet-stream, anything textual is an error page
+        if content_type.starts_with("text/") || content_type.contains("json") || content_type.contains("xml") {
+            return Err(TerrainFetchError::UnexpectedContent { url: redact_url(url), content_type });
+        }
+        Ok(resp.bytes().map_err(network_error)?.to_vec())
+    }
+}
+
+//shared by every http source so the download cap covers all of them
+pub static DOWNLOADER: Lazy<TileDownloader> = Lazy::new(TileDownloader::from_env);
@@ -0,0 +1,212 @@
+//Decoded chunk heights and neighbour aware sampling
+//Chunk meshes sample their heights through a HeightSampler. Pixels past the edge of the chunk's own
+//tile are read from the neighbouring chunk's heightfield when it is loaded, so both chunks compute
+//exactly the same heights and normals along the edge they share.
+
+use image::{DynamicImage, GenericImageView};
+
+pub struct Heightfield {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl Heightfield {
+    pub fn from_image(img: &DynamicImage, is_nextzen: bool) -> Self {
+        let (width, height) = img.dimensions();
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(get_pixel_height(img, x, y, is_nextzen));
+            }
+        }
+        Self {
+            width: width as usize,
+            height: height as usize,
+            data,
+        }
+    }
+
+    //multiply every height, used to turn metres into world units
+    pub fn scale(&mut self, factor: f32) {
+        for height in self.data.iter_mut() {
+            *height *= factor;
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
+
+pub fn get_pixel_height(height_map: &DynamicImage, x: u32, y: u32, is_nextzen: bool) -> f32 {
+    let (width, height) = height_map.dimensions();
+    let x = x.min(width - 1);
+    let y = y.min(height - 1);
+    let pixel = height_map.get_pixel(x, y);
+
+    if is_nextzen {
+        //terrarium encoding, height in metres = (red * 256 + green + blue / 256) - 32768
+        let r = pixel[0] as f32;
+        let g = pixel[1] as f32;
+        let b = pixel[2] as f32;
+        (r * 256. + g + b / 256.) - 32768.
+    } else {
+        pixel[0] as f32 / 256.0
+    }
+}
+
+//a heightfield plus whichever of its 8 neighbours are loaded
+pub struct HeightSampler<'a> {
+    center: &'a Heightfield,
+    //indexed by (dy + 1) * 3 + (dx + 1)
+    neighbours: [Option<&'a Heightfield>; 9],
+}
+
+impl<'a> HeightSampler<'a> {
+    pub fn new(center: &'a Heightfield) -> Self {
+        Self {
+            center,
+            neighbours: [None; 9],
+        }
+    }
+
+    //dx, dy is the neighbour's offset in chunks, +y is south like tile y
+    pub fn with_neighbour(mut self, dx: i32, dy: i32, neighbour: &'a Heightfield) -> Self {
+        if (dx, dy) != (0, 0) {
+            self.neighbours[((dy + 1) * 3 + (dx + 1)) as usize] = Some(neighbour);
+        }
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.center.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.center.height
+    }
+
+    //height of a pixel, pixels outside the tile come from the neighbour or are clamped if it's missing
+    pub fn pixel(&self, x: i32, y: i32) -> f32 {
+        let w = self.center.width as i32;
+        let h = self.center.height as i32;
+        let dx = if x < 0 { -1 } else if x >= w { 1 } else { 0 };
+        let dy = if y < 0 { -1 } else if y >= h { 1 } else { 0 };
+
+        if (dx, dy) != (0, 0) {
+            if let Some(n) = self.neighbours[((dy + 1) * 3 + (dx + 1)) as usize] {
+                //neighbours at another lod have a different resolution
+                let local_x = (x - dx * w) as f32 * n.width as f32 / w as f32;
+                let local_y = (y - dy * h) as f32 * n.height as f32 / h as f32;
+                return n.get(local_x.max(0.) as usize, local_y.max(0.) as usize);
+            }
+        }
+        self.center.get(x.clamp(0, w - 1) as usize, y.clamp(0, h - 1) as usize)
+    }
+
+    //bilinear sample, pixel centres sit at +0.5 so an edge shared by two tiles lands exactly between them
+    pub fn sample(&self, px: f32, py: f32) -> f32 {
+        let fx = px - 0.5;
+        let fy = py - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let tx = fx - x0;
+        let ty = fy - y0;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.pixel(x0, y0) * (1. - tx) + self.pixel(x0 + 1, y0) * tx;
+        let bottom = self.pixel(x0, y0 + 1) * (1. - tx) + self.pixel(x0 + 1, y0 + 1) * tx;
+        top * (1. - ty) + bottom * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 8;
+
+    //a 4x4 block of chunks cut from one surface, indexed [y][x]
+    fn chunks() -> Vec<Vec<Heightfield>> {
+        let surface = |x: usize, y: usize| {
+            (x * 37 % 11) as f32 * 3.5 + (y * 13 % 7) as f32 * 10. + (x * y) as f32 * 0.25
+        };
+        (0..4)
+            .map(|cy| {
+                (0..4)
+                    .map(|cx| Heightfield {
+                        width: SIZE,
+                        height: SIZE,
+                        data: (0..SIZE * SIZE)
+                            .map(|i| surface(cx * SIZE + i % SIZE, cy * SIZE + i / SIZE))
+                            .collect(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    //the chunk at x, y with every neighbour inside the block
+    fn sampler(chunks: &[Vec<Heightfield>], x: usize, y: usize) -> HeightSampler<'_> {
+        let mut sampler = HeightSampler::new(&chunks[y][x]);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if (0..4).contains(&nx) && (0..4).contains(&ny) {
+                    sampler = sampler.with_neighbour(dx, dy, &chunks[ny as usize][nx as usize]);
+                }
+            }
+        }
+        sampler
+    }
+
+    #[test]
+    fn neighbours_share_their_edges() {
+        let chunks = chunks();
+        let (a, east, south, south_east) = (
+            sampler(&chunks, 1, 1),
+            sampler(&chunks, 2, 1),
+            sampler(&chunks, 1, 2),
+            sampler(&chunks, 2, 2),
+        );
+        let edge = SIZE as f32;
+        for i in 0..=SIZE * 4 {
+            let t = i as f32 / 4.;
+            assert_eq!(a.sample(edge, t), east.sample(0., t), "east edge at {t}");
+            assert_eq!(a.sample(t, edge), south.sample(t, 0.), "south edge at {t}");
+        }
+        //the corner is shared by four chunks
+        let corner = a.sample(edge, edge);
+        assert_eq!(corner, east.sample(0., edge));
+        assert_eq!(corner, south.sample(edge, 0.));
+        assert_eq!(corner, south_east.sample(0., 0.));
+    }
+
+    #[test]
+    fn pixels_past_the_edge_come_from_the_neighbour() {
+        let chunks = chunks();
+        let a = sampler(&chunks, 1, 1);
+        assert_eq!(a.pixel(SIZE as i32, 3), chunks[1][2].get(0, 3));
+        assert_eq!(a.pixel(-1, -1), chunks[0][0].get(SIZE - 1, SIZE - 1));
+        assert_eq!(a.pixel(SIZE as i32, SIZE as i32), chunks[2][2].get(0, 0));
+        //without a neighbour the edge pixel is repeated
+        let alone = HeightSampler::new(&chunks[1][1]);
+        assert_eq!(alone.pixel(SIZE as i32, 3), chunks[1][1].get(SIZE - 1, 3));
+        assert_eq!(alone.pixel(-2, -5), chunks[1][1].get(0, 0));
+    }
+
+    //a neighbour at the next lod has half as many pixels over the same ground
+    #[test]
+    fn coarser_neighbours_are_read_at_their_resolution() {
+        let chunks = chunks();
+        let coarse = Heightfield {
+            width: SIZE / 2,
+            height: SIZE / 2,
+            data: (0..SIZE * SIZE / 4).map(|i| i as f32).collect(),
+        };
+        let a = HeightSampler::new(&chunks[1][1]).with_neighbour(1, 0, &coarse);
+        assert_eq!(a.pixel(SIZE as i32, 0), coarse.get(0, 0));
+        assert_eq!(a.pixel(SIZE as i32 + 3, 5), coarse.get(1, 2));
+    }
+}
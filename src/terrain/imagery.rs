@@ -0,0 +1,100 @@
+//Imagery tiles
+//Satellite or map tiles for the same z/x/y as the elevation tiles, draped over the chunks as their
+//base colour texture. They come from an http url template or a local tile directory and go through
+//the same disk cache as the elevation tiles, in their own layer.
+
+use bevy::prelude::*;
+use image::DynamicImage;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::terrain::cache::{source_layer, CachedTileSource, TerrainTileCache};
+use crate::terrain::fetch::{TerrainFetchError, DOWNLOADER};
+use crate::terrain::source::TerrainTileSource;
+
+//file types looked for in a local imagery directory
+const IMAGERY_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+//any tile server taking {z}, {x} and {y}, plus an optional {api_key}
+pub struct HttpImagerySource {
+    pub url_template: String,
+    pub api_key: Option<String>,
+}
+
+impl TerrainTileSource for HttpImagerySource {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn url_template(&self) -> Option<&str> {
+        Some(&self.url_template)
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        let url = self
+            .url_template
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())
+            .replace("{api_key}", self.api_key.as_deref().unwrap_or(""));
+        DOWNLOADER.fetch_image(&url)
+    }
+}
+
+//tiles laid out on disk as {root}/{z}/{x}/{y}.png or .jpg
+pub struct LocalImagerySource {
+    pub root: PathBuf,
+}
+
+impl TerrainTileSource for LocalImagerySource {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    //already on disk
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        let path = IMAGERY_EXTENSIONS
+            .iter()
+            .map(|ext| self.root.join(format!("{z}/{x}/{y}.{ext}")))
+            .find(|path| path.exists())
+            .ok_or(TerrainFetchError::NoData)?;
+        image::open(&path).map_err(|e| TerrainFetchError::Decode { what: path.display().to_string(), message: e.to_string() })
+    }
+}
+
+//imagery source picked from IMAGERY_TILE_URL or IMAGERY_TILE_DIR, None leaves chunks coloured by the terrain material
+#[derive(Resource, Clone)]
+pub struct ImagerySource(pub Option<Arc<dyn TerrainTileSource>>);
+
+impl FromWorld for ImagerySource {
+    fn from_world(world: &mut World) -> Self {
+        let source: Arc<dyn TerrainTileSource> = if let Ok(url_template) = env::var("IMAGERY_TILE_URL") {
+            Arc::new(HttpImagerySource {
+                url_template,
+                api_key: env::var("IMAGERY_API_KEY").ok(),
+            })
+        } else if let Ok(root) = env::var("IMAGERY_TILE_DIR") {
+            Arc::new(LocalImagerySource {
+                root: PathBuf::from(root),
+            })
+        } else {
+            return ImagerySource(None);
+        };
+        info!("using {} imagery tile source", source.name());
+
+        if !source.cacheable() {
+            return ImagerySource(Some(source));
+        }
+        let cache = world
+            .get_resource_or_insert_with(TerrainTileCache::default)
+            .0
+            .clone();
+        let layer = source_layer("imagery", source.name(), source.url_template());
+        ImagerySource(Some(Arc::new(CachedTileSource::with_layer(source, cache, layer))))
+    }
+}
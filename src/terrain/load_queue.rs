@@ -0,0 +1,122 @@
+//Chunk load priority
+//Requested chunks wait in a queue and only a few are fetched at a time. Every frame the queue is
+//ordered by how soon the plane will need each chunk: the one underneath it first, then the ones along
+//its flight path, then everything else by distance.
+
+use bevy::prelude::*;
+
+//how far ahead along the velocity the flight path reaches
+const LOOKAHEAD_SECS: f32 = 20.;
+//weight of plain distance on top of the distance to the flight path, so chunks right next to the
+//plane beat chunks far ahead of it
+const DISTANCE_WEIGHT: f32 = 0.25;
+
+//where the plane is and where it will be soon, on the ground plane
+pub struct LoadFocus {
+    position: Vec2,
+    ahead: Vec2,
+}
+
+impl LoadFocus {
+    //the lookahead is capped to the loaded area so fast flight doesn't ignore the sides completely
+    pub fn new(position: Vec3, velocity: Vec3, max_lookahead: f32) -> Self {
+        let position = position.xz();
+        let ahead = (velocity.xz() * LOOKAHEAD_SECS).clamp_length_max(max_lookahead);
+        Self {
+            position,
+            ahead: position + ahead,
+        }
+    }
+
+    //lower loads sooner
+    pub fn score(&self, chunk_centre: Vec3) -> f32 {
+        let point = chunk_centre.xz();
+        let path = self.ahead - self.position;
+        let t = if path.length_squared() > 0. {
+            ((point - self.position).dot(path) / path.length_squared()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let off_path = point.distance(self.position + path * t);
+        off_path + point.distance(self.position) * DISTANCE_WEIGHT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_LOOKAHEAD: f32 = 5000.;
+
+    fn focus(velocity: Vec3) -> LoadFocus {
+        LoadFocus::new(Vec3::new(100., 800., -50.), velocity, MAX_LOOKAHEAD)
+    }
+
+    fn chunk(x: f32, z: f32) -> Vec3 {
+        Vec3::new(100. + x, 0., -50. + z)
+    }
+
+    #[test]
+    fn the_chunk_underneath_scores_best() {
+        for velocity in [
+            Vec3::ZERO,
+            Vec3::new(60., 0., 0.),
+            Vec3::new(-30., 5., 200.),
+        ] {
+            let plane = focus(velocity);
+            let under = plane.score(chunk(0., 0.));
+            assert_eq!(under, 0.);
+            for (x, z) in [
+                (500., 0.),
+                (-500., 0.),
+                (0., 500.),
+                (0., -500.),
+                (3000., 3000.),
+            ] {
+                assert!(plane.score(chunk(x, z)) > under);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_ahead_beat_chunks_behind() {
+        let plane = focus(Vec3::new(0., 0., 80.));
+        for distance in [300., 1000., 4000.] {
+            assert!(plane.score(chunk(0., distance)) < plane.score(chunk(0., -distance)));
+            //and chunks the same distance off to the side
+            assert!(plane.score(chunk(0., distance)) < plane.score(chunk(distance, 0.)));
+        }
+        //without a velocity only the distance counts
+        let still = focus(Vec3::ZERO);
+        assert_eq!(
+            still.score(chunk(0., 1000.)),
+            still.score(chunk(0., -1000.))
+        );
+    }
+
+    #[test]
+    fn lookahead_grows_with_speed_up_to_the_limit() {
+        let lookahead = |speed: f32| {
+            focus(Vec3::new(speed, 0., 0.))
+                .ahead
+                .distance(Vec2::new(100., -50.))
+        };
+        assert_eq!(lookahead(0.), 0.);
+        assert!((lookahead(50.) - 50. * LOOKAHEAD_SECS).abs() < 1e-3);
+        assert!(lookahead(50.) < lookahead(100.));
+        assert!((lookahead(1000.) - MAX_LOOKAHEAD).abs() < 1e-3);
+        assert_eq!(lookahead(1000.), lookahead(5000.));
+        //vertical speed doesn't reach any further along the ground
+        assert_eq!(
+            focus(Vec3::new(50., -300., 0.)).ahead,
+            focus(Vec3::new(50., 0., 0.)).ahead
+        );
+
+        //a chunk past the end of a slow plane's path loses out to one a faster plane will reach
+        let (slow, fast) = (
+            focus(Vec3::new(20., 0., 0.)),
+            focus(Vec3::new(200., 0., 0.)),
+        );
+        assert!(fast.score(chunk(3000., 0.)) < slow.score(chunk(3000., 0.)));
+    }
+}
@@ -0,0 +1,172 @@
+//Terrain level of detail
+//The chunks in view are covered by a quadtree centred on the player. Nodes close to the player are
+//split down to single chunks, nodes further away stay large. A chunk gets the level of the leaf it
+//sits in: level 0 is full resolution, every level above halves the mesh resolution and uses a tile
+//one zoom level lower, cropped down to the chunk. The chunks under one lower zoom tile all fetch that
+//tile, which the tile cache only downloads once.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+pub const MAX_LOD: u32 = 3;
+
+//a node is split while the player is closer than this many node widths
+const LOD_SPLIT_DISTANCE: i32 = 1;
+
+struct QuadNode {
+    min: IVec2,
+    size: i32,
+}
+
+impl QuadNode {
+    //chebyshev distance in chunks from a chunk to the nearest chunk of this node
+    fn distance_to(&self, chunk: IVec2) -> i32 {
+        let max = self.min + IVec2::splat(self.size - 1);
+        let dx = (self.min.x - chunk.x).max(chunk.x - max.x).max(0);
+        let dy = (self.min.y - chunk.y).max(chunk.y - max.y).max(0);
+        dx.max(dy)
+    }
+
+    fn level(&self) -> u32 {
+        self.size.trailing_zeros()
+    }
+}
+
+//lod level for every chunk in the view box view_min..=view_max
+pub fn build_lod_quadtree(center: IVec2, view_min: IVec2, view_max: IVec2) -> HashMap<IVec2, u32> {
+    let mut levels = HashMap::new();
+    let root_size = 1 << MAX_LOD;
+
+    //roots are aligned to the root size in chunk coordinates so the tree doesn't shift as the player
+    //moves. Chunk (0, 0) is the origin tile, so a node only lines up with a single lower zoom tile when
+    //the origin tile is a multiple of 1 << MAX_LOD. Nothing relies on that: every chunk fetches the
+    //parent tile of its own tile, tile >> lod, and crops its part out (fetch_chunk_tile in scene.rs)
+    let root_min = IVec2::new(
+        view_min.x.div_euclid(root_size),
+        view_min.y.div_euclid(root_size),
+    );
+    let root_max = IVec2::new(
+        view_max.x.div_euclid(root_size),
+        view_max.y.div_euclid(root_size),
+    );
+    for rx in root_min.x..=root_max.x {
+        for ry in root_min.y..=root_max.y {
+            let root = QuadNode {
+                min: IVec2::new(rx, ry) * root_size,
+                size: root_size,
+            };
+            subdivide(root, center, view_min, view_max, &mut levels);
+        }
+    }
+    levels
+}
+
+fn subdivide(
+    node: QuadNode,
+    center: IVec2,
+    view_min: IVec2,
+    view_max: IVec2,
+    levels: &mut HashMap<IVec2, u32>,
+) {
+    if node.size > 1 && node.distance_to(center) < node.size * LOD_SPLIT_DISTANCE {
+        let half = node.size / 2;
+        for (ox, oy) in [(0, 0), (half, 0), (0, half), (half, half)] {
+            let child = QuadNode {
+                min: node.min + IVec2::new(ox, oy),
+                size: half,
+            };
+            subdivide(child, center, view_min, view_max, levels);
+        }
+        return;
+    }
+
+    let level = node.level();
+    for x in node.min.x..node.min.x + node.size {
+        for y in node.min.y..node.min.y + node.size {
+            let chunk = IVec2::new(x, y);
+            if chunk.cmpge(view_min).all() && chunk.cmple(view_max).all() {
+                levels.insert(chunk, level);
+            }
+        }
+    }
+}
+
+//mesh resolution for a level, halved every level
+pub fn lod_resolution(chunk_res: usize, level: u32) -> usize {
+    (chunk_res >> level).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn neighbours(chunk: IVec2) -> impl Iterator<Item = IVec2> {
+        [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+            .into_iter()
+            .map(move |d| chunk + d)
+    }
+
+    #[test]
+    fn covers_the_view_with_full_detail_in_the_middle() {
+        for center in [
+            IVec2::ZERO,
+            IVec2::new(3, -5),
+            IVec2::new(-17, 22),
+            IVec2::new(7, 8),
+        ] {
+            let (view_min, view_max) = (center - IVec2::splat(12), center + IVec2::new(12, 9));
+            let levels = build_lod_quadtree(center, view_min, view_max);
+            assert_eq!(levels[&center], 0);
+            //every chunk in view has a level, and nothing outside it
+            assert_eq!(levels.len(), 25 * 22);
+            assert!(levels
+                .keys()
+                .all(|chunk| chunk.cmpge(view_min).all() && chunk.cmple(view_max).all()));
+            assert!(levels.values().all(|level| *level <= MAX_LOD));
+            for chunk in neighbours(center) {
+                assert_eq!(levels[&chunk], 0);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_levels_differ_by_at_most_one() {
+        for cx in -9..9 {
+            for cy in -9..9 {
+                let center = IVec2::new(cx, cy);
+                let levels = build_lod_quadtree(
+                    center,
+                    center - IVec2::splat(20),
+                    center + IVec2::splat(20),
+                );
+                for (chunk, level) in &levels {
+                    for neighbour in neighbours(*chunk) {
+                        if let Some(other) = levels.get(&neighbour) {
+                            assert!(
+                                level.abs_diff(*other) <= 1,
+                                "{chunk} at {level} next to {neighbour} at {other}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn levels_grow_away_from_the_player() {
+        let levels = build_lod_quadtree(IVec2::ZERO, IVec2::splat(-40), IVec2::splat(40));
+        assert_eq!(levels[&IVec2::new(40, 0)], MAX_LOD);
+        assert_eq!(levels[&IVec2::new(-40, -40)], MAX_LOD);
+        let ring_level = |r: i32| (-r..=r).map(|i| levels[&IVec2::new(r, i)]).max().unwrap();
+        assert!((1..40).all(|r| ring_level(r) <= ring_level(r + 1)));
+    }
+
+    #[test]
+    fn resolution_halves_per_level() {
+        assert_eq!(lod_resolution(64, 0), 64);
+        assert_eq!(lod_resolution(64, 1), 32);
+        assert_eq!(lod_resolution(64, 3), 8);
+        assert_eq!(lod_resolution(4, 3), 2);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod cache;
+pub mod chunk_manager;
+pub mod dem;
+pub mod fetch;
+pub mod heightfield;
+pub mod imagery;
+pub mod load_queue;
+pub mod lod;
+pub mod mvt;
+pub mod noise;
+pub mod overlay;
+pub mod query;
+pub mod settings;
+pub mod source;
+pub mod vector;
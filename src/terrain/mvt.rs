@@ -0,0 +1,480 @@
+//Mapbox Vector Tile decoding
+//Vector tiles are protobuf messages holding named layers of features. Every feature carries its
+//geometry as a stream of drawing commands in tile coordinates (0..extent, y down) and its attributes
+//as indices into the layer's shared key and value tables.
+//https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+use bevy::prelude::*;
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+
+const DEFAULT_EXTENT: u32 = 4096;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+//geometry commands
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeomType {
+    Unknown,
+    Point,
+    LineString,
+    Polygon,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Float(f64),
+    Int(i64),
+    Bool(bool),
+}
+
+impl Value {
+    //numbers, and strings holding a number, as f64
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(v) => Some(*v),
+            Value::Int(v) => Some(*v as f64),
+            Value::String(s) => s.trim().parse().ok(),
+            Value::Bool(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Feature {
+    pub id: Option<u64>,
+    pub geom_type: GeomType,
+    pub properties: HashMap<String, Value>,
+    //one entry per MoveTo, points, a line or a ring. Rings are not closed, the last point is not repeated
+    pub geometry: Vec<Vec<IVec2>>,
+}
+
+impl Feature {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.properties.get(key)
+    }
+
+    //rings grouped into polygons, an exterior ring followed by its holes
+    //exterior rings have a positive area in tile coordinates, clockwise as drawn with y down
+    pub fn polygons(&self) -> Vec<Vec<Vec<IVec2>>> {
+        if self.geom_type != GeomType::Polygon {
+            return Vec::new();
+        }
+        let mut polygons: Vec<Vec<Vec<IVec2>>> = Vec::new();
+        for ring in &self.geometry {
+            let area = ring_area(ring);
+            if ring.len() < 3 || area == 0 {
+                continue;
+            }
+            match polygons.last_mut() {
+                Some(polygon) if area < 0 => polygon.push(ring.clone()),
+                //a hole before any exterior ring has nothing to cut
+                None if area < 0 => {}
+                _ => polygons.push(vec![ring.clone()]),
+            }
+        }
+        polygons
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub name: String,
+    //size of the tile in the layer's coordinates
+    pub extent: u32,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Tile {
+    pub layers: Vec<Layer>,
+}
+
+impl Tile {
+    //decode a tile, gzipped tiles are unpacked first since servers often send them that way
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            let mut unpacked = Vec::new();
+            GzDecoder::new(bytes)
+                .read_to_end(&mut unpacked)
+                .map_err(|e| format!("bad gzip data: {e}"))?;
+            return Self::decode_raw(&unpacked);
+        }
+        Self::decode_raw(bytes)
+    }
+
+    fn decode_raw(bytes: &[u8]) -> Result<Self, String> {
+        let mut tile = Tile::default();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.key()? {
+            match (field, wire) {
+                (3, WIRE_LEN) => tile.layers.push(decode_layer(reader.bytes()?)?),
+                _ => reader.skip(wire)?,
+            }
+        }
+        Ok(tile)
+    }
+}
+
+fn decode_layer(bytes: &[u8]) -> Result<Layer, String> {
+    let mut name = String::new();
+    let mut extent = DEFAULT_EXTENT;
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    //features refer to keys and values by index, and those may come after them in the message
+    let mut raw_features = Vec::new();
+
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        match (field, wire) {
+            (1, WIRE_LEN) => name = reader.string()?,
+            (2, WIRE_LEN) => raw_features.push(reader.bytes()?),
+            (3, WIRE_LEN) => keys.push(reader.string()?),
+            (4, WIRE_LEN) => values.push(decode_value(reader.bytes()?)?),
+            (5, WIRE_VARINT) => extent = reader.varint()? as u32,
+            _ => reader.skip(wire)?,
+        }
+    }
+    if extent == 0 {
+        return Err(format!("layer {name} has an extent of 0"));
+    }
+
+    let features = raw_features
+        .into_iter()
+        .map(|bytes| decode_feature(bytes, &keys, &values))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Layer { name, extent, features })
+}
+
+fn decode_value(bytes: &[u8]) -> Result<Value, String> {
+    let mut value = Value::Bool(false);
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        value = match (field, wire) {
+            (1, WIRE_LEN) => Value::String(reader.string()?),
+            (2, WIRE_32) => Value::Float(f32::from_bits(reader.fixed32()?) as f64),
+            (3, WIRE_64) => Value::Float(f64::from_bits(reader.fixed64()?)),
+            (4, WIRE_VARINT) => Value::Int(reader.varint()? as i64),
+            (5, WIRE_VARINT) => Value::Int(reader.varint()? as i64),
+            (6, WIRE_VARINT) => Value::Int(zigzag(reader.varint()?)),
+            (7, WIRE_VARINT) => Value::Bool(reader.varint()? != 0),
+            _ => {
+                reader.skip(wire)?;
+                continue;
+            }
+        };
+    }
+    Ok(value)
+}
+
+fn decode_feature(bytes: &[u8], keys: &[String], values: &[Value]) -> Result<Feature, String> {
+    let mut id = None;
+    let mut geom_type = GeomType::Unknown;
+    let mut tags = Vec::new();
+    let mut commands = Vec::new();
+
+    let mut reader = Reader::new(bytes);
+    while let Some((field, wire)) = reader.key()? {
+        match (field, wire) {
+            (1, WIRE_VARINT) => id = Some(reader.varint()?),
+            (2, WIRE_LEN) => tags = reader.packed()?,
+            (3, WIRE_VARINT) => {
+                geom_type = match reader.varint()? {
+                    1 => GeomType::Point,
+                    2 => GeomType::LineString,
+                    3 => GeomType::Polygon,
+                    _ => GeomType::Unknown,
+                }
+            }
+            (4, WIRE_LEN) => commands = reader.packed()?,
+            _ => reader.skip(wire)?,
+        }
+    }
+
+    let mut properties = HashMap::new();
+    for pair in tags.chunks_exact(2) {
+        let (Some(key), Some(value)) = (keys.get(pair[0] as usize), values.get(pair[1] as usize)) else {
+            return Err("feature tag points past the layer's keys or values".to_string());
+        };
+        properties.insert(key.clone(), value.clone());
+    }
+
+    Ok(Feature {
+        id,
+        geom_type,
+        properties,
+        geometry: decode_geometry(&commands)?,
+    })
+}
+
+//run the drawing commands, the cursor carries over between commands and parts
+fn decode_geometry(commands: &[u32]) -> Result<Vec<Vec<IVec2>>, String> {
+    let mut parts: Vec<Vec<IVec2>> = Vec::new();
+    let mut cursor = IVec2::ZERO;
+    let mut i = 0;
+    while i < commands.len() {
+        let id = commands[i] & 7;
+        let count = (commands[i] >> 3) as usize;
+        i += 1;
+        match id {
+            MOVE_TO | LINE_TO => {
+                if i + count * 2 > commands.len() {
+                    return Err("geometry ends in the middle of a command".to_string());
+                }
+                for _ in 0..count {
+                    cursor += IVec2::new(zigzag(commands[i] as u64) as i32, zigzag(commands[i + 1] as u64) as i32);
+                    i += 2;
+                    match (id, parts.last_mut()) {
+                        (LINE_TO, Some(part)) => part.push(cursor),
+                        (LINE_TO, None) => return Err("LineTo before any MoveTo".to_string()),
+                        _ => parts.push(vec![cursor]),
+                    }
+                }
+            }
+            //rings are stored open, closing only returns to the first point
+            CLOSE_PATH => {}
+            other => return Err(format!("unknown geometry command {other}")),
+        }
+    }
+    Ok(parts)
+}
+
+//twice the signed area by the surveyor's formula
+fn ring_area(ring: &[IVec2]) -> i64 {
+    let mut area = 0;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        area += a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64;
+    }
+    area
+}
+
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+//protobuf wire types
+const WIRE_VARINT: u8 = 0;
+const WIRE_64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_32: u8 = 5;
+
+//just enough of a protobuf reader for vector tiles
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    //field number and wire type of the next field, None at the end of the message
+    fn key(&mut self) -> Result<Option<(u32, u8)>, String> {
+        if self.pos >= self.buf.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        Ok(Some(((key >> 3) as u32, (key & 7) as u8)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some(&byte) = self.buf.get(self.pos) else {
+                return Err("message ends in the middle of a varint".to_string());
+            };
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.buf.len());
+        let Some(end) = end else {
+            return Err("field runs past the end of the message".to_string());
+        };
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(self.bytes()?).into_owned())
+    }
+
+    fn fixed32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn fixed64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    //packed repeated uint32
+    fn packed(&mut self) -> Result<Vec<u32>, String> {
+        let mut inner = Reader::new(self.bytes()?);
+        let mut values = Vec::new();
+        while inner.pos < inner.buf.len() {
+            values.push(inner.varint()? as u32);
+        }
+        Ok(values)
+    }
+
+    fn skip(&mut self, wire: u8) -> Result<(), String> {
+        match wire {
+            WIRE_VARINT => self.varint().map(|_| ()),
+            WIRE_64 => self.take(8).map(|_| ()),
+            WIRE_LEN => self.bytes().map(|_| ()),
+            WIRE_32 => self.take(4).map(|_| ()),
+            other => Err(format!("unsupported wire type {other}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a zoom 14 tile with three buildings, a lake and a road, see the vector tests in buildings.rs
+    const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/vector/14/2582/5917.mvt");
+
+    fn layer<'a>(tile: &'a Tile, name: &str) -> &'a Layer {
+        tile.layers.iter().find(|layer| layer.name == name).unwrap()
+    }
+
+    #[test]
+    fn decodes_fixture_layers_and_properties() {
+        let tile = Tile::decode(FIXTURE).unwrap();
+        let names: Vec<&str> = tile
+            .layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect();
+        assert_eq!(names, ["buildings", "water", "roads"]);
+        assert!(tile.layers.iter().all(|layer| layer.extent == 4096));
+
+        let buildings = &layer(&tile, "buildings").features;
+        assert_eq!(buildings.len(), 3);
+        assert_eq!(buildings[0].id, Some(1));
+        assert_eq!(buildings[0].get("height"), Some(&Value::Float(20.)));
+        assert_eq!(
+            buildings[1].get("building:levels").and_then(|v| v.as_f64()),
+            Some(4.)
+        );
+        assert_eq!(buildings[2].get("min_height"), Some(&Value::Float(5.5)));
+        assert_eq!(buildings[2].get("height"), Some(&Value::Float(12.)));
+
+        let lake = &layer(&tile, "water").features[0];
+        assert_eq!(lake.id, None);
+        assert_eq!(lake.get("kind").and_then(|v| v.as_str()), Some("lake"));
+        assert_eq!(lake.get("area"), Some(&Value::Int(-3)));
+
+        let road = &layer(&tile, "roads").features[0];
+        assert_eq!(road.geom_type, GeomType::LineString);
+        assert_eq!(road.get("oneway"), Some(&Value::Bool(true)));
+        assert_eq!(
+            road.geometry,
+            [vec![
+                IVec2::new(0, 2200),
+                IVec2::new(2000, 2200),
+                IVec2::new(4096, 2400)
+            ]]
+        );
+    }
+
+    #[test]
+    fn groups_rings_into_polygons() {
+        let tile = Tile::decode(FIXTURE).unwrap();
+        let buildings = &layer(&tile, "buildings").features;
+
+        //the courtyard is a hole in the first building
+        let polygons = buildings[0].polygons();
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].len(), 2);
+        assert_eq!(polygons[0][0][0], IVec2::new(1000, 1000));
+        assert!(ring_area(&polygons[0][0]) > 0 && ring_area(&polygons[0][1]) < 0);
+
+        let l_shape = buildings[1].polygons();
+        assert_eq!(l_shape.len(), 1);
+        assert_eq!(l_shape[0][0].len(), 6);
+        assert_eq!(ring_area(&l_shape[0][0]), 2 * 750_000);
+
+        assert!(layer(&tile, "roads").features[0].polygons().is_empty());
+    }
+
+    #[test]
+    fn gzipped_tiles_decode_the_same() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(FIXTURE).unwrap();
+        let tile = Tile::decode(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(tile.layers.len(), 3);
+        assert_eq!(
+            tile.layers[0].features[1].geometry,
+            Tile::decode(FIXTURE).unwrap().layers[0].features[1].geometry
+        );
+    }
+
+    #[test]
+    fn rejects_broken_tiles() {
+        assert!(Tile::decode(&FIXTURE[..FIXTURE.len() - 7]).is_err());
+        assert!(Tile::decode(&[0x1f, 0x8b, 0, 0]).is_err());
+        //a layer with an extent of 0
+        assert!(Tile::decode(&[0x1a, 0x02, 0x28, 0x00]).is_err());
+        assert!(Tile::decode(&[]).unwrap().layers.is_empty());
+    }
+
+    //the examples from the vector tile spec
+    #[test]
+    fn decodes_spec_geometry() {
+        assert_eq!(
+            decode_geometry(&[9, 50, 34]).unwrap(),
+            [vec![IVec2::new(25, 17)]]
+        );
+        assert_eq!(
+            decode_geometry(&[9, 4, 4, 18, 0, 16, 16, 0]).unwrap(),
+            [vec![
+                IVec2::new(2, 2),
+                IVec2::new(2, 10),
+                IVec2::new(10, 10)
+            ]]
+        );
+        assert_eq!(
+            decode_geometry(&[9, 6, 12, 18, 10, 12, 24, 44, 15]).unwrap(),
+            [vec![
+                IVec2::new(3, 6),
+                IVec2::new(8, 12),
+                IVec2::new(20, 34)
+            ]]
+        );
+        assert!(decode_geometry(&[18, 0, 16]).is_err());
+        assert!(decode_geometry(&[9, 50]).is_err());
+        assert_eq!(zigzag(3), -2);
+        assert_eq!(zigzag(4), 2);
+    }
+}
@@ -0,0 +1,208 @@
+//Procedural terrain
+//Seeded fractal gradient noise shaped into plains, rolling hills, ridged mountain ranges and valleys.
+//Heights are a function of the position on the Web Mercator plane, so every tile at every zoom level
+//samples the same continuous surface and neighbouring chunks always line up.
+//https://en.wikipedia.org/wiki/Perlin_noise
+
+use bevy::math::DVec2;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+//wavelength of the largest hills, in metres
+const BASE_WAVELENGTH: f64 = 40000.;
+//lowest and highest ground the shaping aims for, in metres
+const SEA_FLOOR: f64 = -120.;
+const MOUNTAIN_HEIGHT: f64 = 2600.;
+
+//offsets so the layers built from the same seed don't line up with each other
+const CONTINENT_OFFSET: DVec2 = DVec2::new(7_340.5, -3_912.25);
+const MOUNTAIN_OFFSET: DVec2 = DVec2::new(-15_731.75, 9_181.5);
+const VALLEY_OFFSET: DVec2 = DVec2::new(4_457.25, 21_689.75);
+
+//16 directions evenly spread around the circle
+const GRADIENTS: [DVec2; 16] = [
+    DVec2::new(1.0, 0.0),
+    DVec2::new(0.923_879_5, 0.382_683_4),
+    DVec2::new(FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    DVec2::new(0.382_683_4, 0.923_879_5),
+    DVec2::new(0.0, 1.0),
+    DVec2::new(-0.382_683_4, 0.923_879_5),
+    DVec2::new(-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    DVec2::new(-0.923_879_5, 0.382_683_4),
+    DVec2::new(-1.0, 0.0),
+    DVec2::new(-0.923_879_5, -0.382_683_4),
+    DVec2::new(-FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+    DVec2::new(-0.382_683_4, -0.923_879_5),
+    DVec2::new(0.0, -1.0),
+    DVec2::new(0.382_683_4, -0.923_879_5),
+    DVec2::new(FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+    DVec2::new(0.923_879_5, -0.382_683_4),
+];
+
+pub struct TerrainNoise {
+    seed: u32,
+}
+
+impl TerrainNoise {
+    pub fn new(seed: u32) -> Self {
+        Self { seed }
+    }
+
+    //ground height in metres at a point on the mercator plane, in metres from its north west corner
+    pub fn height(&self, position: DVec2) -> f32 {
+        let p = position / BASE_WAVELENGTH;
+
+        //large scale land masses decide between sea, lowland plains and highlands
+        let continent = self.fbm(p * 0.25 + CONTINENT_OFFSET, 4, 1);
+        let land = smoothstep(-0.35, 0.15, continent);
+
+        //plains are low and nearly flat, hills roll over the rest of the land
+        let hills = self.fbm(p * 2.0, 5, 2) * 120.0 * land;
+        let plains = SEA_FLOOR + land * 260.0;
+
+        //mountain ranges grow only where the highland mask is up, sharp ridges come from folded noise
+        let highland = smoothstep(0.05, 0.45, self.fbm(p * 0.5 + MOUNTAIN_OFFSET, 3, 3)) * land;
+        let mountains = self.ridged(p, 7, 4) * MOUNTAIN_HEIGHT * highland;
+
+        //long winding valleys follow the zero line of another noise layer
+        let valley_noise = self.fbm(p * 0.7 + VALLEY_OFFSET, 3, 5).abs();
+        let valley = 1.0 - smoothstep(0.0, 0.06, valley_noise);
+        let carved = valley * (80.0 + mountains * 0.35) * land;
+
+        (plains + hills + mountains - carved) as f32
+    }
+
+    //fractal sum of noise octaves, roughly -1..1
+    fn fbm(&self, p: DVec2, octaves: u32, layer: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        for octave in 0..octaves {
+            sum += amplitude * self.gradient(p * frequency, layer * 16 + octave);
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum
+    }
+
+    //ridged multifractal, 0..1 with sharp crests, each octave is weighted by the one before so detail
+    //gathers on the ridges instead of the valley floors
+    fn ridged(&self, p: DVec2, octaves: u32, layer: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        let mut weight = 1.0;
+        for octave in 0..octaves {
+            let ridge = 1.0 - self.gradient(p * frequency, layer * 16 + octave).abs();
+            let ridge = ridge * ridge * weight;
+            sum += ridge * amplitude;
+            weight = (ridge * 2.0).clamp(0.0, 1.0);
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum
+    }
+
+    //2d gradient noise on an unbounded integer lattice, roughly -1..1
+    fn gradient(&self, p: DVec2, octave: u32) -> f64 {
+        let cell = p.floor();
+        let f = p - cell;
+        let (x, y) = (cell.x as i64, cell.y as i64);
+
+        let dot = |dx: i64, dy: i64| {
+            let g = self.lattice_gradient(x + dx, y + dy, octave);
+            g.dot(f - DVec2::new(dx as f64, dy as f64))
+        };
+        let u = fade(f.x);
+        let v = fade(f.y);
+        let top = lerp(dot(0, 0), dot(1, 0), u);
+        let bottom = lerp(dot(0, 1), dot(1, 1), u);
+        lerp(top, bottom, v) * std::f64::consts::SQRT_2
+    }
+
+    //one of 16 unit vectors picked by hashing the lattice point, no permutation table so the lattice
+    //never repeats
+    fn lattice_gradient(&self, x: i64, y: i64, octave: u32) -> DVec2 {
+        let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ ((self.seed as u64) << 32 | octave as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        h ^= h >> 33;
+        GRADIENTS[(h & 15) as usize]
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //points spread over a few hundred km of the mercator plane
+    fn points() -> impl Iterator<Item = DVec2> {
+        (0..400).map(|i| {
+            DVec2::new(
+                20_000_000. + (i % 20) as f64 * 13_713.3,
+                11_000_000. + (i / 20) as f64 * 17_117.7,
+            )
+        })
+    }
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let (a, b) = (TerrainNoise::new(7), TerrainNoise::new(7));
+        assert!(points().all(|p| a.height(p) == b.height(p)));
+    }
+
+    #[test]
+    fn seeds_change_the_terrain() {
+        let (a, b) = (TerrainNoise::new(0), TerrainNoise::new(1));
+        let differing = points()
+            .filter(|p| (a.height(*p) - b.height(*p)).abs() > 1.)
+            .count();
+        assert!(differing > 300, "{differing}");
+    }
+
+    #[test]
+    fn heights_stay_in_range() {
+        let noise = TerrainNoise::new(0);
+        for p in points() {
+            let h = noise.height(p) as f64;
+            assert!(
+                (SEA_FLOOR - 200.0..=MOUNTAIN_HEIGHT + 600.).contains(&h),
+                "{h} at {p}"
+            );
+        }
+    }
+
+    //the surface is continuous, a metre over never jumps more than a steep slope would
+    #[test]
+    fn no_jumps_between_neighbouring_points() {
+        let noise = TerrainNoise::new(0);
+        for p in points() {
+            let h = noise.height(p);
+            for step in [DVec2::X, DVec2::Y, DVec2::NEG_X, DVec2::NEG_Y] {
+                assert!((noise.height(p + step) - h).abs() < 2., "jump at {p}");
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_noise_is_zero_on_the_lattice() {
+        let noise = TerrainNoise::new(3);
+        for (x, y) in [(0., 0.), (5., -2.), (-17., 40.)] {
+            assert!(noise.gradient(DVec2::new(x, y), 0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//Map overlays
+//Land use areas, water and roads from a chunk's vector tile are drawn into a transparent texture that
+//the terrain shader lays over the ground colour, so rivers, lakes, forests, towns and roads can be
+//picked out from the air. Works with tilezen tiles (kind) and openmaptiles style tiles (class).
+//The same shapes are also drawn into a coarser land use map that vegetation reads to place trees.
+
+use bevy::prelude::*;
+use image::{Rgba, RgbaImage};
+
+use crate::terrain::mvt::{Feature, GeomType, Layer, Tile};
+
+//overlay pixels along one edge of a full detail chunk, lower lods halve it down to MIN_OVERLAY_SIZE
+pub const OVERLAY_SIZE: u32 = 512;
+const MIN_OVERLAY_SIZE: u32 = 128;
+
+//roads are kept at least this many pixels wide so the big ones still show from far away
+const MIN_ROAD_PIXELS: f32 = 1.2;
+
+const WATER: Rgba<u8> = Rgba([62, 112, 165, 235]);
+const FOREST: Rgba<u8> = Rgba([34, 74, 36, 150]);
+const PARK: Rgba<u8> = Rgba([88, 140, 68, 110]);
+const FARMLAND: Rgba<u8> = Rgba([176, 164, 102, 80]);
+const URBAN: Rgba<u8> = Rgba([152, 142, 132, 150]);
+const INDUSTRIAL: Rgba<u8> = Rgba([140, 128, 140, 150]);
+
+//land use map cells along one edge of a full detail chunk, halved per lod like the overlay
+const LAND_USE_SIZE: u32 = 256;
+const MIN_LAND_USE_SIZE: u32 = 64;
+
+//layers drawn in order, later ones on top
+const LANDUSE_LAYERS: [&str; 3] = ["landuse", "landcover", "park"];
+const WATER_LAYERS: [&str; 2] = ["water", "waterway"];
+const ROAD_LAYERS: [&str; 2] = ["roads", "transportation"];
+
+pub fn overlay_size(lod: u32) -> u32 {
+    (OVERLAY_SIZE >> lod).max(MIN_OVERLAY_SIZE)
+}
+
+pub fn land_use_size(lod: u32) -> u32 {
+    (LAND_USE_SIZE >> lod).max(MIN_LAND_USE_SIZE)
+}
+
+//what covers the ground, as far as the vector tile knows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LandUse {
+    #[default]
+    Unknown,
+    Forest,
+    Park,
+    Farmland,
+    Urban,
+    Industrial,
+    Water,
+    Road,
+}
+
+impl LandUse {
+    fn color(self) -> Option<Rgba<u8>> {
+        match self {
+            LandUse::Forest => Some(FOREST),
+            LandUse::Park => Some(PARK),
+            LandUse::Farmland => Some(FARMLAND),
+            LandUse::Urban => Some(URBAN),
+            LandUse::Industrial => Some(INDUSTRIAL),
+            LandUse::Water => Some(WATER),
+            LandUse::Unknown | LandUse::Road => None,
+        }
+    }
+}
+
+//land use of a chunk on a grid running from its north west corner, like the overlay's pixels
+#[derive(Clone)]
+pub struct LandUseMap {
+    size: u32,
+    data: Vec<LandUse>,
+}
+
+impl LandUseMap {
+    //land use at a position relative to the chunk's centre
+    pub fn at(&self, local: Vec2, chunk_size: f32) -> LandUse {
+        let cell = ((local / chunk_size + 0.5) * self.size as f32).as_ivec2();
+        let max = self.size as i32 - 1;
+        self.data[(cell.y.clamp(0, max) * self.size as i32 + cell.x.clamp(0, max)) as usize]
+    }
+
+    //one land use everywhere, for tests that don't draw vector tiles
+    #[cfg(test)]
+    pub fn filled(land_use: LandUse, size: u32) -> Self {
+        Self {
+            size,
+            data: vec![land_use; (size * size) as usize],
+        }
+    }
+}
+
+//where a vector tile lands on a chunk. A chunk is drawn from part of a parent tile a few zoom levels
+//up, or from all the tiles covering it a few levels down
+#[derive(Clone, Copy, Debug)]
+pub struct OverlayRegion {
+    //width of the tile in chunks, 2^n for a tile n levels up and 1 / 2^n for one n levels down
+    pub tile_span: f32,
+    //the tile's north west corner from the chunk's, in chunks
+    pub origin: Vec2,
+    //ground metres to overlay pixels
+    pub pixels_per_metre: f32,
+}
+
+impl OverlayRegion {
+    //pixels of a size x size overlay that belong to this tile, max exclusive. Shapes reach a little past
+    //their tile's edge, so they are cut off there to not be drawn twice where tiles meet
+    fn clip(&self, size: u32) -> IRect {
+        let min = (self.origin * size as f32).round().as_ivec2().max(IVec2::ZERO);
+        let max = ((self.origin + self.tile_span) * size as f32).round().as_ivec2().min(IVec2::splat(size as i32));
+        IRect::from_corners(min, max)
+    }
+}
+
+//the vector tiles at vector_zoom covering the terrain tile x, y at zoom, with where each one lands
+pub fn overlay_tiles(zoom: u32, tile_x: u32, tile_y: u32, vector_zoom: u32, pixels_per_metre: f32) -> Vec<((u32, u32, u32), OverlayRegion)> {
+    if vector_zoom <= zoom {
+        let shift = zoom - vector_zoom;
+        let mask = (1 << shift) - 1;
+        let region = OverlayRegion {
+            tile_span: (1 << shift) as f32,
+            origin: -UVec2::new(tile_x & mask, tile_y & mask).as_vec2(),
+            pixels_per_metre,
+        };
+        return vec![((vector_zoom, tile_x >> shift, tile_y >> shift), region)];
+    }
+    let per_side = 1 << (vector_zoom - zoom);
+    (0..per_side)
+        .flat_map(|j| (0..per_side).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            let region = OverlayRegion {
+                tile_span: 1. / per_side as f32,
+                origin: UVec2::new(i, j).as_vec2() / per_side as f32,
+                pixels_per_metre,
+            };
+            ((vector_zoom, tile_x * per_side + i, tile_y * per_side + j), region)
+        })
+        .collect()
+}
+
+//draw the tiles' overlay layers, None if nothing in them lands on the chunk
+pub fn rasterise_overlay(tiles: &[(Tile, OverlayRegion)], size: u32) -> Option<RgbaImage> {
+    let mut canvas = Canvas {
+        image: RgbaImage::new(size, size),
+        painted: false,
+    };
+    for_each_shape(tiles, size, |shape, clip| match shape {
+        Shape::Area(rings, land_use) => {
+            if let Some(color) = land_use.color() {
+                canvas.fill_polygon(&rings, color, clip);
+            }
+        }
+        Shape::Line(a, b, width, color, _) => canvas.draw_segment(a, b, width, color, clip),
+    });
+    canvas.painted.then_some(canvas.image)
+}
+
+//land use of every cell of the chunk from the same layers as the overlay, None if the tiles have none
+pub fn rasterise_land_use(tiles: &[(Tile, OverlayRegion)], size: u32) -> Option<LandUseMap> {
+    let mut map = LandUseMap {
+        size,
+        data: vec![LandUse::Unknown; (size * size) as usize],
+    };
+    let mut painted = false;
+    for_each_shape(tiles, size, |shape, clip| {
+        let mut paint = |x: i32, y: i32, land_use: LandUse| {
+            map.data[(y as u32 * size + x as u32) as usize] = land_use;
+            painted = true;
+        };
+        match shape {
+            Shape::Area(rings, land_use) => fill_spans(&rings, clip, |y, start, end| {
+                for x in start..=end {
+                    paint(x, y, land_use);
+                }
+            }),
+            //only cells mostly covered by the line count as road or river
+            Shape::Line(a, b, width, _, land_use) => segment_coverage(a, b, width, clip, |x, y, coverage| {
+                if coverage >= 0.5 {
+                    paint(x, y, land_use);
+                }
+            }),
+        }
+    });
+    painted.then_some(map)
+}
+
+//an area with its rings in pixels, or a line segment in pixels with its width, colour and land use
+enum Shape {
+    Area(Vec<Vec<Vec2>>, LandUse),
+    Line(Vec2, Vec2, f32, Rgba<u8>, LandUse),
+}
+
+//every area and line of the overlay layers that we know how to draw, in drawing order, with the pixels
+//they may cover
+fn for_each_shape(tiles: &[(Tile, OverlayRegion)], size: u32, mut draw: impl FnMut(Shape, IRect)) {
+    let layers = LANDUSE_LAYERS.iter().chain(WATER_LAYERS.iter()).chain(ROAD_LAYERS.iter());
+    for name in layers {
+        for (tile, region) in tiles {
+            let clip = region.clip(size);
+            if clip.is_empty() {
+                continue;
+            }
+            for layer in tile.layers.iter().filter(|layer| layer.name == *name) {
+                for_each_layer_shape(layer, name, region, size, |shape| draw(shape, clip));
+            }
+        }
+    }
+}
+
+//the shapes of one layer of a tile, in overlay pixels
+fn for_each_layer_shape(layer: &Layer, name: &str, region: &OverlayRegion, size: u32, mut draw: impl FnMut(Shape)) {
+    //tile coordinates to overlay pixels
+    let scale = size as f32 * region.tile_span / layer.extent as f32;
+    let origin = region.origin * size as f32;
+    let to_pixel = |p: IVec2| p.as_vec2() * scale + origin;
+    //water lines are rivers, everything else drawn as a line is a road or railway
+    let line_land_use = if WATER_LAYERS.contains(&name) { LandUse::Water } else { LandUse::Road };
+
+    for feature in &layer.features {
+        match feature.geom_type {
+            GeomType::Polygon => {
+                let land_use = area_land_use(name, feature);
+                if land_use == LandUse::Unknown {
+                    continue;
+                }
+                let rings: Vec<Vec<Vec2>> = feature
+                    .geometry
+                    .iter()
+                    .map(|ring| ring.iter().map(|p| to_pixel(*p)).collect())
+                    .collect();
+                draw(Shape::Area(rings, land_use));
+            }
+            GeomType::LineString => {
+                let Some((color, width)) = line_style(name, feature) else {
+                    continue;
+                };
+                let width = (width * region.pixels_per_metre).max(MIN_ROAD_PIXELS);
+                for line in &feature.geometry {
+                    for segment in line.windows(2) {
+                        draw(Shape::Line(to_pixel(segment[0]), to_pixel(segment[1]), width, color, line_land_use));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+//tilezen names the type of a feature kind, openmaptiles class
+fn feature_kind(feature: &Feature) -> &str {
+    feature
+        .get("kind")
+        .or_else(|| feature.get("class"))
+        .and_then(|value| value.as_str())
+        .unwrap_or("")
+}
+
+fn area_land_use(layer: &str, feature: &Feature) -> LandUse {
+    if WATER_LAYERS.contains(&layer) {
+        //the sea is already drawn by the water planes
+        return if feature_kind(feature) != "ocean" { LandUse::Water } else { LandUse::Unknown };
+    }
+    match feature_kind(feature) {
+        "forest" | "wood" | "natural_wood" | "nature_reserve" => LandUse::Forest,
+        "park" | "grass" | "grassland" | "meadow" | "golf_course" | "recreation_ground" | "garden" | "national_park" => LandUse::Park,
+        "farmland" | "farm" | "farmyard" | "orchard" | "vineyard" | "allotments" => LandUse::Farmland,
+        "residential" | "urban" | "urban_area" | "commercial" | "retail" | "suburb" | "neighbourhood" => LandUse::Urban,
+        "industrial" | "railway" | "quarry" | "landfill" => LandUse::Industrial,
+        "water" | "reservoir" | "basin" | "wetland" => LandUse::Water,
+        _ => LandUse::Unknown,
+    }
+}
+
+//colour and width in metres
+fn line_style(layer: &str, feature: &Feature) -> Option<(Rgba<u8>, f32)> {
+    if WATER_LAYERS.contains(&layer) {
+        return match feature_kind(feature) {
+            "river" | "canal" => Some((WATER, 30.)),
+            "stream" | "ditch" | "drain" => Some((WATER, 6.)),
+            _ => None,
+        };
+    }
+    match feature_kind(feature) {
+        "highway" | "motorway" | "trunk" => Some((Rgba([236, 176, 82, 255]), 24.)),
+        "major_road" | "primary" | "secondary" => Some((Rgba([232, 222, 196, 245]), 14.)),
+        "minor_road" | "tertiary" | "minor" | "service" => Some((Rgba([212, 210, 204, 220]), 8.)),
+        "rail" | "transit" => Some((Rgba([84, 80, 78, 220]), 6.)),
+        "path" | "track" => Some((Rgba([190, 172, 140, 160]), 3.)),
+        _ => None,
+    }
+}
+
+struct Canvas {
+    image: RgbaImage,
+    painted: bool,
+}
+
+impl Canvas {
+    //alpha blend a colour over a pixel, coverage scales the colour's alpha
+    fn blend(&mut self, x: i32, y: i32, color: Rgba<u8>, coverage: f32) {
+        let (w, h) = self.image.dimensions();
+        if x < 0 || y < 0 || x >= w as i32 || y >= h as i32 || coverage <= 0. {
+            return;
+        }
+        let src_a = color[3] as f32 / 255. * coverage.min(1.);
+        let dst = self.image.get_pixel_mut(x as u32, y as u32);
+        let dst_a = dst[3] as f32 / 255.;
+        let out_a = src_a + dst_a * (1. - src_a);
+        if out_a <= 0. {
+            return;
+        }
+        for c in 0..3 {
+            let value = (color[c] as f32 * src_a + dst[c] as f32 * dst_a * (1. - src_a)) / out_a;
+            dst[c] = value.round() as u8;
+        }
+        dst[3] = (out_a * 255.).round() as u8;
+        self.painted = true;
+    }
+
+    fn fill_polygon(&mut self, rings: &[Vec<Vec2>], color: Rgba<u8>, clip: IRect) {
+        fill_spans(rings, clip, |y, start, end| {
+            for x in start..=end {
+                self.blend(x, y, color, 1.);
+            }
+        });
+    }
+
+    fn draw_segment(&mut self, a: Vec2, b: Vec2, width: f32, color: Rgba<u8>, clip: IRect) {
+        segment_coverage(a, b, width, clip, |x, y, coverage| self.blend(x, y, color, coverage));
+    }
+}
+
+//even-odd scanline fill over all rings together, so holes stay open. Calls span with each row and the
+//first and last pixel of every run inside the polygon and the clip rectangle (max exclusive)
+fn fill_spans(rings: &[Vec<Vec2>], clip: IRect, mut span: impl FnMut(i32, i32, i32)) {
+    let (min_y, max_y) = rings
+        .iter()
+        .flatten()
+        .fold((f32::MAX, f32::MIN), |(min, max), p| (min.min(p.y), max.max(p.y)));
+    let first_row = (min_y.floor() as i32).max(clip.min.y);
+    let last_row = (max_y.ceil() as i32).min(clip.max.y - 1);
+
+    let mut crossings = Vec::new();
+    for y in first_row..=last_row {
+        let yc = y as f32 + 0.5;
+        crossings.clear();
+        for ring in rings {
+            for (i, a) in ring.iter().enumerate() {
+                let b = ring[(i + 1) % ring.len()];
+                if (a.y <= yc) != (b.y <= yc) {
+                    crossings.push(a.x + (yc - a.y) / (b.y - a.y) * (b.x - a.x));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        for pair in crossings.chunks_exact(2) {
+            let start = ((pair[0] - 0.5).ceil() as i32).max(clip.min.x);
+            let end = ((pair[1] - 0.5).floor() as i32).min(clip.max.x - 1);
+            if start <= end {
+                span(y, start, end);
+            }
+        }
+    }
+}
+
+//pixels near a line segment of the given width with how much of each it covers, with a soft one
+//pixel edge. Only pixels inside the clip rectangle (max exclusive) are visited
+fn segment_coverage(a: Vec2, b: Vec2, width: f32, clip: IRect, mut pixel: impl FnMut(i32, i32, f32)) {
+    let half = width * 0.5;
+    let min = (a.min(b) - Vec2::splat(half + 1.)).floor().as_ivec2().max(clip.min);
+    let max = (a.max(b) + Vec2::splat(half + 1.)).ceil().as_ivec2().min(clip.max - 1);
+    if max.x < min.x || max.y < min.y {
+        return;
+    }
+
+    let ab = b - a;
+    let length_squared = ab.length_squared().max(f32::EPSILON);
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let t = ((p - a).dot(ab) / length_squared).clamp(0., 1.);
+            let distance = p.distance(a + ab * t);
+            let coverage = half + 0.5 - distance;
+            if coverage > 0. {
+                pixel(x, y, coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a tile with a lake in its south west corner and a road across it, see the tests in mvt.rs
+    fn fixture() -> Tile {
+        Tile::decode(include_bytes!(
+            "../../tests/fixtures/vector/14/2582/5917.mvt"
+        ))
+        .unwrap()
+    }
+
+    fn whole_chunk() -> OverlayRegion {
+        OverlayRegion {
+            tile_span: 1.,
+            origin: Vec2::ZERO,
+            pixels_per_metre: 1.,
+        }
+    }
+
+    #[test]
+    fn tiles_covering_a_chunk() {
+        let deeper = overlay_tiles(12, 645, 1479, 14, 1.);
+        assert_eq!(deeper.len(), 16);
+        assert_eq!(deeper[0].0, (14, 2580, 5916));
+        assert_eq!(deeper[6].0, (14, 2582, 5917));
+        assert_eq!(deeper[6].1.origin, Vec2::new(0.5, 0.25));
+        assert_eq!(deeper[6].1.tile_span, 0.25);
+        assert_eq!(deeper[6].1.clip(512), IRect::new(256, 128, 384, 256));
+
+        let coarser = overlay_tiles(14, 2582, 5917, 12, 1.);
+        assert_eq!(coarser.len(), 1);
+        assert_eq!(coarser[0].0, (12, 645, 1479));
+        assert_eq!(coarser[0].1.origin, -Vec2::new(2., 1.));
+        assert_eq!(coarser[0].1.tile_span, 4.);
+        assert_eq!(coarser[0].1.clip(512), IRect::new(0, 0, 512, 512));
+        assert_eq!(
+            overlay_tiles(14, 2582, 5917, 14, 1.)[0].1.origin,
+            Vec2::ZERO
+        );
+    }
+
+    #[test]
+    fn draws_water_and_roads() {
+        let image = rasterise_overlay(&[(fixture(), whole_chunk())], 512).unwrap();
+        assert_eq!(*image.get_pixel(50, 450), WATER);
+        //the road runs along y = 275 on the left half of the tile
+        let road = image.get_pixel(200, 275);
+        assert!(road[3] > 200 && road[0] > 200);
+        assert_eq!(image.get_pixel(300, 100)[3], 0);
+        //buildings are not part of the overlay
+        assert_eq!(image.get_pixel(180, 180)[3], 0);
+    }
+
+    #[test]
+    fn maps_land_use() {
+        let map = rasterise_land_use(&[(fixture(), whole_chunk())], 256).unwrap();
+        let at = |x: f32, y: f32| map.at((Vec2::new(x, y) / 4096. - 0.5) * 1000., 1000.);
+        assert_eq!(at(400., 3500.), LandUse::Water);
+        assert_eq!(at(1000., 2200.), LandUse::Road);
+        assert_eq!(at(3000., 1000.), LandUse::Unknown);
+        assert!(rasterise_land_use(&[(Tile::default(), whole_chunk())], 256).is_none());
+    }
+
+    //each sub-tile only draws its own part of the chunk, roads running up to a tile's edge aren't blended
+    //twice where they reach into the next one
+    #[test]
+    fn sub_tiles_meet_without_overlap() {
+        let tiles: Vec<(Tile, OverlayRegion)> = overlay_tiles(13, 1291, 2958, 14, 1.)
+            .into_iter()
+            .map(|(_, region)| (fixture(), region))
+            .collect();
+        let image = rasterise_overlay(&tiles, 512).unwrap();
+        for y in 0..256 {
+            for x in 0..256 {
+                let p = image.get_pixel(x, y);
+                assert_eq!(p, image.get_pixel(x + 256, y), "at {x}, {y}");
+                assert_eq!(p, image.get_pixel(x, y + 256), "at {x}, {y}");
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//Terrain height queries
+//Samples the meshes of loaded chunks so gameplay code sees exactly the ground that is drawn,
+//including edges stitched to neighbours. Areas without a loaded chunk are unknown.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+
+use crate::scene::{get_chunk_space_position, get_world_space_position};
+use crate::terrain::chunk_manager::ChunkManager;
+use crate::geo::GeoOrigin;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainSample {
+    //world space height of the ground
+    pub height: f32,
+    //world space surface normal
+    pub normal: Vec3,
+    //the ground is at or below sea level and covered by water
+    pub water: bool,
+}
+
+impl TerrainSample {
+    //angle between the ground and horizontal, in radians
+    pub fn slope(&self) -> f32 {
+        self.normal.angle_between(Vec3::Y)
+    }
+}
+
+#[derive(SystemParam)]
+pub struct TerrainQuery<'w> {
+    chunk_manager: Res<'w, ChunkManager>,
+    meshes: Res<'w, Assets<Mesh>>,
+    geo_origin: Res<'w, GeoOrigin>,
+}
+
+impl<'w> TerrainQuery<'w> {
+    //ground height and normal at world x, z, None if the terrain there isn't loaded
+    pub fn sample(&self, x: f32, z: f32) -> Option<TerrainSample> {
+        let chunk_size = self.geo_origin.chunk_size;
+        let chunk = get_chunk_space_position(Vec3::new(x, 0.0, z), chunk_size);
+        let coord = IVec2::new(chunk.x as i32, chunk.z as i32);
+        let heights = self.chunk_manager.heights(coord)?;
+        let mesh = self.meshes.get(&heights.mesh)?;
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return None;
+        };
+        let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else {
+            return None;
+        };
+
+        //position inside the chunk's vertex grid, chunks are centred on their world position
+        let res = heights.res;
+        let centre = get_world_space_position(chunk, chunk_size);
+        let step = chunk_size / (res - 1) as f32;
+        let gx = ((x - centre.x + chunk_size * 0.5) / step).clamp(0.0, (res - 1) as f32);
+        let gz = ((z - centre.z + chunk_size * 0.5) / step).clamp(0.0, (res - 1) as f32);
+
+        Some(TerrainSample {
+            height: lerp_grid(positions, res, gx, gz).y + centre.y,
+            normal: lerp_grid(normals, res, gx, gz).normalize_or_zero(),
+            water: heights.water.is_water(gx / (res - 1) as f32, gz / (res - 1) as f32),
+        })
+    }
+}
+
+//copy of a chunk's vertex grid, for placing things on the ground off the main thread
+pub struct GroundGrid {
+    positions: Vec<[f32; 3]>,
+    res: usize,
+    size: f32,
+}
+
+impl GroundGrid {
+    //the grid of a loaded chunk, skirts left out
+    pub fn from_chunk(chunk_manager: &ChunkManager, meshes: &Assets<Mesh>, coord: IVec2, chunk_size: f32) -> Option<Self> {
+        let heights = chunk_manager.heights(coord)?;
+        let Some(VertexAttributeValues::Float32x3(positions)) = meshes.get(&heights.mesh)?.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return None;
+        };
+        let res = heights.res;
+        Some(Self {
+            positions: positions.get(..res * res)?.to_vec(),
+            res,
+            size: chunk_size,
+        })
+    }
+
+    //level ground at one height, for tests that don't load a chunk
+    #[cfg(test)]
+    pub fn flat(height: f32, res: usize, chunk_size: f32) -> Self {
+        Self::from_fn(res, chunk_size, |_| height)
+    }
+
+    //ground with the height given for each vertex's position relative to the chunk's centre
+    #[cfg(test)]
+    pub fn from_fn(res: usize, chunk_size: f32, height: impl Fn(Vec2) -> f32) -> Self {
+        let step = chunk_size / (res - 1) as f32;
+        let positions = (0..res * res)
+            .map(|i| {
+                let local = Vec2::new((i % res) as f32 * step, (i / res) as f32 * step) - chunk_size * 0.5;
+                [local.x, height(local), local.y]
+            })
+            .collect();
+        Self {
+            positions,
+            res,
+            size: chunk_size,
+        }
+    }
+
+    //ground height at a position relative to the chunk's centre, clamped to the chunk
+    pub fn height(&self, local: Vec2) -> f32 {
+        let step = self.size / (self.res - 1) as f32;
+        let max = (self.res - 1) as f32;
+        let gx = ((local.x + self.size * 0.5) / step).clamp(0.0, max);
+        let gz = ((local.y + self.size * 0.5) / step).clamp(0.0, max);
+        lerp_grid(&self.positions, self.res, gx, gz).y
+    }
+}
+
+//value stored per vertex of a res x res chunk grid at grid position gx, gz. Quads are split along the
+//diagonal the same way generate_mesh does, so heights match the drawn triangles
+pub fn lerp_grid(values: &[[f32; 3]], res: usize, gx: f32, gz: f32) -> Vec3 {
+    let ix = (gx.floor() as usize).min(res - 2);
+    let iz = (gz.floor() as usize).min(res - 2);
+    let fx = gx - ix as f32;
+    let fz = gz - iz as f32;
+
+    let a = Vec3::from(values[iz * res + ix]);
+    let b = Vec3::from(values[iz * res + ix + 1]);
+    let c = Vec3::from(values[(iz + 1) * res + ix]);
+    let d = Vec3::from(values[(iz + 1) * res + ix + 1]);
+    if fx >= fz {
+        a + (b - a) * fx + (d - b) * fz
+    } else {
+        a + (c - a) * fz + (d - c) * fx
+    }
+}
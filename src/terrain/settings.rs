@@ -0,0 +1,165 @@
+//Terrain settings
+//Loaded from the .env file at startup and adjustable at runtime from the pause screen.
+//Changing the zoom rebuilds the whole world around the player's current lat/lon,
+//changing the resolution or exaggeration regenerates the loaded chunks in place.
+
+use bevy::prelude::*;
+use std::env;
+use std::str::FromStr;
+
+use crate::geo::GeoOrigin;
+use crate::player::Player;
+use crate::terrain::chunk_manager::{ChunkManager, ChunkStateChanged};
+use crate::ui::PauseState;
+
+const DEFAULT_CHUNK_RES: usize = 512;
+const DEFAULT_VIEW_DISTANCE: u32 = 8;
+const DEFAULT_ZOOM: u32 = 12;
+const DEFAULT_EXAGGERATION: f32 = 1.;
+const DEFAULT_MAX_LOADS: usize = 8;
+const DEFAULT_MAX_UPLOADS: usize = 2;
+
+const MIN_VIEW_DISTANCE: u32 = 2;
+const MAX_VIEW_DISTANCE: u32 = 32;
+const MIN_CHUNK_RES: usize = 32;
+const MAX_CHUNK_RES: usize = 1024;
+const MIN_ZOOM: u32 = 1;
+const MAX_ZOOM: u32 = 15;
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct TerrainSettings {
+    //vertices along one edge of a full resolution chunk
+    pub chunk_res: usize,
+    //chunks along one edge of the loaded area
+    pub view_distance: u32,
+    //slippy map zoom level chunks are fetched at
+    pub zoom: u32,
+    //multiplies real terrain heights, 1 is true to life
+    pub exaggeration: f32,
+    //chunk fetches allowed to run at once, the rest wait in the load queue
+    pub max_loads: usize,
+    //finished chunk meshes applied per frame, the rest wait for the next frames
+    pub max_uploads: usize,
+}
+
+impl TerrainSettings {
+    pub fn from_env() -> Self {
+        Self {
+            chunk_res: env_or("TERRAIN_CHUNK_RES", DEFAULT_CHUNK_RES)
+                .clamp(MIN_CHUNK_RES, MAX_CHUNK_RES),
+            view_distance: env_or("TERRAIN_VIEW_DISTANCE", DEFAULT_VIEW_DISTANCE)
+                .clamp(MIN_VIEW_DISTANCE, MAX_VIEW_DISTANCE),
+            zoom: env_or("TERRAIN_ZOOM", DEFAULT_ZOOM).clamp(MIN_ZOOM, MAX_ZOOM),
+            exaggeration: env_or("TERRAIN_EXAGGERATION", DEFAULT_EXAGGERATION).max(0.),
+            max_loads: env_or("TERRAIN_MAX_LOADS", DEFAULT_MAX_LOADS).max(1),
+            max_uploads: env_or("TERRAIN_MAX_UPLOADS", DEFAULT_MAX_UPLOADS).max(1),
+        }
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        (self.view_distance * self.view_distance) as usize
+    }
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+pub(crate) fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            println!("could not parse {key}, using the default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+//pause screen controls for the settings
+pub fn adjust_terrain_settings(
+    keys: Res<ButtonInput<KeyCode>>,
+    pause: Res<PauseState>,
+    mut settings: ResMut<TerrainSettings>,
+) {
+    if !pause.is_paused {
+        return;
+    }
+    let mut new = *settings;
+    if keys.just_pressed(KeyCode::Digit1) {
+        new.view_distance = new.view_distance.saturating_sub(2).max(MIN_VIEW_DISTANCE);
+    }
+    if keys.just_pressed(KeyCode::Digit2) {
+        new.view_distance = (new.view_distance + 2).min(MAX_VIEW_DISTANCE);
+    }
+    if keys.just_pressed(KeyCode::Digit3) {
+        new.chunk_res = (new.chunk_res / 2).max(MIN_CHUNK_RES);
+    }
+    if keys.just_pressed(KeyCode::Digit4) {
+        new.chunk_res = (new.chunk_res * 2).min(MAX_CHUNK_RES);
+    }
+    if keys.just_pressed(KeyCode::Digit5) {
+        new.exaggeration *= 0.8;
+    }
+    if keys.just_pressed(KeyCode::Digit6) {
+        new.exaggeration *= 1.25;
+    }
+    if keys.just_pressed(KeyCode::Digit7) {
+        new.zoom = new.zoom.saturating_sub(1).max(MIN_ZOOM);
+    }
+    if keys.just_pressed(KeyCode::Digit8) {
+        new.zoom = (new.zoom + 1).min(MAX_ZOOM);
+    }
+    //only touch the resource when something changed so change detection stays quiet
+    if new != *settings {
+        *settings = new;
+    }
+}
+
+//work out which chunks a settings change affects and throw them away
+pub fn apply_terrain_settings(
+    settings: Res<TerrainSettings>,
+    mut applied: Local<Option<TerrainSettings>>,
+    mut geo_origin: ResMut<GeoOrigin>,
+    mut chunk_manager: ResMut<ChunkManager>,
+    mut chunk_events: EventWriter<ChunkStateChanged>,
+    mut player_q: Query<&mut Transform, With<Player>>,
+) {
+    let Some(old) = *applied else {
+        *applied = Some(*settings);
+        return;
+    };
+    if old == *settings {
+        return;
+    }
+    *applied = Some(*settings);
+    info!("terrain settings changed to {:?}", *settings);
+
+    if old.zoom != settings.zoom {
+        //the world moves under the player, keep them over the same spot on earth
+        let mut player = player_q.get_single_mut().ok();
+        let point = player
+            .as_ref()
+            .map(|transform| geo_origin.world_to_lat_lon(transform.translation));
+
+        let new_origin = match point {
+            Some(point) => GeoOrigin::containing(point, settings.zoom),
+            //no plane yet, stay over the same tile
+            None => {
+                let shift = settings.zoom as i32 - old.zoom as i32;
+                let scale = |tile: i32| if shift >= 0 { tile << shift } else { tile >> -shift };
+                GeoOrigin::new(settings.zoom, scale(geo_origin.tile_x), scale(geo_origin.tile_y))
+            }
+        };
+        if let (Some(point), Some(transform)) = (point, player.as_mut()) {
+            let position = new_origin.lat_lon_to_world(point);
+            transform.translation.x = position.x;
+            transform.translation.z = position.z;
+        }
+        *geo_origin = new_origin;
+        chunk_manager.evict_all(&mut chunk_events);
+    } else if old.chunk_res != settings.chunk_res || old.exaggeration != settings.exaggeration {
+        chunk_manager.invalidate();
+    }
+}
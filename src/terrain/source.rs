@@ -0,0 +1,314 @@
+//Terrain tile sources
+//Every source hands back terrarium encoded elevation tiles addressed by slippy map z/x/y,
+//so the chunk mesh path does not care where the data came from.
+//Imagery tiles go through the same trait, see terrain::imagery.
+//Terrarium encoding: height = (red * 256 + green + blue / 256) - 32768
+
+use bevy::math::DVec2;
+use bevy::prelude::*;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::geo::{tiles_at_zoom, EARTH_CIRCUMFERENCE};
+use crate::terrain::cache::{CachedTileSource, TerrainTileCache};
+use crate::terrain::dem::DemTileSource;
+use crate::terrain::fetch::{TerrainFetchError, DOWNLOADER};
+use crate::terrain::noise::TerrainNoise;
+
+pub const TILE_SIZE: u32 = 512;
+
+const DEFAULT_NEXTZEN_URL: &str =
+    "https://tile.nextzen.org/tilezen/terrain/v1/{tilesize}/terrarium/{z}/{x}/{y}.png?api_key={api_key}";
+const DEFAULT_TILE_DIR: &str = "./tiles";
+
+pub trait TerrainTileSource: Send + Sync {
+    //short name used in logs
+    fn name(&self) -> &str;
+
+    //url template of sources on a tile server, tiles from different servers are cached apart
+    fn url_template(&self) -> Option<&str> {
+        None
+    }
+
+    //fetch the terrarium tile at z/x/y, NoData if the source has nothing for it
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError>;
+
+    //whether tiles from this source should go through the disk cache
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+//Nextzen (or any server speaking the same url scheme) over http
+//API INFO:
+//https://www.nextzen.org/
+//https://developers.nextzen.org/about.html
+pub struct NextzenTileSource {
+    pub url_template: String,
+    pub api_key: Option<String>,
+}
+
+impl NextzenTileSource {
+    pub fn from_env() -> Self {
+        Self {
+            url_template: env::var("TERRAIN_TILE_URL").unwrap_or(DEFAULT_NEXTZEN_URL.to_string()),
+            api_key: env::var("Nextzen_API").ok(),
+        }
+    }
+
+    //a custom tile server does not need a nextzen key
+    pub fn has_key(&self) -> bool {
+        self.api_key.is_some() || !self.url_template.contains("{api_key}")
+    }
+
+    fn tile_url(&self, z: u32, x: u32, y: u32) -> String {
+        self.url_template
+            .replace("{tilesize}", &TILE_SIZE.to_string())
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())
+            .replace("{api_key}", self.api_key.as_deref().unwrap_or(""))
+    }
+}
+
+impl TerrainTileSource for NextzenTileSource {
+    fn name(&self) -> &str {
+        "nextzen"
+    }
+
+    fn url_template(&self) -> Option<&str> {
+        Some(&self.url_template)
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        if !self.has_key() {
+            return Err(TerrainFetchError::MissingApiKey);
+        }
+        DOWNLOADER.fetch_image(&self.tile_url(z, x, y))
+    }
+}
+
+//tiles laid out on disk as {root}/{z}/{x}/{y}.png
+pub struct LocalTileSource {
+    pub root: PathBuf,
+}
+
+impl LocalTileSource {
+    pub fn from_env() -> Self {
+        Self {
+            root: PathBuf::from(env::var("TERRAIN_TILE_DIR").unwrap_or(DEFAULT_TILE_DIR.to_string())),
+        }
+    }
+}
+
+impl TerrainTileSource for LocalTileSource {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    //already on disk
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        let path = self.root.join(format!("{z}/{x}/{y}.png"));
+        if !path.exists() {
+            return Err(TerrainFetchError::NoData);
+        }
+        image::open(&path).map_err(|e| TerrainFetchError::Decode { what: path.display().to_string(), message: e.to_string() })
+    }
+}
+
+//seeded noise terrain, see terrain::noise, needs no network and no files
+pub struct ProceduralTileSource {
+    pub noise: TerrainNoise,
+}
+
+impl ProceduralTileSource {
+    pub fn from_env() -> Self {
+        let seed = env::var("TERRAIN_SEED")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        Self {
+            noise: TerrainNoise::new(seed),
+        }
+    }
+}
+
+impl TerrainTileSource for ProceduralTileSource {
+    fn name(&self) -> &str {
+        "procedural"
+    }
+
+    //cheaper to regenerate than to read back
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<DynamicImage, TerrainFetchError> {
+        //heights are sampled at pixel centres in mercator metres, the same at every zoom level
+        let metres_per_tile = EARTH_CIRCUMFERENCE / tiles_at_zoom(z);
+        let img = RgbImage::from_fn(TILE_SIZE, TILE_SIZE, |px, py| {
+            let tile = DVec2::new(x as f64, y as f64) + (DVec2::new(px as f64, py as f64) + 0.5) / TILE_SIZE as f64;
+            encode_terrarium(self.noise.height(tile * metres_per_tile))
+        });
+        Ok(DynamicImage::ImageRgb8(img))
+    }
+}
+
+pub fn encode_terrarium(height: f32) -> Rgb<u8> {
+    let v = (height + 32768.).clamp(0., 65535.996);
+    let r = (v / 256.).floor();
+    let g = (v - r * 256.).floor();
+    let b = ((v - r * 256. - g) * 256.).floor();
+    Rgb([r as u8, g as u8, b as u8])
+}
+
+//without a key nextzen has nothing to give, generated terrain beats an empty world
+fn nextzen_or_procedural() -> Arc<dyn TerrainTileSource> {
+    let nextzen = NextzenTileSource::from_env();
+    if nextzen.has_key() {
+        return Arc::new(nextzen);
+    }
+    println!("no Nextzen_API key set, using procedural terrain instead. Read README.md for more details.");
+    Arc::new(ProceduralTileSource::from_env())
+}
+
+//picks the tile source from the TERRAIN_SOURCE env var (nextzen, local, procedural or dem)
+#[derive(Resource, Clone)]
+pub struct TerrainSource(pub Arc<dyn TerrainTileSource>);
+
+impl FromWorld for TerrainSource {
+    fn from_world(world: &mut World) -> Self {
+        let kind = env::var("TERRAIN_SOURCE").unwrap_or("nextzen".to_string());
+        let source: Arc<dyn TerrainTileSource> = match kind.trim().to_lowercase().as_str() {
+            "local" => Arc::new(LocalTileSource::from_env()),
+            "procedural" => Arc::new(ProceduralTileSource::from_env()),
+            "dem" => Arc::new(DemTileSource::from_env()),
+            "nextzen" => nextzen_or_procedural(),
+            other => {
+                println!("unknown TERRAIN_SOURCE {other}, falling back to nextzen");
+                nextzen_or_procedural()
+            }
+        };
+        info!("using {} terrain tile source", source.name());
+
+        if !source.cacheable() {
+            return TerrainSource(source);
+        }
+        let cache = world
+            .get_resource_or_insert_with(TerrainTileCache::default)
+            .0
+            .clone();
+        TerrainSource(Arc::new(CachedTileSource::new(source, cache)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(img: &DynamicImage) -> Vec<f32> {
+        img.to_rgb8()
+            .pixels()
+            .map(|p| p[0] as f32 * 256. + p[1] as f32 + p[2] as f32 / 256. - 32768.)
+            .collect()
+    }
+
+    fn at(heights: &[f32], px: u32, py: u32) -> f32 {
+        heights[(py * TILE_SIZE + px) as usize]
+    }
+
+    #[test]
+    fn terrarium_round_trip() {
+        for h in [-120., 0., 0.5, 76.25, 4392.] {
+            let p = encode_terrarium(h);
+            let decoded = p[0] as f32 * 256. + p[1] as f32 + p[2] as f32 / 256. - 32768.;
+            assert!(
+                (decoded - h).abs() < 1. / 256.,
+                "{h} came back as {decoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn procedural_tiles_are_deterministic() {
+        let (a, b) = (
+            ProceduralTileSource {
+                noise: TerrainNoise::new(5),
+            },
+            ProceduralTileSource {
+                noise: TerrainNoise::new(5),
+            },
+        );
+        let tile = a.fetch_tile(12, 650, 1470).unwrap();
+        assert_eq!(
+            tile.as_bytes(),
+            b.fetch_tile(12, 650, 1470).unwrap().as_bytes()
+        );
+        assert_ne!(
+            tile.as_bytes(),
+            ProceduralTileSource {
+                noise: TerrainNoise::new(6)
+            }
+            .fetch_tile(12, 650, 1470)
+            .unwrap()
+            .as_bytes()
+        );
+    }
+
+    //neighbouring tiles sample one surface, across the edge heights change no more than inside a tile
+    #[test]
+    fn procedural_tiles_meet_without_seams() {
+        let source = ProceduralTileSource {
+            noise: TerrainNoise::new(0),
+        };
+        let (x, y) = (650, 1470);
+        let centre = heights(&source.fetch_tile(12, x, y).unwrap());
+        let east = heights(&source.fetch_tile(12, x + 1, y).unwrap());
+        let south = heights(&source.fetch_tile(12, x, y + 1).unwrap());
+
+        let last = TILE_SIZE - 1;
+        let steepest = (0..TILE_SIZE)
+            .flat_map(|i| {
+                [
+                    (at(&centre, last, i) - at(&centre, last - 1, i)).abs(),
+                    (at(&centre, i, last) - at(&centre, i, last - 1)).abs(),
+                ]
+            })
+            .fold(0., f32::max);
+        for i in 0..TILE_SIZE {
+            assert!(
+                (at(&east, 0, i) - at(&centre, last, i)).abs() <= steepest * 2. + 0.5,
+                "seam to the east at row {i}"
+            );
+            assert!(
+                (at(&south, i, 0) - at(&centre, i, last)).abs() <= steepest * 2. + 0.5,
+                "seam to the south at column {i}"
+            );
+        }
+    }
+
+    //every zoom level samples the same surface
+    #[test]
+    fn procedural_tiles_match_the_noise() {
+        let source = ProceduralTileSource {
+            noise: TerrainNoise::new(0),
+        };
+        for (z, x, y) in [(10, 162, 367), (12, 650, 1470)] {
+            let tile = heights(&source.fetch_tile(z, x, y).unwrap());
+            let metres_per_tile = EARTH_CIRCUMFERENCE / tiles_at_zoom(z);
+            for (px, py) in [(0, 0), (100, 400), (511, 511)] {
+                let position = (DVec2::new(x as f64, y as f64)
+                    + (DVec2::new(px as f64, py as f64) + 0.5) / TILE_SIZE as f64)
+                    * metres_per_tile;
+                let expected = source.noise.height(position);
+                assert!((at(&tile, px, py) - expected).abs() < 1. / 256.);
+            }
+        }
+    }
+}
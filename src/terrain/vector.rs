@@ -0,0 +1,197 @@
+//Vector tiles
+//Mapbox Vector Tiles on the same slippy map grid as the elevation tiles, holding the buildings, roads,
+//land use and water of an area. They come from Nextzen (or any server speaking the same url scheme)
+//or from a local directory of .mvt files. Downloaded tiles go through the disk cache in their own layer.
+
+use bevy::prelude::*;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::terrain::cache::{source_layer, TerrainTileCache, TileCache, TileKey};
+use crate::terrain::fetch::{redact_url, TerrainFetchError, DOWNLOADER};
+use crate::terrain::mvt::Tile;
+use crate::terrain::settings::env_or;
+
+const DEFAULT_VECTOR_URL: &str = "https://tile.nextzen.org/tilezen/vector/v1/512/all/{z}/{x}/{y}.mvt?api_key={api_key}";
+//buildings only show up in nextzen tiles from about zoom 13
+const DEFAULT_VECTOR_ZOOM: u32 = 14;
+
+pub trait VectorTileSource: Send + Sync {
+    //short name used in logs
+    fn name(&self) -> &str;
+
+    //fetch and decode the tile at z/x/y, NoData if the source has nothing for it
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<Tile, TerrainFetchError>;
+}
+
+fn decode_tile(bytes: &[u8], what: impl FnOnce() -> String) -> Result<Tile, TerrainFetchError> {
+    Tile::decode(bytes).map_err(|message| TerrainFetchError::Decode { what: what(), message })
+}
+
+//any vector tile server taking {z}, {x} and {y}, plus an optional {api_key}
+pub struct HttpVectorSource {
+    pub url_template: String,
+    pub api_key: Option<String>,
+    pub cache: Option<Arc<TileCache>>,
+}
+
+impl HttpVectorSource {
+    fn tile_url(&self, z: u32, x: u32, y: u32) -> String {
+        self.url_template
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())
+            .replace("{api_key}", self.api_key.as_deref().unwrap_or(""))
+    }
+}
+
+impl VectorTileSource for HttpVectorSource {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    //only tiles that decoded fine are stored, a cached tile that no longer decodes is thrown away
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<Tile, TerrainFetchError> {
+        let key = TileKey {
+            layer: source_layer("vector", self.name(), Some(&self.url_template)),
+            z,
+            x,
+            y,
+            ext: "mvt",
+        };
+        if let Some(cache) = &self.cache {
+            if let Some(bytes) = cache.read(&key) {
+                match Tile::decode(&bytes) {
+                    Ok(tile) => return Ok(tile),
+                    Err(_) => {
+                        println!("discarding corrupt cached vector tile {z}/{x}/{y}");
+                        cache.remove(&key);
+                    }
+                }
+            }
+        }
+
+        let url = self.tile_url(z, x, y);
+        let bytes = DOWNLOADER.fetch_bytes(&url)?;
+        let tile = decode_tile(&bytes, || redact_url(&url))?;
+        if let Some(cache) = &self.cache {
+            cache.write(&key, &bytes);
+        }
+        Ok(tile)
+    }
+}
+
+//tiles laid out on disk as {root}/{z}/{x}/{y}.mvt
+pub struct LocalVectorSource {
+    pub root: PathBuf,
+}
+
+impl VectorTileSource for LocalVectorSource {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn fetch_tile(&self, z: u32, x: u32, y: u32) -> Result<Tile, TerrainFetchError> {
+        let path = self.root.join(format!("{z}/{x}/{y}.mvt"));
+        let bytes = fs::read(&path).map_err(|_| TerrainFetchError::NoData)?;
+        decode_tile(&bytes, || path.display().to_string())
+    }
+}
+
+//vector source picked from VECTOR_TILE_DIR or VECTOR_TILE_URL, None leaves the world without buildings
+//and overlays. Tiles are fetched at VECTOR_TILE_ZOOM, which can be deeper than the terrain zoom
+#[derive(Resource, Clone)]
+pub struct VectorSource {
+    pub source: Option<Arc<dyn VectorTileSource>>,
+    pub zoom: u32,
+}
+
+impl VectorSource {
+    //zoom to fetch vector tiles at for chunks at the given terrain zoom, at most 3 levels deeper
+    //so a chunk never needs more than 64 tiles
+    pub fn zoom_for(&self, terrain_zoom: u32) -> u32 {
+        self.zoom.clamp(terrain_zoom, terrain_zoom + 3)
+    }
+}
+
+impl FromWorld for VectorSource {
+    fn from_world(world: &mut World) -> Self {
+        let zoom = env_or("VECTOR_TILE_ZOOM", DEFAULT_VECTOR_ZOOM);
+        let source: Arc<dyn VectorTileSource> = if let Ok(root) = env::var("VECTOR_TILE_DIR") {
+            Arc::new(LocalVectorSource {
+                root: PathBuf::from(root),
+            })
+        } else {
+            let url_template = env::var("VECTOR_TILE_URL").unwrap_or(DEFAULT_VECTOR_URL.to_string());
+            let api_key = env::var("Nextzen_API").ok();
+            if api_key.is_none() && url_template.contains("{api_key}") {
+                println!("no Nextzen_API key set, the world will have no buildings or map overlays. Read README.md for more details.");
+                return VectorSource { source: None, zoom };
+            }
+            let cache = world
+                .get_resource_or_insert_with(TerrainTileCache::default)
+                .0
+                .clone();
+            Arc::new(HttpVectorSource {
+                url_template,
+                api_key,
+                cache: Some(cache),
+            })
+        };
+        info!("using {} vector tile source", source.name());
+        VectorSource {
+            source: Some(source),
+            zoom,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn fixtures() -> LocalVectorSource {
+        LocalVectorSource {
+            root: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/vector"),
+        }
+    }
+
+    #[test]
+    fn reads_local_tiles() {
+        let tile = fixtures().fetch_tile(14, 2582, 5917).unwrap();
+        assert_eq!(tile.layers.len(), 3);
+        assert!(matches!(
+            fixtures().fetch_tile(14, 2582, 5918),
+            Err(TerrainFetchError::NoData)
+        ));
+    }
+
+    #[test]
+    fn broken_local_tiles_name_their_file() {
+        let dir = TempDir::new("vector-broken");
+        fs::create_dir_all(dir.join("3/1")).unwrap();
+        fs::write(dir.join("3/1/2.mvt"), [0x1a, 0x40, 0x0a]).unwrap();
+        let source = LocalVectorSource { root: dir.path().to_path_buf() };
+        match source.fetch_tile(3, 1, 2) {
+            Err(TerrainFetchError::Decode { what, .. }) => assert!(what.ends_with("2.mvt")),
+            other => panic!(
+                "expected a decode error, got {:?}",
+                other.map(|tile| tile.layers.len())
+            ),
+        }
+    }
+
+    #[test]
+    fn vector_zoom_stays_near_the_terrain_zoom() {
+        let source = VectorSource {
+            source: None,
+            zoom: 14,
+        };
+        assert_eq!(source.zoom_for(12), 14);
+        assert_eq!(source.zoom_for(10), 13);
+        assert_eq!(source.zoom_for(15), 15);
+    }
+}
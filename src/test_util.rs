@@ -0,0 +1,37 @@
+//Test helpers
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static NEXT_DIR: AtomicU32 = AtomicU32::new(0);
+
+//an empty directory of its own under the system temp directory, removed with everything in it when
+//dropped. Tests run in parallel, the process id and a counter keep their directories apart
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let n = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("{}-{name}-{}-{n}", env!("CARGO_PKG_NAME"), process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
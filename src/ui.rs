@@ -1,4 +1,10 @@
-use crate::player::MovementSettings;
+use crate::airports::db::{bearing, Airports};
+use crate::collision::{CrashState, Surface};
+use crate::geo::GeoOrigin;
+use crate::player::{MovementSettings, Player};
+use crate::sky::SimClock;
+use crate::terrain::query::TerrainQuery;
+use crate::terrain::settings::TerrainSettings;
 use bevy::window::PrimaryWindow;
 use bevy::{prelude::*, window::CursorGrabMode};
 use bevy_third_person_camera::ThirdPersonCamera;
@@ -52,11 +58,67 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 }
 
+//nearest airport shown in the HUD, searched for this far out in metres
+const NEAREST_AIRPORT_RANGE: f64 = 100_000.;
+
+#[allow(clippy::too_many_arguments)]
 fn text_update_system(
     player: Res<MovementSettings>,
     mut query: Query<&mut Text, With<InformationTextBox>>,
     keys: Res<ButtonInput<KeyCode>>,
+    player_q: Query<&Transform, With<Player>>,
+    geo_origin: Res<GeoOrigin>,
+    terrain: TerrainQuery,
+    crash: Res<CrashState>,
+    airports: Res<Airports>,
+    clock: Res<SimClock>,
 ) {
+    if let Some(crash) = crash.0 {
+        let title = match crash.surface {
+            Surface::Ground => "CRASHED",
+            Surface::Water => "DITCHED",
+        };
+        for mut text in &mut query {
+            text.sections[0].value = format!(
+                "
+            {}\n
+            {}\n
+            Impact Speed(m/s) {:.0}\n
+            Sink Rate(m/s) {:.1}\n
+            Pitch {:.0} Roll {:.0}\n
+            Restart: R",
+                title,
+                crash.reason(),
+                crash.speed,
+                crash.sink_rate,
+                crash.pitch,
+                crash.roll
+            );
+        }
+        return;
+    }
+    let (position, ground) = match player_q.get_single() {
+        Ok(transform) => (
+            geo_origin.world_to_lat_lon(transform.translation),
+            terrain.sample(transform.translation.x, transform.translation.z)
+                .map(|sample| (transform.translation.y - sample.height, sample.slope())),
+        ),
+        Err(_) => return,
+    };
+    //unloaded terrain has no known height
+    let ground = match ground {
+        Some((height, slope)) => format!("{:.0} m, slope {:.0} deg", height, slope.to_degrees()),
+        None => "unknown".to_string(),
+    };
+    let nearest = match airports.0.nearest(position, NEAREST_AIRPORT_RANGE) {
+        Some((airport, distance)) => format!(
+            "{} {:.1} km, bearing {:03.0}",
+            airport.ident,
+            distance / 1000.,
+            bearing(position, airport.point)
+        ),
+        None => "none".to_string(),
+    };
     for mut text in &mut query {
         let current_force = player.thrust_force;
         let percent_force = ((current_force / player.thrust_force_max) * 100.) as i32;
@@ -68,6 +130,10 @@ fn text_update_system(
             Speed(m/s) {}\n
             Flaps {}\n
             Flaps Angle {}\n
+            Lat {:.4} Lon {:.4}\n
+            Above Ground {}\n
+            Nearest Airport {}\n
+            Time {} x{}\n
             Angle Up/Down: W / S
             Roll Angle: Q / E
             Flaps Angle Control: Arrows
@@ -76,7 +142,13 @@ fn text_update_system(
             percent_force,
             speed,
             player.flaps_enabled,
-            player.flaps_angle * 180.0 / 3.14
+            player.flaps_angle * 180.0 / 3.14,
+            position.lat,
+            position.lon,
+            ground,
+            nearest,
+            clock.label(),
+            clock.speed
         );
 
         text.sections[0].value = output.to_string();
@@ -88,6 +160,7 @@ fn pause_update(
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
     mut tpc: Query<&mut ThirdPersonCamera>,
     mut query: Query<&mut Text, With<InformationTextBox>>,
+    settings: Res<TerrainSettings>,
 ) {
     if pause.is_paused {
         let mut window = &mut primary_window.single_mut();
@@ -98,7 +171,17 @@ fn pause_update(
         for mut text in &mut query {
             let output = format!(
                 "
-            Pause",
+            Pause\n
+            Clear Tile Cache: C
+            Export Terrain: P
+            View Distance {}: 1 / 2
+            Chunk Resolution {}: 3 / 4
+            Terrain Exaggeration {:.2}: 5 / 6
+            Zoom {}: 7 / 8",
+                settings.view_distance,
+                settings.chunk_res,
+                settings.exaggeration,
+                settings.zoom
             );
             text.sections[0].value = output.to_string();
         }
@@ -0,0 +1,673 @@
+//Vegetation
+//Trees and bushes are scattered over the chunks near the plane so height and speed can be judged low
+//down. Every chunk is covered by a jittered grid of candidate spots, and each spot rolls its own random
+//numbers from a hash of the chunk's tile and the spot, so a chunk always grows the same plants. Spots are
+//kept by land use, slope and elevation, and never on water or runways.
+//Plants are instances of a few low poly shapes. Each chunk gets one entity per shape, a child of the chunk
+//entity like its buildings, that draws all of the chunk's plants of that shape in one instanced draw call
+//(see instancing.rs), so thousands of plants don't need thousands of entities.
+//Density drops with distance from the plane: every spot has a fixed roll that is compared with the
+//density, so thinning out removes plants without moving the rest.
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::primitives::Aabb;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use bevy::utils::HashMap;
+use futures_lite::future;
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+
+use crate::airports::runway::{chunk_runways, ChunkRunway};
+use crate::airports::Airports;
+use crate::geo::GeoOrigin;
+use crate::instancing::{MeshInstance, MeshInstances};
+use crate::player::Player;
+use crate::scene::{get_world_space_position, handle_terrain_data_threads, ChunkComponent};
+use crate::terrain::chunk_manager::{ChunkManager, ChunkState, ChunkStateChanged};
+use crate::terrain::overlay::{LandUse, LandUseMap};
+use crate::terrain::query::GroundGrid;
+use crate::terrain::settings::{env_or, TerrainSettings};
+use crate::water::SEA_LEVEL;
+
+//metres between candidate spots
+const SPOT_SPACING: f32 = 25.;
+//caps the spots on big chunks, at the default zoom chunks are several km wide
+const MAX_SPOTS_PER_SIDE: u32 = 128;
+//chunks at this lod or finer get vegetation, coarser meshes are too far off the real ground
+const MAX_VEGETATION_LOD: u32 = 1;
+//metres out to which there is vegetation, density halves twice on the way out
+const DEFAULT_VEGETATION_DISTANCE: f32 = 12000.;
+//fraction of VEGETATION_DISTANCE and density for each band, closest first
+const DENSITY_BANDS: [(f32, f32); 3] = [(0.25, 1.), (0.5, 0.5), (1., 0.25)];
+
+//slope, 1 - normal.y, where plants start thinning out and where there are none left
+const SLOPE_START: f32 = 0.15;
+const SLOPE_END: f32 = 0.35;
+//metres above sea level of the tree line and where bushes give out
+const TREE_LINE: f32 = 2000.;
+const BUSH_LINE: f32 = 2800.;
+//metres kept clear around runways
+const RUNWAY_CLEARANCE: f32 = 60.;
+
+pub struct VegetationPlugin;
+
+impl Plugin for VegetationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VegetationSettings>()
+            .init_resource::<ChunkVegetation>()
+            .add_systems(Startup, setup_vegetation)
+            .add_systems(
+                Update,
+                (update_chunk_vegetation, handle_vegetation_tasks)
+                    .chain()
+                    .after(handle_terrain_data_threads),
+            );
+    }
+}
+
+//VEGETATION_DISTANCE in metres, 0 turns vegetation off
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct VegetationSettings {
+    pub distance: f32,
+}
+
+impl Default for VegetationSettings {
+    fn default() -> Self {
+        Self {
+            distance: env_or("VEGETATION_DISTANCE", DEFAULT_VEGETATION_DISTANCE).max(0.),
+        }
+    }
+}
+
+impl VegetationSettings {
+    //density for a chunk whose nearest point is this many metres from the plane
+    fn density(&self, distance: f32) -> f32 {
+        DENSITY_BANDS
+            .iter()
+            .find(|(fraction, _)| distance <= self.distance * fraction)
+            .map_or(0., |(_, density)| *density)
+    }
+}
+
+#[derive(Component)]
+pub struct Vegetation;
+
+#[derive(Component)]
+pub struct GenVegetation(Task<(Entity, ChunkPlants)>);
+
+//plant entities, running task and density of each chunk entity
+#[derive(Resource, Default)]
+pub struct ChunkVegetation {
+    plants: HashMap<Entity, Vec<Entity>>,
+    tasks: HashMap<Entity, Entity>,
+    //density the chunk's plants were or are being placed with, missing if they need placing
+    density: HashMap<Entity, f32>,
+    //indexed by Plant
+    shapes: Vec<Handle<Mesh>>,
+}
+
+impl ChunkVegetation {
+    //drop the task entity, which drops the task and stops it
+    fn cancel(&mut self, commands: &mut Commands, chunk: Entity) {
+        if let Some(task) = self.tasks.remove(&chunk) {
+            commands.entity(task).despawn();
+        }
+    }
+
+    fn clear(&mut self, commands: &mut Commands, chunk: Entity) {
+        self.cancel(commands, chunk);
+        self.despawn_plants(commands, chunk);
+    }
+
+    fn despawn_plants(&mut self, commands: &mut Commands, chunk: Entity) {
+        for entity in self.plants.remove(&chunk).into_iter().flatten() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+//leaf and bark colours come from vertex colours, the instancing shader does the lighting
+fn setup_vegetation(mut vegetation: ResMut<ChunkVegetation>, mut meshes: ResMut<Assets<Mesh>>) {
+    vegetation.shapes = PLANT_SHAPES.iter().map(|shape| meshes.add(shape.mesh())).collect();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_chunk_vegetation(
+    mut commands: Commands,
+    mut chunk_events: EventReader<ChunkStateChanged>,
+    mut removed_chunks: RemovedComponents<ChunkComponent>,
+    mut vegetation: ResMut<ChunkVegetation>,
+    player_q: Query<&Transform, With<Player>>,
+    meshes: Res<Assets<Mesh>>,
+    chunk_manager: Res<ChunkManager>,
+    geo_origin: Res<GeoOrigin>,
+    terrain_settings: Res<TerrainSettings>,
+    settings: Res<VegetationSettings>,
+    airports: Res<Airports>,
+) {
+    for chunk in removed_chunks.read() {
+        vegetation.cancel(&mut commands, chunk);
+        vegetation.plants.remove(&chunk);
+        vegetation.density.remove(&chunk);
+    }
+
+    for event in chunk_events.read() {
+        match event.state {
+            //new ground, the plants have to be placed again
+            ChunkState::Ready => {
+                vegetation.density.remove(&event.entity);
+            }
+            ChunkState::Evicting | ChunkState::Pooled => {
+                vegetation.clear(&mut commands, event.entity);
+                vegetation.density.remove(&event.entity);
+            }
+            _ => {}
+        }
+    }
+
+    let Ok(player) = player_q.get_single() else {
+        return;
+    };
+    let half = geo_origin.chunk_size * 0.5;
+    for chunk in chunk_manager.chunks().filter(|chunk| chunk.state == ChunkState::Ready) {
+        let centre = get_world_space_position(Vec3::new(chunk.coord.x as f32, 0., chunk.coord.y as f32), geo_origin.chunk_size);
+        let offset = (player.translation - centre).abs();
+        let distance = Vec2::new(offset.x - half, offset.z - half).max(Vec2::ZERO).length();
+        let Some((tile_x, tile_y)) = geo_origin.chunk_to_tile(chunk.coord.x, chunk.coord.y) else {
+            continue;
+        };
+        let scale = geo_origin.vertical_scale(tile_y);
+        let density = if chunk.lod > MAX_VEGETATION_LOD || chunk.failed_at.is_some() {
+            0.
+        } else {
+            settings.density(distance / scale)
+        };
+        if vegetation.density.get(&chunk.entity) == Some(&density) {
+            continue;
+        }
+
+        //the old plants stay up until the new ones are done
+        vegetation.cancel(&mut commands, chunk.entity);
+        if density <= 0. {
+            vegetation.clear(&mut commands, chunk.entity);
+            vegetation.density.insert(chunk.entity, density);
+            continue;
+        }
+        let Some(ground) = GroundGrid::from_chunk(&chunk_manager, &meshes, chunk.coord, geo_origin.chunk_size) else {
+            continue;
+        };
+        vegetation.density.insert(chunk.entity, density);
+
+        let area = ScatterArea {
+            seed: chunk_seed(geo_origin.zoom, tile_x, tile_y),
+            size: geo_origin.chunk_size,
+            scale,
+            height_scale: scale * terrain_settings.exaggeration,
+            density,
+        };
+        let land_use = chunk_manager.heights(chunk.coord).and_then(|heights| heights.land_use.clone());
+        let runways = chunk_runways(&airports.0, &geo_origin, chunk.coord, terrain_settings.exaggeration);
+        let entity = chunk.entity;
+        let task = AsyncComputeTaskPool::get()
+            .spawn(async move { (entity, scatter_vegetation(&area, &ground, land_use.as_ref(), &runways)) });
+        let task_entity = commands.spawn(GenVegetation(task)).id();
+        vegetation.tasks.insert(entity, task_entity);
+    }
+}
+
+fn handle_vegetation_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut GenVegetation)>,
+    mut vegetation: ResMut<ChunkVegetation>,
+) {
+    for (task_entity, mut task) in &mut tasks {
+        let Some((chunk, plants)) = bevy::tasks::block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        commands.entity(task_entity).despawn();
+        //a chunk that started another task since then only wants the newest
+        if vegetation.tasks.get(&chunk) != Some(&task_entity) {
+            continue;
+        }
+        vegetation.tasks.remove(&chunk);
+
+        //the old plants go in the same frame the new ones show up
+        vegetation.despawn_plants(&mut commands, chunk);
+        let mut entities = Vec::new();
+        for (instances, shape) in plants.into_iter().zip(vegetation.shapes.clone()) {
+            let Some(bounds) = plant_bounds(&instances) else {
+                continue;
+            };
+            let entity = commands
+                .spawn((
+                    Vegetation,
+                    MeshInstances(Arc::new(instances)),
+                    shape,
+                    bounds,
+                    SpatialBundle::default(),
+                    NotShadowCaster,
+                ))
+                .id();
+            commands.entity(chunk).add_child(entity);
+            entities.push(entity);
+        }
+        vegetation.plants.insert(chunk, entities);
+    }
+}
+
+//bounds around every plant, a plant reaches PLANT_RADIUS of its width out from its trunk. None without plants
+fn plant_bounds(instances: &[MeshInstance]) -> Option<Aabb> {
+    let reach = |instance: &MeshInstance| Vec3::new(instance.width * PLANT_RADIUS, 0., instance.width * PLANT_RADIUS);
+    let min = instances.iter().map(|instance| instance.position - reach(instance)).reduce(Vec3::min)?;
+    let max = instances
+        .iter()
+        .map(|instance| instance.position + reach(instance) + Vec3::Y * instance.height)
+        .reduce(Vec3::max)?;
+    Some(Aabb::from_min_max(min, max))
+}
+
+//what the scatter task needs to know about a chunk
+struct ScatterArea {
+    seed: u64,
+    size: f32,
+    //metres to world units across the ground, and for terrain heights
+    scale: f32,
+    height_scale: f32,
+    density: f32,
+}
+
+//instances of each Plant, in spot order
+type ChunkPlants = [Vec<MeshInstance>; 3];
+
+#[derive(Clone, Copy)]
+enum Plant {
+    Conifer,
+    Broadleaf,
+    Bush,
+}
+
+//place the chunk's plants, relative to the chunk's centre
+fn scatter_vegetation(area: &ScatterArea, ground: &GroundGrid, land_use: Option<&LandUseMap>, runways: &[ChunkRunway]) -> ChunkPlants {
+    let per_side = ((area.size / (SPOT_SPACING * area.scale)).ceil() as u32).clamp(1, MAX_SPOTS_PER_SIDE);
+    let cell = area.size / per_side as f32;
+    let mut plants = ChunkPlants::default();
+
+    for j in 0..per_side {
+        for i in 0..per_side {
+            let mut rng = SpotRng::new(area.seed, j * per_side + i);
+            //the first roll decides which density a spot shows up at, so it has to come first
+            if rng.next() >= area.density {
+                continue;
+            }
+            let local = Vec2::new((i as f32 + rng.next()) * cell, (j as f32 + rng.next()) * cell) - Vec2::splat(area.size * 0.5);
+
+            let height = ground.height(local);
+            let elevation = height / area.height_scale;
+            if height <= SEA_LEVEL + area.height_scale || runways.iter().any(|runway| runway.covers(local, RUNWAY_CLEARANCE)) {
+                continue;
+            }
+            //slope from the drawn ground, across a quarter of a spot
+            let d = cell * 0.25;
+            let dx = (ground.height(local + Vec2::X * d) - ground.height(local - Vec2::X * d)) / (2. * d);
+            let dz = (ground.height(local + Vec2::Y * d) - ground.height(local - Vec2::Y * d)) / (2. * d);
+            let slope = 1. - Vec3::new(-dx, 1., -dz).normalize().y;
+            let steepness = ((SLOPE_END - slope) / (SLOPE_END - SLOPE_START)).clamp(0., 1.);
+
+            let (trees, bushes) = match land_use.map(|map| map.at(local, area.size)) {
+                Some(LandUse::Forest) => (0.85, 0.1),
+                Some(LandUse::Park) => (0.3, 0.25),
+                Some(LandUse::Farmland) => (0.03, 0.05),
+                Some(LandUse::Urban) => (0.08, 0.08),
+                Some(LandUse::Industrial) => (0.01, 0.02),
+                Some(LandUse::Water) | Some(LandUse::Road) => continue,
+                //open country, or no vector tiles at all
+                Some(LandUse::Unknown) | None => (0.25, 0.15),
+            };
+            let trees = trees * steepness * (1. - smoothstep(TREE_LINE - 300., TREE_LINE, elevation));
+            let bushes = bushes * steepness * (1. - smoothstep(BUSH_LINE - 300., BUSH_LINE, elevation));
+
+            let roll = rng.next();
+            let plant = if roll < trees {
+                //more conifers higher up
+                let conifers = 0.35 + 0.55 * smoothstep(600., 1500., elevation);
+                if rng.next() < conifers { Plant::Conifer } else { Plant::Broadleaf }
+            } else if roll < trees + bushes {
+                Plant::Bush
+            } else {
+                continue;
+            };
+
+            //metres tall and how much wider or narrower than the shape
+            let (tall, spread) = match plant {
+                Plant::Conifer => (12. + 12. * rng.next(), 0.8 + 0.4 * rng.next()),
+                Plant::Broadleaf => (9. + 9. * rng.next(), 0.8 + 0.5 * rng.next()),
+                Plant::Bush => (1.5 + 1.5 * rng.next(), 0.8 + 0.6 * rng.next()),
+            };
+            plants[plant as usize].push(MeshInstance {
+                //sunk in a little so slopes don't show the base
+                position: Vec3::new(local.x, height - 0.5 * area.scale, local.y),
+                width: spread * tall * area.scale,
+                height: tall * area.scale,
+                heading: rng.next() * std::f32::consts::TAU,
+                shade: 0.85 + 0.3 * rng.next(),
+            });
+        }
+    }
+    plants
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+//seed for a chunk from its place on the tile grid, the same wherever the world origin is
+fn chunk_seed(zoom: u32, tile_x: u32, tile_y: u32) -> u64 {
+    ((zoom as u64) << 56) ^ ((tile_x as u64) << 28) ^ tile_y as u64
+}
+
+//splitmix64 stream for one spot of a chunk
+//https://prng.di.unimi.it/splitmix64.c
+struct SpotRng(u64);
+
+impl SpotRng {
+    fn new(seed: u64, spot: u32) -> Self {
+        SpotRng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (spot as u64).wrapping_mul(0xD1B5_4A32_D192_ED03))
+    }
+
+    //uniform in 0..1
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+//a plant one unit tall standing on the origin, flat shaded, drawn once for every plant of its kind
+#[derive(Default)]
+struct PlantShape {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    colors: Vec<[f32; 4]>,
+}
+
+const TRUNK_COLOR: [f32; 4] = [0.3, 0.22, 0.15, 1.];
+const CONIFER_COLOR: [f32; 4] = [0.11, 0.22, 0.12, 1.];
+const BROADLEAF_COLOR: [f32; 4] = [0.2, 0.34, 0.14, 1.];
+const BUSH_COLOR: [f32; 4] = [0.26, 0.34, 0.16, 1.];
+//widest ring of any shape, for bounds
+const PLANT_RADIUS: f32 = 0.6;
+
+//indexed by Plant
+static PLANT_SHAPES: Lazy<[PlantShape; 3]> = Lazy::new(|| {
+    let mut conifer = PlantShape::default();
+    conifer.cone(0., 0.3, 0.04, 0.04, 3, TRUNK_COLOR);
+    conifer.cone(0.15, 0.7, 0.28, 0., 6, CONIFER_COLOR);
+    conifer.cone(0.45, 1., 0.2, 0., 6, CONIFER_COLOR);
+
+    let mut broadleaf = PlantShape::default();
+    broadleaf.cone(0., 0.45, 0.05, 0.05, 3, TRUNK_COLOR);
+    broadleaf.cone(0.3, 0.65, 0., 0.35, 6, BROADLEAF_COLOR);
+    broadleaf.cone(0.65, 1., 0.35, 0., 6, BROADLEAF_COLOR);
+
+    let mut bush = PlantShape::default();
+    bush.cone(0., 0.4, 0.35, 0.6, 5, BUSH_COLOR);
+    bush.cone(0.4, 1., 0.6, 0., 5, BUSH_COLOR);
+
+    [conifer, broadleaf, bush]
+});
+
+impl PlantShape {
+    //sides of a cut cone from a ring at y0 to a ring at y1, either radius can be 0 for a point
+    fn cone(&mut self, y0: f32, y1: f32, r0: f32, r1: f32, sides: u32, color: [f32; 4]) {
+        let ring = |r: f32, y: f32, i: u32| {
+            let angle = i as f32 / sides as f32 * std::f32::consts::TAU;
+            Vec3::new(angle.cos() * r, y, angle.sin() * r)
+        };
+        for i in 0..sides {
+            let (a, b) = (ring(r0, y0, i), ring(r0, y0, i + 1));
+            let (c, d) = (ring(r1, y1, i + 1), ring(r1, y1, i));
+            for triangle in [[a, c, b], [a, d, c]] {
+                let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+                //a point at either end leaves one of the two triangles without area
+                if normal.length_squared() <= f32::EPSILON {
+                    continue;
+                }
+                self.positions.extend(triangle);
+                self.normals.extend([normal.normalize(); 3]);
+                self.colors.extend([color; 3]);
+            }
+        }
+    }
+
+    fn mesh(&self) -> Mesh {
+        let indices = (0..self.positions.len() as u32).collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors.clone());
+        mesh.insert_indices(Indices::U32(indices));
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::airports::db::RunwaySurface;
+
+    const SIZE: f32 = 2000.;
+    const GROUND: f32 = 150.;
+
+    fn area(seed: u64, density: f32) -> ScatterArea {
+        ScatterArea {
+            seed,
+            size: SIZE,
+            scale: 1.,
+            height_scale: 1.,
+            density,
+        }
+    }
+
+    fn seed() -> u64 {
+        chunk_seed(12, 645, 1480)
+    }
+
+    fn all(plants: &ChunkPlants) -> Vec<MeshInstance> {
+        plants.iter().flatten().copied().collect()
+    }
+
+    fn plants_of_every_kind(plants: &ChunkPlants) -> bool {
+        plants.iter().all(|kind| !kind.is_empty())
+    }
+
+    #[test]
+    fn the_same_chunk_grows_the_same_plants() {
+        let ground = GroundGrid::from_fn(33, SIZE, |p| GROUND + (p.x * 0.01).sin() * 20.);
+        let first = scatter_vegetation(&area(seed(), 1.), &ground, None, &[]);
+        let second = scatter_vegetation(&area(seed(), 1.), &ground, None, &[]);
+        assert!(plants_of_every_kind(&first));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn other_tiles_grow_other_plants() {
+        let ground = GroundGrid::flat(GROUND, 17, SIZE);
+        let plants = scatter_vegetation(&area(seed(), 1.), &ground, None, &[]);
+        for other in [
+            chunk_seed(12, 646, 1480),
+            chunk_seed(12, 645, 1481),
+            chunk_seed(13, 645, 1480),
+        ] {
+            assert_ne!(other, seed());
+            let other = scatter_vegetation(&area(other, 1.), &ground, None, &[]);
+            assert!(plants_of_every_kind(&other));
+            assert_ne!(all(&other), all(&plants));
+        }
+    }
+
+    #[test]
+    fn thinning_out_keeps_the_remaining_plants_in_place() {
+        let ground = GroundGrid::flat(GROUND, 17, SIZE);
+        let full = scatter_vegetation(&area(seed(), 1.), &ground, None, &[]);
+        let half = scatter_vegetation(&area(seed(), 0.5), &ground, None, &[]);
+        let quarter = scatter_vegetation(&area(seed(), 0.25), &ground, None, &[]);
+
+        for (dense, sparse) in [(&full, &half), (&half, &quarter)] {
+            let (dense_count, sparse_count) = (all(dense).len(), all(sparse).len());
+            assert!(sparse_count > 0 && sparse_count < dense_count);
+            //roughly in proportion to the density
+            assert!((sparse_count as f32 / dense_count as f32 - 0.5).abs() < 0.1);
+            //every plant left is one of the denser chunk's, of the same kind and exactly where it was
+            for (dense, sparse) in dense.iter().zip(sparse) {
+                assert!(sparse.iter().all(|plant| dense.contains(plant)));
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_grows_on_water() {
+        let sea = GroundGrid::flat(SEA_LEVEL, 17, SIZE);
+        assert_eq!(
+            all(&scatter_vegetation(&area(seed(), 1.), &sea, None, &[])),
+            []
+        );
+
+        let ground = GroundGrid::flat(GROUND, 17, SIZE);
+        let lake = LandUseMap::filled(LandUse::Water, 8);
+        assert_eq!(
+            all(&scatter_vegetation(
+                &area(seed(), 1.),
+                &ground,
+                Some(&lake),
+                &[]
+            )),
+            []
+        );
+        //forests are denser than open country
+        let forest = LandUseMap::filled(LandUse::Forest, 8);
+        let trees = scatter_vegetation(&area(seed(), 1.), &ground, Some(&forest), &[]);
+        let open = scatter_vegetation(&area(seed(), 1.), &ground, None, &[]);
+        assert!(all(&trees).len() > all(&open).len() * 2);
+    }
+
+    #[test]
+    fn nothing_grows_on_steep_ground() {
+        //a 60 degree slope between x 0 and 600 with level ground either side
+        let ground = GroundGrid::from_fn(65, SIZE, |p| GROUND + p.x.clamp(0., 600.) * 1.75);
+        let plants = all(&scatter_vegetation(&area(seed(), 1.), &ground, None, &[]));
+        //the slope is read a quarter of a spot either side, plants right at its foot or top may see it
+        let margin = SPOT_SPACING * 0.25;
+        assert!(plants
+            .iter()
+            .all(|plant| plant.position.x < margin || plant.position.x > 600. - margin));
+        assert!(plants.iter().filter(|plant| plant.position.x < 0.).count() > 1000);
+        assert!(
+            plants
+                .iter()
+                .filter(|plant| plant.position.x > 600.)
+                .count()
+                > 500
+        );
+        //and all of them stand on the ground, sunk in a little
+        assert!(plants
+            .iter()
+            .all(
+                |plant| (plant.position.y + 0.5 - ground.height(plant.position.xz())).abs() < 1e-3
+            ));
+    }
+
+    #[test]
+    fn nothing_grows_near_runways() {
+        let ground = GroundGrid::flat(GROUND, 17, SIZE);
+        let runway = ChunkRunway {
+            start: Vec2::new(-600., -100.),
+            end: Vec2::new(500., 300.),
+            start_height: GROUND,
+            end_height: GROUND,
+            width: 45.,
+            surface: RunwaySurface::Asphalt,
+            idents: ["07".to_string(), "25".to_string()],
+            scale: 1.,
+        };
+        let plants = all(&scatter_vegetation(
+            &area(seed(), 1.),
+            &ground,
+            None,
+            std::slice::from_ref(&runway),
+        ));
+        assert!(!plants.is_empty());
+        assert!(plants
+            .iter()
+            .all(|plant| !runway.covers(plant.position.xz(), RUNWAY_CLEARANCE)));
+        //the clearance is the only gap, just outside it plants grow as usual
+        let beside = plants
+            .iter()
+            .filter(|plant| runway.covers(plant.position.xz(), RUNWAY_CLEARANCE * 2.))
+            .count();
+        assert!(beside > 20);
+    }
+
+    #[test]
+    fn spot_rolls_are_repeatable_and_uniform() {
+        let rolls = |seed, spot| {
+            let mut rng = SpotRng::new(seed, spot);
+            (0..8).map(|_| rng.next()).collect::<Vec<_>>()
+        };
+        assert_eq!(rolls(seed(), 3), rolls(seed(), 3));
+        assert_ne!(rolls(seed(), 3), rolls(seed(), 4));
+        assert_ne!(rolls(seed(), 3), rolls(chunk_seed(12, 646, 1480), 3));
+
+        let first: Vec<f32> = (0..10000)
+            .map(|spot| SpotRng::new(seed(), spot).next())
+            .collect();
+        assert!(first.iter().all(|v| (0. ..1.).contains(v)));
+        let mean = first.iter().sum::<f32>() / first.len() as f32;
+        assert!((mean - 0.5).abs() < 0.02);
+        assert!(
+            (first.iter().filter(|v| **v < 0.25).count() as f32 / first.len() as f32 - 0.25).abs()
+                < 0.02
+        );
+    }
+
+    fn assert_vec3_eq(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn bounds_cover_every_plant() {
+        let plants = [
+            MeshInstance {
+                position: Vec3::new(-10., 5., 20.),
+                width: 4.,
+                height: 12.,
+                heading: 0.,
+                shade: 1.,
+            },
+            MeshInstance {
+                position: Vec3::new(30., -2., -40.),
+                width: 2.,
+                height: 3.,
+                heading: 1.,
+                shade: 1.,
+            },
+        ];
+        let bounds = plant_bounds(&plants).unwrap();
+        assert_vec3_eq(
+            bounds.min().into(),
+            Vec3::new(-10. - 4. * PLANT_RADIUS, -2., -40. - 2. * PLANT_RADIUS),
+        );
+        assert_vec3_eq(
+            bounds.max().into(),
+            Vec3::new(30. + 2. * PLANT_RADIUS, 17., 20. + 4. * PLANT_RADIUS),
+        );
+        assert!(plant_bounds(&[]).is_none());
+    }
+}
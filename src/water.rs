@@ -0,0 +1,172 @@
+//Water surfaces
+//Every chunk keeps a mask of where its terrain is at or below sea level. Chunks with any water get a
+//flat, reflective water plane at sea level as a child entity, so it moves and hides with the chunk.
+//Terrain above sea level covers the plane, so only the flooded parts show.
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::mesh::Indices;
+use bevy::render::primitives::Aabb;
+use bevy::utils::HashMap;
+
+use crate::geo::GeoOrigin;
+use crate::scene::{handle_terrain_data_threads, ChunkComponent};
+use crate::terrain::chunk_manager::{ChunkManager, ChunkState, ChunkStateChanged};
+use crate::terrain::heightfield::Heightfield;
+
+//world height of the water surface
+pub const SEA_LEVEL: f32 = 0.;
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WaterSurfaces>()
+            .add_systems(Startup, setup_water)
+            .add_systems(Update, update_water_surfaces.after(handle_terrain_data_threads));
+    }
+}
+
+//which parts of a chunk are under water, one entry per heightfield pixel
+pub struct WaterMask {
+    width: usize,
+    height: usize,
+    data: Vec<bool>,
+}
+
+impl WaterMask {
+    pub fn from_heightfield(heights: &Heightfield) -> Self {
+        Self {
+            width: heights.width,
+            height: heights.height,
+            data: heights.data.iter().map(|h| *h <= SEA_LEVEL).collect(),
+        }
+    }
+
+    //no water anywhere, for chunks that have no real heights
+    pub fn dry() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            data: vec![false],
+        }
+    }
+
+    pub fn has_water(&self) -> bool {
+        self.data.iter().any(|water| *water)
+    }
+
+    //u, v run 0..1 across the chunk from its north west corner
+    pub fn is_water(&self, u: f32, v: f32) -> bool {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.data[y * self.width + x]
+    }
+}
+
+#[derive(Component)]
+pub struct WaterSurface;
+
+//water plane entity of each chunk entity that has needed one so far
+#[derive(Resource, Default)]
+pub struct WaterSurfaces {
+    planes: HashMap<Entity, Entity>,
+    material: Handle<StandardMaterial>,
+    mesh: Handle<Mesh>,
+    //chunk size the mesh was built for
+    mesh_size: f32,
+}
+
+fn setup_water(mut surfaces: ResMut<WaterSurfaces>, mut materials: ResMut<Assets<StandardMaterial>>) {
+    surfaces.material = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.05, 0.2, 0.35, 0.85),
+        perceptual_roughness: 0.05,
+        metallic: 0.0,
+        reflectance: 0.9,
+        alpha_mode: AlphaMode::Blend,
+        fog_enabled: true,
+        ..default()
+    });
+}
+
+//a flat square centred on its origin, the same size as a chunk
+fn water_plane_mesh(size: f32) -> Mesh {
+    let half = size * 0.5;
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::all());
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vec![
+            Vec3::new(-half, 0., -half),
+            Vec3::new(half, 0., -half),
+            Vec3::new(half, 0., half),
+            Vec3::new(-half, 0., half),
+        ],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![Vec3::Y; 4]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]]);
+    mesh.insert_indices(Indices::U32(vec![0, 3, 2, 0, 2, 1]));
+    mesh
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_water_surfaces(
+    mut commands: Commands,
+    mut chunk_events: EventReader<ChunkStateChanged>,
+    mut removed_chunks: RemovedComponents<ChunkComponent>,
+    mut surfaces: ResMut<WaterSurfaces>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut planes: Query<(&mut Visibility, &mut Handle<Mesh>), With<WaterSurface>>,
+    chunk_manager: Res<ChunkManager>,
+    geo_origin: Res<GeoOrigin>,
+) {
+    //despawned chunks take their water with them
+    for chunk in removed_chunks.read() {
+        surfaces.planes.remove(&chunk);
+    }
+
+    //chunks change size with the zoom level
+    if surfaces.mesh_size != geo_origin.chunk_size {
+        surfaces.mesh = meshes.add(water_plane_mesh(geo_origin.chunk_size));
+        surfaces.mesh_size = geo_origin.chunk_size;
+    }
+
+    for event in chunk_events.read() {
+        let has_water = match event.state {
+            ChunkState::Ready => chunk_manager
+                .heights(event.coord)
+                .is_some_and(|heights| heights.water.has_water()),
+            ChunkState::Pooled => false,
+            _ => continue,
+        };
+
+        match surfaces.planes.get(&event.entity) {
+            Some(&plane) => {
+                if let Ok((mut visibility, mut mesh)) = planes.get_mut(plane) {
+                    *visibility = if has_water { Visibility::Inherited } else { Visibility::Hidden };
+                    if *mesh != surfaces.mesh {
+                        *mesh = surfaces.mesh.clone();
+                        //bounds are only worked out for entities without them
+                        commands.entity(plane).remove::<Aabb>();
+                    }
+                }
+            }
+            None if has_water => {
+                let plane = commands
+                    .spawn((
+                        WaterSurface,
+                        PbrBundle {
+                            mesh: surfaces.mesh.clone(),
+                            material: surfaces.material.clone(),
+                            transform: Transform::from_xyz(0., SEA_LEVEL, 0.),
+                            ..default()
+                        },
+                    ))
+                    .id();
+                commands.entity(event.entity).add_child(plane);
+                surfaces.planes.insert(event.entity, plane);
+            }
+            None => {}
+        }
+    }
+}